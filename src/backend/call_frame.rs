@@ -6,6 +6,8 @@ pub struct CallFrame {
     closure: Rc<Closure>,
     ip: usize,
     frame_start: usize,
+    print_result: bool,
+    host_call: bool,
 }
 
 impl CallFrame {
@@ -14,9 +16,29 @@ impl CallFrame {
             closure,
             ip: 0,
             frame_start,
+            print_result: false,
+            host_call: false,
         }
     }
 
+    /// Marks frame as a `toString` call made by `print`, returned value is printed instead of pushed
+    pub fn set_print_result(&mut self) {
+        self.print_result = true;
+    }
+
+    pub fn is_print_result(&self) -> bool {
+        self.print_result
+    }
+
+    /// Marks frame as called by the host, the machine stops when it returns
+    pub fn set_host_call(&mut self) {
+        self.host_call = true;
+    }
+
+    pub fn is_host_call(&self) -> bool {
+        self.host_call
+    }
+
     pub fn ip(&self) -> usize {
         self.ip
     }
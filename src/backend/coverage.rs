@@ -0,0 +1,46 @@
+use std::rc::Rc;
+
+use crate::Func;
+
+/// Instruction offsets executed in a single function
+pub struct FuncCoverage {
+    func: Rc<Func>,
+    executed: Vec<bool>,
+}
+
+impl FuncCoverage {
+    pub(crate) fn new(func: Rc<Func>) -> Self {
+        let executed = vec![false; func.chunk().byte_size()];
+        Self { func, executed }
+    }
+
+    pub(crate) fn mark(&mut self, offset: usize) {
+        if let Some(flag) = self.executed.get_mut(offset) {
+            *flag = true;
+        }
+    }
+
+    /// `None` for the top level script
+    pub fn name(&self) -> Option<&str> {
+        self.func.name.as_deref()
+    }
+
+    pub fn executed_offsets(&self) -> Vec<usize> {
+        self.executed
+            .iter()
+            .enumerate()
+            .filter(|(_, flag)| **flag)
+            .map(|(offset, _)| offset)
+            .collect()
+    }
+
+    /// Offsets of the function instructions that never were executed
+    pub fn unexecuted_offsets(&self) -> Vec<usize> {
+        self.func
+            .chunk()
+            .instruction_offsets()
+            .into_iter()
+            .filter(|offset| !self.executed[*offset])
+            .collect()
+    }
+}
@@ -1,27 +1,47 @@
 use std::{
-    collections::{HashMap, LinkedList},
+    collections::{HashMap, HashSet, LinkedList},
     ops::Deref,
     rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use crate::{
-    MachineError, MachineResult, Shared, StackTraceElement,
-    backend::{NativeFunctionsProvider, call_frame::CallFrame, service::BackendService},
+    MachineError, MachineResult, Shared, StackTraceElement, StepResult,
+    backend::{
+        FuncCoverage, HostContext, NativeFunctionsProvider, call_frame::CallFrame,
+        service::BackendService,
+    },
     data::*,
     shared,
-    utils::bytes_to_word,
+    utils::{MapHasher, bytes_to_word},
 };
 
 const FRAMES_MAX: usize = 64;
 const STACK_MAX_SIZE: usize = FRAMES_MAX * UINT8_COUNT;
 
+static NEXT_MACHINE_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Machine {
+    // tags global slots cached in chunks, closures may be shared between machines
+    id: usize,
     frames: Vec<CallFrame>,
     stack: Vec<Value>,
-    globals: HashMap<Rc<String>, Value>,
+    globals: Vec<Value>,
+    global_slots: HashMap<Rc<String>, usize, MapHasher>,
     service: Shared<dyn BackendService>,
     open_upvalues: LinkedList<Shared<Upvalue>>,
     init_method: Rc<String>,
+    to_string_method: Rc<String>,
+    bool_method: Rc<String>,
+    memory_limit: Option<usize>,
+    allocated_bytes: usize,
+    coverage: Option<HashMap<*const Func, FuncCoverage>>,
+    call_profile: Option<HashMap<String, usize>>,
+    warn_overrides: bool,
+    print_line_numbers: bool,
+    verbose_instances: bool,
+    strict_arithmetic: bool,
+    breakpoints: HashSet<usize>,
 }
 
 impl Machine {
@@ -35,91 +55,314 @@ impl Machine {
         native.get_functions().into_iter().for_each(|(name, func)| {
             vm.define_native(name, func);
         });
-        // prepare to start
+        native.get_classes().into_iter().for_each(|class| {
+            vm.define_foreign_class(class);
+        });
+        native
+            .get_host_functions()
+            .into_iter()
+            .for_each(|(name, func)| {
+                vm.define_host_function(name, func);
+            });
+        vm.load_script(func);
+        vm
+    }
+
+    fn load_script(&mut self, func: Func) {
         let func_ref = Rc::new(func);
         let closure = Closure::new(func_ref);
         let closure_ref = Rc::new(closure);
-        _ = vm.stack_push(Value::Closure(closure_ref.clone()));
-        vm.unchecked_call(closure_ref, 0);
-        vm
+        _ = self.stack_push(Value::Closure(closure_ref.clone()));
+        self.unchecked_call(closure_ref, 0);
     }
 
     fn new(service: Shared<dyn BackendService>) -> Self {
         Self {
+            id: NEXT_MACHINE_ID.fetch_add(1, Ordering::Relaxed),
             frames: Vec::with_capacity(FRAMES_MAX),
             stack: Vec::with_capacity(STACK_MAX_SIZE),
-            globals: HashMap::new(),
+            globals: Vec::new(),
+            global_slots: HashMap::default(),
             service,
             open_upvalues: Default::default(),
             init_method: Rc::new(INITIALIZER_METHOD_NAME.to_string()),
+            to_string_method: Rc::new(TO_STRING_METHOD_NAME.to_string()),
+            bool_method: Rc::new(BOOL_METHOD_NAME.to_string()),
+            memory_limit: None,
+            allocated_bytes: 0,
+            coverage: None,
+            call_profile: None,
+            warn_overrides: false,
+            print_line_numbers: false,
+            verbose_instances: false,
+            strict_arithmetic: false,
+            breakpoints: HashSet::new(),
         }
     }
 
+    /// Limits total amount of bytes the script may allocate for strings
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Enables recording of executed instruction offsets, drops collected data when disabled
+    pub fn set_coverage_enabled(&mut self, enabled: bool) {
+        self.coverage = enabled.then(HashMap::new);
+    }
+
+    /// Enables counting of calls per function name, drops collected data when disabled
+    pub fn set_call_profiling_enabled(&mut self, enabled: bool) {
+        self.call_profile = enabled.then(HashMap::new);
+    }
+
+    /// Makes `+` fail unless both operands are numbers or both are strings,
+    /// by default a string operand converts the other one to a string
+    pub fn set_strict_arithmetic(&mut self, enabled: bool) {
+        self.strict_arithmetic = enabled;
+    }
+
+    /// Prefixes output of every `print` with `[line N]` of the statement, helps with debugging
+    pub fn set_print_line_numbers(&mut self, enabled: bool) {
+        self.print_line_numbers = enabled;
+    }
+
+    /// Makes `print` show fields of instances without `toString`, e.g. `Brioche{jam: "grape"}`
+    pub fn set_verbose_instances(&mut self, enabled: bool) {
+        self.verbose_instances = enabled;
+    }
+
+    /// Reports methods that replace a superclass method through `BackendService::set_diagnostic`
+    pub fn set_override_warnings(&mut self, enabled: bool) {
+        self.warn_overrides = enabled;
+    }
+
+    /// Makes `run_until_breakpoint` pause when execution reaches the line
+    pub fn set_breakpoint(&mut self, line: usize) {
+        self.breakpoints.insert(line);
+    }
+
+    pub fn remove_breakpoint(&mut self, line: usize) {
+        self.breakpoints.remove(&line);
+    }
+
+    /// Coverage of the functions called at least once, order isn't specified.
+    /// Empty if coverage isn't enabled
+    pub fn coverage(&self) -> Vec<&FuncCoverage> {
+        self.coverage
+            .as_ref()
+            .map(|coverage| coverage.values().collect())
+            .unwrap_or_default()
+    }
+
+    /// Call counts by function name, the most called functions go first.
+    /// Empty if call profiling isn't enabled
+    pub fn call_profile(&self) -> Vec<(String, usize)> {
+        let mut profile = self
+            .call_profile
+            .as_ref()
+            .map(|profile| {
+                profile
+                    .iter()
+                    .map(|(name, count)| (name.clone(), *count))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        profile.sort_by(|(a_name, a_count), (b_name, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+        });
+        profile
+    }
+
+    /// Uses a fixed-seed hasher for globals, so their enumeration order is the same on every run
+    pub fn set_deterministic_hashing(&mut self, enabled: bool) {
+        let hasher = if enabled {
+            MapHasher::Fixed
+        } else {
+            MapHasher::default()
+        };
+        let mut slots = HashMap::with_capacity_and_hasher(self.global_slots.len(), hasher);
+        slots.extend(self.global_slots.drain());
+        self.global_slots = slots;
+    }
+
+    /// Names of defined globals including natives, the order is stable
+    /// across runs only in deterministic hashing mode
+    pub fn global_names(&self) -> Vec<Rc<String>> {
+        self.global_slots.keys().cloned().collect()
+    }
+
+    /// Value of the global variable, `None` if it isn't defined
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.get_global(&Rc::new(name.to_string()))
+    }
+
     pub fn run(&mut self) -> MachineResult<()> {
         let result = self.perform();
         if let Err(err) = &result {
-            self.service.borrow_mut().set_error(err.clone());
-            self.flush_track_trace();
-            self.stack.clear();
-            self.frames.clear();
+            self.reset_on_error(err);
         }
+        self.service.borrow_mut().flush();
         result
     }
 
-    fn perform(&mut self) -> MachineResult<()> {
-        let mut is_alive = true;
-        while is_alive {
-            let fetch_result = self.fetch_instruction();
-            let instr = match fetch_result {
-                Ok(instr) => instr,
-                Err(FetchError::End) => break,
-                Err(err) => return Err(self.runtime_error(format!("{err}"))),
+    /// Calls a global function defined by previously run scripts and returns its result
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> MachineResult<Value> {
+        let result = self.perform_call(name, args);
+        if let Err(err) = &result {
+            self.reset_on_error(err);
+        }
+        self.service.borrow_mut().flush();
+        result
+    }
+
+    fn perform_call(&mut self, name: &str, args: &[Value]) -> MachineResult<Value> {
+        let Some(callee) = self.global(name) else {
+            return Err(self.runtime_error(format!("Undefined function '{name}'")));
+        };
+        let depth = self.frames.len();
+        self.stack_push(callee.clone())?;
+        for arg in args {
+            self.stack_push(arg.clone())?;
+        }
+        self.call_value(callee, args.len())?;
+        // natives and classes without initializer complete immediately
+        if self.frames.len() > depth {
+            self.frame_mut()?.set_host_call();
+            self.perform()?;
+        }
+        self.stack_pop()
+    }
+
+    fn reset_on_error(&mut self, err: &MachineError) {
+        let stack_trace = self.stack_trace();
+        self.service
+            .borrow_mut()
+            .on_uncaught_error(err, &stack_trace);
+        self.flush_locals();
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    /// Runs another compiled script on the same machine.
+    /// Globals defined by previous runs are kept even if they completed with an error
+    pub fn run_chunk(&mut self, func: Func) -> MachineResult<()> {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        self.load_script(func);
+        self.run()
+    }
+
+    /// Executes exactly one instruction, so hosts like debuggers can inspect the state in between
+    pub fn step(&mut self) -> MachineResult<StepResult> {
+        if self.frames.is_empty() {
+            return Ok(StepResult::Finished);
+        }
+        let result = self.execute_instruction();
+        if let Err(err) = &result {
+            self.reset_on_error(err);
+        }
+        self.service.borrow_mut().flush();
+        if !result? {
+            return Ok(StepResult::Finished);
+        }
+        let frame = self.frame()?;
+        Ok(StepResult::Running {
+            ip: frame.ip(),
+            line: frame.line_number(),
+        })
+    }
+
+    /// Steps until the next instruction starts a breakpoint line in any function.
+    /// Instructions of the line where the machine is paused don't trigger it again
+    pub fn run_until_breakpoint(&mut self) -> MachineResult<StepResult> {
+        let mut location = (
+            self.frames.len(),
+            self.frames.last().and_then(|frame| frame.line_number()),
+        );
+        loop {
+            let result = self.step()?;
+            let StepResult::Running { line, .. } = result else {
+                return Ok(result);
             };
-            match instr {
-                Instruction::Constant(index) => self.op_constant(index)?,
-                Instruction::Equal => self.op_binary(Value::equals)?,
-                Instruction::Greater => self.op_binary(Value::greater)?,
-                Instruction::Less => self.op_binary(Value::less)?,
-                Instruction::Nil => self.stack_push(Value::Nil)?,
-                Instruction::True => self.stack_push(Value::Bool(true))?,
-                Instruction::False => self.stack_push(Value::Bool(false))?,
-                Instruction::Add => self.op_binary(Value::add)?,
-                Instruction::Subtract => self.op_binary(Value::subtract)?,
-                Instruction::Multiply => self.op_binary(Value::multiply)?,
-                Instruction::Divide => self.op_binary(Value::divide)?,
-                Instruction::Negate => self.op_negate()?,
-                Instruction::Not => self.op_not()?,
-                Instruction::Print => self.op_print()?,
-                Instruction::Return => self.op_return(&mut is_alive)?,
-                Instruction::Pop => self.op_pop()?,
-                Instruction::DefineGlobal(index) => self.define_global(index)?,
-                Instruction::GetGlobal(index) => self.get_global(index)?,
-                Instruction::SetGlobal(index) => self.set_global(index)?,
-                Instruction::GetLocal(rel_slot) => self.op_get_local(rel_slot)?,
-                Instruction::SetLocal(rel_slot) => self.op_set_local(rel_slot)?,
-                Instruction::JumpIfFalse(first, second) => self.op_jump_if_false(first, second)?,
-                Instruction::Jump(first, second) => self.op_jump(first, second)?,
-                Instruction::Loop(first, second) => self.op_loop(first, second)?,
-                Instruction::Duplicate => self.op_duplicate_top()?,
-                Instruction::Call(arg_count) => self.op_call(arg_count)?,
-                Instruction::Closure(index) => self.op_closure(index)?,
-                Instruction::GetUpvalue(index) => self.op_get_upvalue(index)?,
-                Instruction::SetUpvalue(index) => self.op_set_upvalue(index)?,
-                Instruction::CloseUpvalue => self.op_close_upvalue()?,
-                Instruction::Class(index) => self.op_class(index)?,
-                Instruction::GetProperty(index) => self.get_class_property(index)?,
-                Instruction::SetProperty(index) => self.set_class_property(index)?,
-                Instruction::Method(index) => self.op_method(index)?,
-                Instruction::Invoke(name, arg_count) => self.op_invoke(name, arg_count)?,
-                Instruction::Inherit => self.op_inherit()?,
-                Instruction::GetSuper(index) => self.op_get_super(index)?,
-                Instruction::SuperInvoke(name, arg_count) => {
-                    self.op_super_invoke(name, arg_count)?
-                }
+            let next = (self.frames.len(), line);
+            if next != location && line.is_some_and(|line| self.breakpoints.contains(&line)) {
+                return Ok(result);
             }
+            location = next;
         }
+    }
+
+    fn perform(&mut self) -> MachineResult<()> {
+        while self.execute_instruction()? {}
         Ok(())
     }
+
+    /// Returns `false` when the run is complete
+    fn execute_instruction(&mut self) -> MachineResult<bool> {
+        let mut is_alive = true;
+        let instr = match self.fetch_instruction() {
+            Ok(instr) => instr,
+            Err(FetchError::End) => return Ok(false),
+            Err(err) => return Err(self.runtime_error(format!("{err}"))),
+        };
+        match instr {
+            Instruction::Constant(index) => self.op_constant(index)?,
+            Instruction::ConstantSmall(val) => self.stack_push(Value::number(val as Double))?,
+            Instruction::SharedConstant(first, second) => {
+                self.op_shared_constant(bytes_to_word(first, second))?
+            }
+            Instruction::Equal => self.op_binary(Value::equals, "==")?,
+            Instruction::Greater => self.op_binary(Value::greater, ">")?,
+            Instruction::Less => self.op_binary(Value::less, "<")?,
+            Instruction::NotEqual => self.op_binary(Value::not_equals, "!=")?,
+            Instruction::GreaterEqual => self.op_binary(Value::greater_equal, ">=")?,
+            Instruction::LessEqual => self.op_binary(Value::less_equal, "<=")?,
+            Instruction::Nil => self.stack_push(Value::Nil)?,
+            Instruction::True => self.stack_push(Value::Bool(true))?,
+            Instruction::False => self.stack_push(Value::Bool(false))?,
+            Instruction::Add => self.op_add()?,
+            Instruction::Subtract => self.op_binary(Value::subtract, "-")?,
+            Instruction::Multiply => self.op_multiply()?,
+            Instruction::Divide => self.op_binary(Value::divide, "/")?,
+            Instruction::FloorDivide => self.op_binary(Value::floor_divide, "~/")?,
+            Instruction::Negate => self.op_negate()?,
+            Instruction::Not => self.op_not()?,
+            Instruction::Print => self.op_print()?,
+            Instruction::AssertEqual => self.op_assert_equal()?,
+            Instruction::Return => self.op_return(&mut is_alive)?,
+            Instruction::Pop => self.op_pop()?,
+            Instruction::PopN(count) => self.op_pop_n(count)?,
+            Instruction::DefineGlobal(index) => self.define_global(index)?,
+            Instruction::GetGlobal(index) => self.get_global(index)?,
+            Instruction::SetGlobal(index) => self.set_global(index)?,
+            Instruction::GetLocal(rel_slot) => self.op_get_local(rel_slot)?,
+            Instruction::SetLocal(rel_slot) => self.op_set_local(rel_slot)?,
+            Instruction::JumpIfFalse(first, second) => self.op_jump_if_false(first, second)?,
+            Instruction::Jump(first, second) => self.op_jump(first, second)?,
+            Instruction::Loop(first, second) => self.op_loop(first, second)?,
+            Instruction::Duplicate => self.op_duplicate_top()?,
+            Instruction::Call(arg_count) => self.op_call(arg_count)?,
+            Instruction::Closure(index) => self.op_closure(index)?,
+            Instruction::GetUpvalue(index) => self.op_get_upvalue(index)?,
+            Instruction::SetUpvalue(index) => self.op_set_upvalue(index)?,
+            Instruction::CloseUpvalue => self.op_close_upvalue()?,
+            Instruction::Class(index) => self.op_class(index)?,
+            Instruction::GetProperty(index) => self.get_class_property(index)?,
+            Instruction::SetProperty(index) => self.set_class_property(index)?,
+            Instruction::Method(index) => self.op_method(index)?,
+            Instruction::Invoke(name, arg_count) => self.op_invoke(name, arg_count)?,
+            Instruction::Inherit => self.op_inherit()?,
+            Instruction::GetSuper(index) => self.op_get_super(index)?,
+            Instruction::SuperInvoke(name, arg_count) => self.op_super_invoke(name, arg_count)?,
+            Instruction::BuildList(count) => self.op_build_list(count)?,
+            Instruction::GetIndex => self.op_get_index()?,
+            Instruction::SetIndex => self.op_set_index()?,
+            Instruction::Unpack(count) => self.op_unpack(count)?,
+        }
+        Ok(is_alive)
+    }
 }
 
 /// Jumps
@@ -131,7 +374,7 @@ impl Machine {
             .pop()
             .ok_or(MachineError::with_str("Bug: return on empty call frame"))?;
 
-        if self.frames.is_empty() {
+        if self.frames.is_empty() && !frame.is_host_call() {
             self.stack_pop()?;
             *is_alive = false;
             return Ok(());
@@ -139,11 +382,21 @@ impl Machine {
 
         self.close_upvalues(frame.frame_start())?;
         self.stack.truncate(frame.frame_start());
+        if frame.is_host_call() {
+            *is_alive = false;
+        }
+        if frame.is_print_result() {
+            self.print(result);
+            return Ok(());
+        }
         self.stack_push(result)
     }
 
     fn op_loop(&mut self, first: u8, second: u8) -> MachineResult<()> {
         let jump = bytes_to_word(first, second);
+        if jump > self.frame()?.ip() {
+            return Err(self.runtime_error("Loop jumps before the chunk start"));
+        }
         self.frame_mut()?.ip_dec(jump);
         Ok(())
     }
@@ -156,8 +409,8 @@ impl Machine {
 
     fn op_jump_if_false(&mut self, first: u8, second: u8) -> MachineResult<()> {
         let jump = bytes_to_word(first, second);
-        let condition = self.stack_peek()?.as_bool();
-        if !condition {
+        let value = self.stack_peek()?;
+        if !self.is_truthy(value)? {
             self.frame_mut()?.ip_inc(jump);
         }
         Ok(())
@@ -168,29 +421,113 @@ impl Machine {
 impl Machine {
     fn op_not(&mut self) -> MachineResult<()> {
         let value = self.stack_pop()?;
-        self.stack_push(Value::Bool(!value.as_bool()))
+        let truthy = self.is_truthy(value)?;
+        self.stack_push(Value::Bool(!truthy))
+    }
+
+    /// Same as `Value::as_bool` but instances may define it with the `__bool` method.
+    /// The method is run to completion before the current instruction continues
+    fn is_truthy(&mut self, value: Value) -> MachineResult<bool> {
+        let Some(method) = value
+            .as_instance()
+            .and_then(|instance| instance.class().get_method(&self.bool_method))
+        else {
+            return Ok(value.as_bool());
+        };
+        let depth = self.frames.len();
+        self.stack_push(value)?;
+        self.call_value(method, 0)?;
+        if self.frames.len() > depth {
+            self.frame_mut()?.set_host_call();
+            self.perform()?;
+        }
+        match self.stack_pop()? {
+            Value::Bool(flag) => Ok(flag),
+            _ => Err(self.runtime_error(format!("'{BOOL_METHOD_NAME}' must return a boolean"))),
+        }
     }
 
     fn op_negate(&mut self) -> MachineResult<()> {
-        let value = self.stack_pop()?;
-        let Some(value) = value.as_number() else {
-            return Err(self.runtime_error("Operand must be a number"));
+        match self.stack.last_mut() {
+            Some(Value::Number(value)) => {
+                *value = -*value;
+                Ok(())
+            }
+            Some(_) => Err(self.runtime_error("Operand must be a number")),
+            None => Err(self.runtime_error("Pop on empty stack")),
+        }
+    }
+
+    fn op_add(&mut self) -> MachineResult<()> {
+        let operation = if self.strict_arithmetic {
+            Value::add_strict
+        } else {
+            Value::add
         };
-        self.stack_push(Value::number(-value))
+        self.op_binary(operation, "+")?;
+        if let Value::Text(text) = self.stack_peek()? {
+            self.track_allocation(text.len())?;
+        }
+        Ok(())
     }
 
-    fn op_binary(&mut self, operation: ValueOperation) -> MachineResult<()> {
+    fn op_assert_equal(&mut self) -> MachineResult<()> {
+        let actual = self.stack_pop()?;
+        let expected = self.stack_pop()?;
+        if expected != actual {
+            let message = format!("assertion failed: {expected} != {actual}");
+            return Err(self.runtime_error(message));
+        }
+        Ok(())
+    }
+
+    fn op_multiply(&mut self) -> MachineResult<()> {
+        // the limit is checked before the repeated text is allocated
+        let len = self.stack.len();
+        if len >= 2
+            && let Some(bytes) =
+                Value::repeated_text_length(&self.stack[len - 2], &self.stack[len - 1])
+        {
+            self.track_allocation(bytes)?;
+        }
+        self.op_binary(Value::multiply, "*")
+    }
+
+    fn track_allocation(&mut self, bytes: usize) -> MachineResult<()> {
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        self.allocated_bytes = self.allocated_bytes.saturating_add(bytes);
+        if self.allocated_bytes > limit {
+            return Err(self.runtime_error("Memory limit exceeded"));
+        }
+        Ok(())
+    }
+
+    /// Replaces left operand on top of the stack with the operation result
+    /// `symbol` of the operator is used in error messages only
+    fn op_binary(&mut self, operation: ValueOperation, symbol: &str) -> MachineResult<()> {
         let b = self.stack_pop()?;
-        let a = self.stack_pop()?;
-        match operation(&a, &b) {
+        let Some(a) = self.stack.last_mut() else {
+            return Err(self.runtime_error("Pop on empty stack"));
+        };
+        let result = operation(a, &b).map_err(|err| (err, a.type_name()));
+        match result {
             Ok(value) => {
-                self.stack.push(value);
+                *a = value;
                 Ok(())
             }
-            Err(OperationError::TypeMismatch) => {
-                Err(self.runtime_error("Invalid/incompatible operands type"))
+            Err((OperationError::TypeMismatch, a_type)) => {
+                let message = format!(
+                    "Operator '{symbol}' cannot be applied to {a_type} and {}",
+                    b.type_name()
+                );
+                Err(self.runtime_error(message))
             }
-            Err(OperationError::DivisionByZero) => Err(self.runtime_error("Division by zeros")),
+            Err((OperationError::DivisionByZero, _)) => {
+                Err(self.runtime_error("Division by zeros"))
+            }
+            Err((OperationError::TextTooLong, _)) => Err(self.runtime_error("Text is too long")),
         }
     }
 }
@@ -206,13 +543,21 @@ impl Machine {
     fn invoke(&mut self, method: Rc<String>, arg_count: u8) -> MachineResult<()> {
         let arg_count = arg_count as usize;
         let receiver = self.stack_peek_at(arg_count)?;
+        if let Value::ForeignInstance(instance) = receiver {
+            return self.invoke_foreign(instance, method, arg_count);
+        }
+        if let Value::Text(text) = receiver {
+            return self.invoke_text(text, method, arg_count);
+        }
+        if let Value::List(list) = receiver {
+            return self.invoke_list(list, method, arg_count);
+        }
         let instance = receiver
             .as_instance()
             .ok_or(MachineError::with_str("Only instances have methods"))?;
 
         if let Some(value) = instance.get_field(&method) {
-            let len = self.stack.len();
-            self.stack[len - arg_count - 1] = value.clone();
+            self.stack_set_at(arg_count, value.clone())?;
             return self.call_value(value, arg_count);
         }
 
@@ -232,6 +577,55 @@ impl Machine {
         self.call_value(method, arg_count)
     }
 
+    fn invoke_foreign(
+        &mut self,
+        instance: Rc<ForeignInstance>,
+        name: Rc<String>,
+        arg_count: usize,
+    ) -> MachineResult<()> {
+        let Some(method) = instance.class().get_method(&name) else {
+            let message = format!("Undefined property '{name}'");
+            return Err(self.runtime_error(message));
+        };
+        let len = self.stack.len();
+        let Some(result) = instance.call(&method, &self.stack[len - arg_count..]) else {
+            return Err(self.runtime_error("Host object is already in use"));
+        };
+        self.replace_call_frame_values(arg_count, result)
+    }
+
+    fn invoke_list(
+        &mut self,
+        list: Rc<List>,
+        name: Rc<String>,
+        arg_count: usize,
+    ) -> MachineResult<()> {
+        let Some(method) = list_method(&name) else {
+            let message = format!("Undefined property '{name}'");
+            return Err(self.runtime_error(message));
+        };
+        let len = self.stack.len();
+        let result = method(&list, &self.stack[len - arg_count..])
+            .map_err(|message| self.runtime_error(message))?;
+        self.replace_call_frame_values(arg_count, result)
+    }
+
+    fn invoke_text(
+        &mut self,
+        text: Rc<String>,
+        name: Rc<String>,
+        arg_count: usize,
+    ) -> MachineResult<()> {
+        let Some(method) = text_method(&name) else {
+            let message = format!("Undefined property '{name}'");
+            return Err(self.runtime_error(message));
+        };
+        let len = self.stack.len();
+        let result = method(&text, &self.stack[len - arg_count..])
+            .map_err(|message| self.runtime_error(message))?;
+        self.replace_call_frame_values(arg_count, result)
+    }
+
     fn op_call(&mut self, arg_count: u8) -> MachineResult<()> {
         let arg_count = arg_count as usize;
         let value = self.stack_peek_at(arg_count)?;
@@ -244,6 +638,7 @@ impl Machine {
             Value::NativeFun(callee) => self.call_native(callee, arg_count),
             Value::Class(callee) => self.call_class(callee, arg_count),
             Value::BoundMethod(callee) => self.call_bound_method(callee, arg_count),
+            Value::ForeignClass(callee) => self.call_foreign_class(callee, arg_count),
             _ => Err(self.runtime_error("Can only call functions and classes")),
         }
     }
@@ -254,15 +649,13 @@ impl Machine {
         arg_count: usize,
     ) -> MachineResult<()> {
         let closure = callee.closure();
-        let len = self.stack.len();
-        self.stack[len - arg_count - 1] = callee.receiver_owned();
+        self.stack_set_at(arg_count, callee.receiver_owned())?;
         self.call_closure(closure, arg_count)
     }
 
     fn call_class(&mut self, callee: Rc<Class>, arg_count: usize) -> MachineResult<()> {
-        let len = self.stack.len();
         let instance = Rc::new(Instance::new(callee));
-        self.stack[len - arg_count - 1] = Value::Instance(instance.clone());
+        self.stack_set_at(arg_count, Value::Instance(instance.clone()))?;
 
         let class = instance.class();
         if let Some(initializer) = class.get_method(&self.init_method) {
@@ -277,7 +670,7 @@ impl Machine {
     fn call_closure(&mut self, callee: Rc<Closure>, arg_count: usize) -> MachineResult<()> {
         let arity = callee.func().arity;
         if arg_count != arity {
-            let message = format!("Expected {} arguments but got {}", arity, arg_count);
+            let message = arity_error_message(callee.func(), arg_count);
             return Err(self.runtime_error(message));
         }
         if self.frames.len() == FRAMES_MAX {
@@ -287,54 +680,144 @@ impl Machine {
         Ok(())
     }
 
+    fn call_foreign_class(
+        &mut self,
+        callee: Rc<ForeignClass>,
+        arg_count: usize,
+    ) -> MachineResult<()> {
+        let len = self.stack.len();
+        let state = callee.construct(&self.stack[len - arg_count..]);
+        let instance = ForeignInstance::new(callee, state);
+        self.replace_call_frame_values(arg_count, Value::ForeignInstance(Rc::new(instance)))
+    }
+
     fn call_native(&mut self, callee: Rc<NativeFunc>, arg_count: usize) -> MachineResult<()> {
         let len = self.stack.len();
-        let args = &self.stack[len - arg_count..];
-        let result = callee.call(args);
-        self.stack.truncate(len - arg_count);
+        // arguments are moved out, so the function may access the machine
+        let args = self.stack.split_off(len - arg_count);
+        match callee.call(self, &args) {
+            NativeAction::Return(result) => self.replace_call_frame_values(0, result),
+            NativeAction::Call(callee, args) => {
+                let arg_count = args.len();
+                self.replace_call_frame_values(0, callee.clone())?;
+                for arg in args {
+                    self.stack_push(arg)?;
+                }
+                self.call_value(callee, arg_count)
+            }
+        }
+    }
+
+    /// Removes callee and its arguments from the stack and pushes call result instead
+    fn replace_call_frame_values(&mut self, arg_count: usize, result: Value) -> MachineResult<()> {
+        let len = self.stack.len();
+        self.stack.truncate(len - arg_count - 1);
         self.stack_push(result)
     }
 
     fn define_native<T: AsRef<str>>(&mut self, name: T, func: NativeFn) {
         let value = Value::native_func(func);
-        self.globals
-            .insert(Rc::new(name.as_ref().to_string()), value);
+        self.insert_global(Rc::new(name.as_ref().to_string()), value);
+    }
+
+    fn define_host_function<T: AsRef<str>>(&mut self, name: T, func: HostFn) {
+        let value = Value::host_func(func);
+        self.insert_global(Rc::new(name.as_ref().to_string()), value);
+    }
+
+    fn define_foreign_class(&mut self, class: ForeignClass) {
+        let name = Rc::new(class.name().to_string());
+        let value = Value::ForeignClass(Rc::new(class));
+        self.insert_global(name, value);
     }
 
     fn unchecked_call(&mut self, closure: Rc<Closure>, arg_count: usize) {
+        if let Some(profile) = self.call_profile.as_mut() {
+            let name = closure.func().name.as_deref().unwrap_or("script");
+            *profile.entry(name.to_string()).or_default() += 1;
+        }
         let frame_start = self.stack.len() - arg_count - 1;
         let frame = CallFrame::new(closure, frame_start);
         self.frames.push(frame);
     }
 }
 
+impl HostContext for Machine {
+    fn get_global(&self, name: &Rc<String>) -> Option<Value> {
+        let slot = self.global_slots.get(name)?;
+        self.globals.get(*slot).cloned()
+    }
+
+    fn set_global(&mut self, name: Rc<String>, value: Value) {
+        self.insert_global(name, value);
+    }
+
+    fn flush_output(&mut self) {
+        self.service.borrow_mut().flush();
+    }
+}
+
 /// Variables
 impl Machine {
     fn define_global(&mut self, index: u8) -> MachineResult<()> {
         let name = self.read_const_string(index)?;
         let value = self.stack_pop()?;
-        self.globals.insert(name, value);
+        let slot = self.insert_global(name, value);
+        self.frame()?
+            .chunk()
+            .cache_global_slot(index, self.id, slot);
         Ok(())
     }
 
     fn get_global(&mut self, index: u8) -> MachineResult<()> {
-        let name = self.read_const_string(index)?;
-        let Some(value) = self.globals.get(&name).cloned() else {
-            let message = format!("Undefined variable {}", name);
-            return Err(self.runtime_error(message));
-        };
+        let slot = self.resolve_global_slot(index)?;
+        let value = self
+            .globals
+            .get(slot)
+            .cloned()
+            .ok_or_else(|| self.invalid_global_slot(slot))?;
         self.stack_push(value)
     }
 
     fn set_global(&mut self, index: u8) -> MachineResult<()> {
+        let slot = self.resolve_global_slot(index)?;
+        let value = self.stack_peek()?;
+        let Some(global) = self.globals.get_mut(slot) else {
+            return Err(self.invalid_global_slot(slot));
+        };
+        *global = value;
+        Ok(())
+    }
+
+    fn invalid_global_slot(&self, slot: usize) -> MachineError {
+        self.runtime_error(format!("Bug: invalid global slot {slot}"))
+    }
+
+    /// Finds slot of the global variable which name is stored in the constant at `index`.
+    /// Once resolved, the slot is cached in the chunk so hash lookup is performed only once per machine
+    fn resolve_global_slot(&self, index: u8) -> MachineResult<usize> {
+        let chunk = self.frame()?.chunk();
+        if let Some(slot) = chunk.cached_global_slot(index, self.id) {
+            return Ok(slot);
+        }
         let name = self.read_const_string(index)?;
-        if !self.globals.contains_key(&name) {
+        let Some(slot) = self.global_slots.get(&name).cloned() else {
             let message = format!("Undefined variable {}", name);
             return Err(self.runtime_error(message));
+        };
+        chunk.cache_global_slot(index, self.id, slot);
+        Ok(slot)
+    }
+
+    fn insert_global(&mut self, name: Rc<String>, value: Value) -> usize {
+        if let Some(slot) = self.global_slots.get(&name).cloned() {
+            self.globals[slot] = value;
+            return slot;
         }
-        let value = self.stack_peek()?;
-        self.globals.insert(name, value);
-        Ok(())
+        let slot = self.globals.len();
+        self.globals.push(value);
+        self.global_slots.insert(name, slot);
+        slot
     }
 
     fn op_get_local(&mut self, rel_slot: u8) -> MachineResult<()> {
@@ -342,6 +825,13 @@ impl Machine {
         let Some(value) = self.stack.get(slot).cloned() else {
             return Err(self.runtime_error("Bug: failed to get local value"));
         };
+        // hosts may call a method closure directly, so `this` isn't bound
+        if rel_slot == 0
+            && value.as_instance().is_none()
+            && self.frame()?.closure().func().is_method
+        {
+            return Err(self.runtime_error("Cannot access 'this' without a receiver"));
+        }
         self.stack_push(value)?;
         Ok(())
     }
@@ -349,8 +839,7 @@ impl Machine {
     fn op_set_local(&mut self, rel_slot: u8) -> MachineResult<()> {
         let value = self.stack_peek()?;
         let slot = self.relative_to_absolute_slot(rel_slot)?;
-        self.stack[slot] = value;
-        Ok(())
+        self.stack_set(slot, value)
     }
 
     fn relative_to_absolute_slot(&self, relative_slot: u8) -> MachineResult<usize> {
@@ -388,6 +877,11 @@ impl Machine {
         let class = self.stack_peek()?.as_class().ok_or(MachineError::with_str(
             "Bug: method on non-class object (inherit)",
         ))?;
+        // methods are copied down on inheritance, so lookup can't loop over a class chain;
+        // the only possible cycle is a class inheriting itself in a hand-built chunk
+        if Rc::ptr_eq(&class, &super_class) {
+            return Err(self.runtime_error("A class can't inherit from itself"));
+        }
         class.inherit_methods(&super_class);
         self.stack_pop()?;
         Ok(())
@@ -410,14 +904,50 @@ impl Machine {
             .stack_peek_at(1)?
             .as_class()
             .ok_or(MachineError::with_str("Bug: method on non-class object"))?;
+        if self.warn_overrides {
+            self.check_override(&class, &name);
+        }
         class.add_method(name, method);
         self.stack_pop()?;
         Ok(())
     }
 
+    fn check_override(&mut self, class: &Class, name: &Rc<String>) {
+        // initializers are expected to be redefined
+        if *name == self.init_method {
+            return;
+        }
+        let Some(superclass) = class.superclass() else {
+            return;
+        };
+        if superclass.get_method(name).is_none() {
+            return;
+        }
+        let message = format!(
+            "Method '{name}' of class '{}' overrides method of '{}'",
+            class.name(),
+            superclass.name()
+        );
+        self.service.borrow_mut().set_diagnostic(message);
+    }
+
     fn get_class_property(&mut self, index: u8) -> MachineResult<()> {
-        let instance = self
-            .stack_peek()?
+        let receiver = self.stack_peek()?;
+        if receiver.as_text().is_some() {
+            let name = self.read_const_string(index)?;
+            if text_method(&name).is_some() {
+                let message = format!("String method '{name}' must be called");
+                return Err(self.runtime_error(message));
+            }
+        }
+        if receiver.as_list().is_some() {
+            let name = self.read_const_string(index)?;
+            if list_method(&name).is_some() {
+                let message = format!("List method '{name}' must be called");
+                return Err(self.runtime_error(message));
+            }
+        }
+        let instance = receiver
             .as_instance()
             .ok_or(MachineError::with_str("Only instances have fields"))?;
         let name = self.read_const_string(index)?;
@@ -475,7 +1005,7 @@ impl Machine {
             let upvalue = if data.is_local {
                 self.capture_upvalue(data.index)?
             } else {
-                self.frame()?.closure().upvalue(data.index as usize)
+                self.frame_upvalue(data.index)?
             };
             closure.assign_upvalue(i, upvalue);
         }
@@ -510,34 +1040,41 @@ impl Machine {
         Ok(upvalue)
     }
 
+    fn frame_upvalue(&self, index: u8) -> MachineResult<Shared<Upvalue>> {
+        self.frame()?
+            .closure()
+            .upvalue(index as usize)
+            .ok_or_else(|| self.runtime_error(format!("Invalid upvalue index {index}")))
+    }
+
     fn op_close_upvalue(&mut self) -> MachineResult<()> {
-        self.close_upvalues(self.stack.len() - 1)?;
+        let Some(last) = self.stack.len().checked_sub(1) else {
+            return Err(self.runtime_error("Pop on empty stack"));
+        };
+        self.close_upvalues(last)?;
         self.stack_pop()?;
         Ok(())
     }
 
     fn close_upvalues(&mut self, last: usize) -> MachineResult<()> {
-        loop {
-            let Some(front) = self.open_upvalues.front() else {
-                break;
-            };
+        while let Some(front) = self.open_upvalues.front() {
             let stack_index = extract_stack_index(front)?;
             if stack_index < last {
                 break;
             }
-            let value = self.stack[stack_index].clone();
+            let value = self.stack_get(stack_index)?;
             let upvalue = self
                 .open_upvalues
                 .pop_front()
                 .ok_or(MachineError::with_str("Bug: failed to pop front upvalue"))?;
 
-            *upvalue.borrow_mut() = Upvalue::Heap(shared(value));
+            *upvalue.borrow_mut() = Upvalue::closed(value);
         }
         Ok(())
     }
 
     fn op_get_upvalue(&mut self, index: u8) -> MachineResult<()> {
-        let shared_upvalue = self.frame()?.closure().upvalue(index as usize);
+        let shared_upvalue = self.frame_upvalue(index)?;
         let upvalue = shared_upvalue
             .try_borrow()
             .map_err(|err| MachineError::with_str(&err.to_string()))?;
@@ -552,7 +1089,7 @@ impl Machine {
     }
 
     fn op_set_upvalue(&mut self, index: u8) -> MachineResult<()> {
-        let shared_upvalue = self.frame()?.closure().upvalue(index as usize);
+        let shared_upvalue = self.frame_upvalue(index)?;
         let upvalue = shared_upvalue
             .try_borrow()
             .map_err(|err| MachineError::with_str(&err.to_string()))?;
@@ -560,7 +1097,7 @@ impl Machine {
         let value = self.stack_peek()?;
         match upvalue.deref() {
             Upvalue::Stack(index) => {
-                self.stack[*index] = value;
+                self.stack_set(*index, value)?;
             }
             Upvalue::Heap(ref_cell) => {
                 *ref_cell.borrow_mut() = value;
@@ -573,6 +1110,66 @@ impl Machine {
     }
 }
 
+/// Lists
+impl Machine {
+    fn op_build_list(&mut self, count: u8) -> MachineResult<()> {
+        let Some(start) = self.stack.len().checked_sub(count as usize) else {
+            return Err(self.runtime_error("Bug: not enough values to build a list"));
+        };
+        let items = self.stack.split_off(start);
+        self.stack_push(Value::list(items))
+    }
+
+    fn op_get_index(&mut self) -> MachineResult<()> {
+        let index = self.stack_pop()?;
+        let target = self.stack_pop()?;
+        let (list, index) = self.list_index(&target, &index)?;
+        let Some(value) = list.get(index) else {
+            return Err(self.runtime_error("List index out of range"));
+        };
+        self.stack_push(value)
+    }
+
+    /// Assigned value is left on the stack as the result of the assignment
+    fn op_set_index(&mut self) -> MachineResult<()> {
+        let value = self.stack_pop()?;
+        let index = self.stack_pop()?;
+        let target = self.stack_pop()?;
+        let (list, index) = self.list_index(&target, &index)?;
+        if !list.set(index, value.clone()) {
+            return Err(self.runtime_error("List index out of range"));
+        }
+        self.stack_push(value)
+    }
+
+    fn op_unpack(&mut self, count: u8) -> MachineResult<()> {
+        let value = self.stack_pop()?;
+        let Some(list) = value.as_list() else {
+            let message = format!("Only lists can be unpacked, got {}", value.type_name());
+            return Err(self.runtime_error(message));
+        };
+        if list.len() != count as usize {
+            let message = format!("Expected {count} values to unpack but got {}", list.len());
+            return Err(self.runtime_error(message));
+        }
+        for value in list.items() {
+            self.stack_push(value)?;
+        }
+        Ok(())
+    }
+
+    fn list_index(&self, target: &Value, index: &Value) -> MachineResult<(Rc<List>, usize)> {
+        let Some(list) = target.as_list() else {
+            let message = format!("Only lists can be indexed, got {}", target.type_name());
+            return Err(self.runtime_error(message));
+        };
+        match index.as_number() {
+            Some(x) if x >= 0.0 && x.fract() == 0.0 => Ok((list, x as usize)),
+            _ => Err(self.runtime_error("List index must be a non-negative integer")),
+        }
+    }
+}
+
 /// Access & fetch
 impl Machine {
     fn op_constant(&mut self, index: u8) -> MachineResult<()> {
@@ -580,13 +1177,32 @@ impl Machine {
         self.stack_push(value)
     }
 
+    fn op_shared_constant(&mut self, index: usize) -> MachineResult<()> {
+        let Some(value) = self.frame()?.chunk().read_shared_const(index) else {
+            return Err(self.runtime_error("Invalid shared constant index"));
+        };
+        self.stack_push(value)
+    }
+
     fn fetch_instruction(&mut self) -> FetchResult<Instruction> {
+        self.track_coverage();
         let frame = self
             .frame_mut()
             .map_err(|err| FetchError::Other(err.text))?;
         frame.fetch_instruction()
     }
 
+    fn track_coverage(&mut self) {
+        let (Some(coverage), Some(frame)) = (self.coverage.as_mut(), self.frames.last()) else {
+            return;
+        };
+        let func = frame.closure().func_ref();
+        coverage
+            .entry(Rc::as_ptr(&func))
+            .or_insert_with(|| FuncCoverage::new(func.clone()))
+            .mark(frame.ip());
+    }
+
     fn frame(&self) -> MachineResult<&CallFrame> {
         let Some(f) = self.frames.last() else {
             return Err(MachineError::with_str("Bug: empty call frame"));
@@ -629,6 +1245,15 @@ impl Machine {
         Ok(())
     }
 
+    fn op_pop_n(&mut self, count: u8) -> MachineResult<()> {
+        let count = count as usize;
+        let Some(len) = self.stack.len().checked_sub(count) else {
+            return Err(self.runtime_error("Pop on empty stack"));
+        };
+        self.stack.truncate(len);
+        Ok(())
+    }
+
     fn stack_push(&mut self, value: Value) -> MachineResult<()> {
         if self.stack.len() >= STACK_MAX_SIZE {
             return Err(self.runtime_error("Stack overflow"));
@@ -650,6 +1275,24 @@ impl Machine {
             )))
     }
 
+    fn stack_set(&mut self, index: usize, value: Value) -> MachineResult<()> {
+        let Some(item) = self.stack.get_mut(index) else {
+            let message = format!("Bug: invalid stack index ({index})");
+            return Err(self.runtime_error(message));
+        };
+        *item = value;
+        Ok(())
+    }
+
+    /// Replaces value at `rev_index` counting from the top of the stack
+    fn stack_set_at(&mut self, rev_index: usize, value: Value) -> MachineResult<()> {
+        let Some(index) = self.stack.len().checked_sub(rev_index + 1) else {
+            let message = format!("Bug: trying access stack with invalid index {rev_index}");
+            return Err(self.runtime_error(message));
+        };
+        self.stack_set(index, value)
+    }
+
     fn stack_peek_at(&self, rev_index: usize) -> MachineResult<Value> {
         let len = self.stack.len();
         let err = || {
@@ -686,36 +1329,108 @@ fn extract_stack_index(val: &Shared<Upvalue>) -> MachineResult<usize> {
 impl Machine {
     fn op_print(&mut self) -> MachineResult<()> {
         let value = self.stack_pop()?;
-        self.service.borrow_mut().print_value(value);
+        if let Some(method) = value
+            .as_instance()
+            .and_then(|instance| instance.class().get_method(&self.to_string_method))
+        {
+            // print the value returned by `toString` once the method completes
+            self.stack_push(value)?;
+            self.call_value(method, 0)?;
+            self.frame_mut()?.set_print_result();
+            return Ok(());
+        }
+        self.print(value);
         Ok(())
     }
 
-    fn runtime_error<T: AsRef<str>>(&self, message: T) -> MachineError {
-        let mut line_number: Option<usize> = None;
-        if let Ok(frame) = self.frame() {
-            let idx = frame.ip() - 1;
-            line_number = frame.chunk().line_number(idx);
+    fn print(&mut self, value: Value) {
+        let location = self.current_location();
+        let value = match value {
+            Value::Instance(_) if self.verbose_instances => {
+                Value::text_from_string(format!("{value:#}"))
+            }
+            value => value,
+        };
+        let value = match location.line {
+            Some(line) if self.print_line_numbers => {
+                Value::text_from_string(format!("[line {line}] {value}"))
+            }
+            _ => value,
+        };
+        self.service.borrow_mut().print_value_at(value, location);
+    }
+
+    /// Line and function of the instruction being executed
+    fn current_location(&self) -> StackTraceElement {
+        let Ok(frame) = self.frame() else {
+            return StackTraceElement {
+                line: None,
+                func_name: None,
+            };
+        };
+        let idx = frame.ip() - 1;
+        StackTraceElement {
+            line: frame.chunk().line_number(idx),
+            func_name: frame.func_name().map(|s| s.to_string()),
         }
+    }
+
+    fn runtime_error<T: AsRef<str>>(&self, message: T) -> MachineError {
         MachineError {
             text: message.as_ref().to_string(),
-            line_number,
+            line_number: self.current_location().line,
         }
     }
 
-    fn flush_track_trace(&mut self) {
-        let stack_trace = self
-            .frames
+    fn flush_locals(&mut self) {
+        let Ok(frame) = self.frame() else {
+            return;
+        };
+        let offset = frame.ip().saturating_sub(1);
+        let Some(infos) = frame.closure().func().debug_locals_at(offset) else {
+            return;
+        };
+        let locals = infos
+            .into_iter()
+            .filter_map(|info| {
+                let value = self.stack.get(frame.frame_start() + info.slot)?;
+                Some((info.name.clone(), value.clone()))
+            })
+            .collect::<Vec<_>>();
+        self.service.borrow_mut().set_locals(locals);
+    }
+
+    fn stack_trace(&self) -> Vec<StackTraceElement> {
+        self.frames
             .iter()
             .rev()
             .map(|frame| StackTraceElement {
                 line: frame.line_number(),
                 func_name: frame.func_name().map(|s| s.to_string()),
             })
-            .collect::<Vec<_>>();
-        self.service.borrow_mut().set_stack_trace(stack_trace);
+            .collect()
     }
 }
 
+/// Names the function and its missing parameters, e.g.
+/// `Expected 2 arguments but got 1 calling 'add', missing 'b'`
+fn arity_error_message(func: &Func, arg_count: usize) -> String {
+    let mut message = format!("Expected {} arguments but got {arg_count}", func.arity);
+    if let Some(name) = &func.name {
+        message.push_str(&format!(" calling '{name}'"));
+    }
+    let missing = func
+        .params()
+        .iter()
+        .skip(arg_count)
+        .map(|param| format!("'{param}'"))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        message.push_str(&format!(", missing {}", missing.join(", ")));
+    }
+    message
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -731,6 +1446,40 @@ pub mod tests {
         machine_test(chunk, &[Value::number(10.0)], &[Value::number(-10.0)], &[])
     }
 
+    #[test]
+    fn operation_negate_non_number() {
+        let mut chunk = Chunk::new();
+        chunk.write_u8(OPCODE_NEGATE, 1);
+        let res = machine_test(chunk, &[Value::text_from_str("abc")], &[], &[]);
+        assert_eq!(
+            res.err().as_ref().map(|x| x.message()),
+            Some("Operand must be a number")
+        );
+    }
+
+    #[test]
+    fn operation_arithmetic_sequence() -> MachineResult<()> {
+        // -(2 + 3) * 4 - 1
+        let mut chunk = Chunk::new();
+        for value in [2.0, 3.0, 4.0, 1.0] {
+            chunk.add_constant(Value::number(value));
+        }
+        let instructions = [
+            Instruction::Constant(0),
+            Instruction::Constant(1),
+            Instruction::Add,
+            Instruction::Negate,
+            Instruction::Constant(2),
+            Instruction::Multiply,
+            Instruction::Constant(3),
+            Instruction::Subtract,
+        ];
+        for instr in instructions {
+            chunk.write_buffer(&instr.as_vec(), 1);
+        }
+        machine_test(chunk, &[], &[Value::number(-21.0)], &[])
+    }
+
     #[test]
     fn operation_not() -> MachineResult<()> {
         let make_chunk = || {
@@ -1000,6 +1749,310 @@ pub mod tests {
         Ok(())
     }
 
+    #[derive(Default)]
+    struct EventCollector {
+        events: Vec<OutputEvent>,
+    }
+
+    impl BackendService for EventCollector {
+        fn on_event(&mut self, event: OutputEvent) {
+            self.events.push(event);
+        }
+    }
+
+    #[test]
+    fn output_events_order() {
+        let mut chunk = Chunk::new();
+        let idx = chunk.add_constant(Value::text_from_str("abc")) as u8;
+        chunk.write_buffer(&Instruction::Constant(idx).as_vec(), 1);
+        chunk.write_buffer(&Instruction::Print.as_vec(), 1);
+        chunk.write_buffer(&Instruction::Constant(idx).as_vec(), 2);
+        chunk.write_buffer(&Instruction::Negate.as_vec(), 2);
+
+        let collector = shared(EventCollector::default());
+        let mut machine = make_machine(chunk, collector.clone());
+        assert!(machine.run().is_err());
+
+        let events = &collector.borrow().events;
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], OutputEvent::Print(Value::Text(t)) if t.as_str() == "abc"));
+        assert!(
+            matches!(&events[1], OutputEvent::Error(err) if err.message() == "Operand must be a number")
+        );
+        assert!(matches!(&events[2], OutputEvent::Trace(trace) if trace.len() == 1));
+    }
+
+    #[test]
+    fn memory_limit_on_string_growth() {
+        let code = r#"
+            var s = "x";
+            for (;;) s = s + s;
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let probe_ref = make_probe_ref();
+        let mut machine = Machine::with(func, probe_ref.clone(), EmptyNative);
+        machine.set_memory_limit(Some(1024));
+        let result = machine.run();
+        assert_eq!(
+            result.err().as_ref().map(|x| x.message()),
+            Some("Memory limit exceeded")
+        );
+        assert!(machine.allocated_bytes <= 2 * 1024);
+    }
+
+    #[test]
+    fn huge_text_repeat_is_error() {
+        let run = |limit: Option<usize>| {
+            let code = r#"var s = "x" * 100000000000000;"#;
+            let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+            let mut machine = Machine::with(func, make_probe_ref(), EmptyNative);
+            machine.set_memory_limit(limit);
+            machine.run().err().map(|x| x.message().to_string())
+        };
+        assert_eq!(run(Some(1024)).as_deref(), Some("Memory limit exceeded"));
+        assert_eq!(run(None).as_deref(), Some("Text is too long"));
+    }
+
+    #[test]
+    fn debug_locals_on_error() {
+        let code = r#"
+            fun f(x) {
+                var a = 1;
+                {
+                    var tmp = 0;
+                }
+                var b = "two";
+                return a + -b;
+            }
+            f(5);
+        "#;
+        let options = crate::CompileOptions {
+            debug_info: true,
+            ..Default::default()
+        };
+        let func = crate::compile_with(Rc::new(code.chars().collect()), options)
+            .expect("Failed to compile");
+        let probe_ref = make_probe_ref();
+        let mut machine = Machine::with(func, probe_ref.clone(), EmptyNative);
+        assert!(machine.run().is_err());
+
+        let probe = probe_ref.borrow();
+        let locals = probe.locals.as_ref().expect("Locals are missing");
+        let expected = [
+            ("x".to_string(), Value::number(5.0)),
+            ("a".to_string(), Value::number(1.0)),
+            ("b".to_string(), Value::text_from_str("two")),
+        ];
+        assert_eq!(locals.as_slice(), &expected);
+    }
+
+    #[test]
+    fn no_debug_locals_by_default() {
+        let code = r#"
+            fun f() {
+                var a = 1;
+                return -"b";
+            }
+            f();
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let probe_ref = make_probe_ref();
+        let mut machine = Machine::with(func, probe_ref.clone(), EmptyNative);
+        assert!(machine.run().is_err());
+        assert!(probe_ref.borrow().locals.is_none());
+    }
+
+    #[test]
+    fn malformed_chunks_fail_without_panic() {
+        let cases = [
+            vec![Instruction::Nil, Instruction::SetLocal(200)],
+            vec![Instruction::GetLocal(200)],
+            vec![Instruction::GetUpvalue(5)],
+            vec![Instruction::Nil, Instruction::SetUpvalue(5)],
+            vec![Instruction::Pop, Instruction::CloseUpvalue],
+            vec![Instruction::Loop(0, 100)],
+            vec![Instruction::PopN(10)],
+        ];
+        for instructions in cases {
+            let mut chunk = Chunk::new();
+            instructions
+                .iter()
+                .for_each(|instr| chunk.write_buffer(&instr.as_vec(), 1));
+            let mut machine = make_machine(chunk, make_probe_ref());
+            assert!(machine.run().is_err(), "{instructions:?} must fail");
+        }
+    }
+
+    #[test]
+    fn inherit_from_itself() {
+        let mut chunk = Chunk::new();
+        let idx = chunk.add_constant(Value::text_from_str("A")) as u8;
+        chunk.write_buffer(&Instruction::Class(idx).as_vec(), 1);
+        chunk.write_buffer(&Instruction::Duplicate.as_vec(), 1);
+        chunk.write_buffer(&Instruction::Inherit.as_vec(), 1);
+        let probe_ref = make_probe_ref();
+        let mut machine = make_machine(chunk, probe_ref.clone());
+        assert!(machine.run().is_err());
+        let probe = probe_ref.borrow();
+        let error = probe.error.as_ref().expect("Error is missing");
+        assert_eq!(error.message(), "A class can't inherit from itself");
+    }
+
+    #[test]
+    fn coverage_skips_dead_branch() -> MachineResult<()> {
+        let code = r#"
+            var a = 1;
+            if (a > 5) {
+                print "big";
+            }
+            fun f() {}
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let chunk = func.chunk();
+        let big_offset = chunk
+            .instruction_offsets()
+            .into_iter()
+            .find(|offset| {
+                let mut offset = *offset;
+                matches!(
+                    chunk.fetch(&mut offset),
+                    Ok(Instruction::Constant(idx))
+                        if chunk.read_const(idx) == Some(Value::text_from_str("big"))
+                )
+            })
+            .expect("Dead branch constant not found");
+        let print_offset = big_offset + Instruction::Constant(0).size();
+
+        let mut machine = Machine::with(func, make_probe_ref(), EmptyNative);
+        machine.set_coverage_enabled(true);
+        machine.run()?;
+
+        // `f` is never called
+        let coverage = machine.coverage();
+        assert_eq!(coverage.len(), 1);
+        let script = coverage[0];
+        assert_eq!(script.name(), None);
+        let unexecuted = script.unexecuted_offsets();
+        assert!(unexecuted.contains(&big_offset));
+        assert!(unexecuted.contains(&print_offset));
+        assert!(!script.executed_offsets().contains(&big_offset));
+        assert!(script.executed_offsets().contains(&0));
+        Ok(())
+    }
+
+    #[test]
+    fn call_profile_counts_recursive_calls() -> MachineResult<()> {
+        let code = r#"
+            fun fib(n) {
+                if (n <= 1) return n;
+                return fib(n - 2) + fib(n - 1);
+            }
+            fun report(value) {}
+            for (var i = 0; i < 20; i = i + 1) {
+                report(fib(i));
+            }
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let mut machine = Machine::with(func, make_probe_ref(), EmptyNative);
+        machine.set_call_profiling_enabled(true);
+        machine.run()?;
+
+        // fib(n) makes 2 * F(n + 1) - 1 calls, summed up for n in 0..20
+        assert_eq!(
+            machine.call_profile(),
+            vec![("fib".to_string(), 35400), ("report".to_string(), 20)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn call_function_from_host() -> MachineResult<()> {
+        let code = r#"
+            fun add(a, b) {
+                return a + b;
+            }
+            class Pair {
+                init(a, b) {
+                    this.sum = add(a, b);
+                }
+            }
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let mut machine = Machine::with(func, make_probe_ref(), EmptyNative);
+        machine.run()?;
+
+        let result = machine.call_function("add", &[Value::number(2.0), Value::number(3.0)])?;
+        assert_eq!(result, Value::number(5.0));
+        assert!(machine.stack.is_empty());
+
+        let pair = machine.call_function("Pair", &[Value::number(1.0), Value::number(2.0)])?;
+        let Value::Instance(pair) = pair else {
+            panic!("Instance expected");
+        };
+        let sum = pair.get_field(&Rc::new("sum".to_string()));
+        assert_eq!(sum, Some(Value::number(3.0)));
+        assert!(machine.stack.is_empty());
+
+        let result = machine.call_function("add", &[Value::number(1.0)]);
+        assert!(result.is_err());
+        assert!(machine.call_function("missing", &[]).is_err());
+        assert!(machine.stack.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_globals_order() -> MachineResult<()> {
+        let code = r#"
+            var alpha = 1;
+            var beta = 2;
+            fun gamma() {}
+            class Delta {}
+            var epsilon = "e";
+            var zeta = nil;
+        "#;
+        let names = || -> MachineResult<Vec<Rc<String>>> {
+            let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+            let mut machine = Machine::with(func, make_probe_ref(), EmptyNative);
+            machine.set_deterministic_hashing(true);
+            machine.run()?;
+            Ok(machine.global_names())
+        };
+        let first = names()?;
+        assert_eq!(first.len(), 6);
+        for _ in 0..5 {
+            assert_eq!(names()?, first);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_chunk_after_runtime_error() -> MachineResult<()> {
+        let compile = |code: &str| {
+            crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile")
+        };
+        let probe_ref = make_probe_ref();
+        let first = compile(
+            r#"
+            var counter = 41;
+            fun broken() {
+                var local = 1;
+                return -"text";
+            }
+            broken();
+            counter = 0;
+        "#,
+        );
+        let mut machine = Machine::with(first, probe_ref.clone(), EmptyNative);
+        assert!(machine.run().is_err());
+        assert!(machine.stack.is_empty());
+
+        let second = compile("print counter + 1;");
+        machine.run_chunk(second)?;
+        probe_ref.borrow().assert_output_match(&["42"]);
+        assert!(machine.stack.is_empty());
+        Ok(())
+    }
+
     fn machine_test(
         chunk: Chunk,
         stack_in: &[Value],
@@ -1025,6 +2078,310 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn print_line_numbers_prefix() -> MachineResult<()> {
+        let code = r#"
+            class Point {
+                toString() {
+                    return "point";
+                }
+            }
+            print 1;
+
+            print Point();
+        "#;
+        let run = |enabled: bool| -> MachineResult<Vec<String>> {
+            let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+            let probe = make_probe_ref();
+            let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+            machine.set_print_line_numbers(enabled);
+            machine.run()?;
+            Ok(probe.borrow().print_buffer.clone())
+        };
+        assert_eq!(run(false)?, vec!["1", "point"]);
+        assert_eq!(run(true)?, vec!["[line 7] 1", "[line 9] point"]);
+        Ok(())
+    }
+
+    #[test]
+    fn print_verbose_instances() -> MachineResult<()> {
+        let code = r#"
+            class Brioche {}
+            class Point {
+                toString() {
+                    return "point";
+                }
+            }
+            var bun = Brioche();
+            bun.jam = "grape";
+            print bun;
+            print Point();
+        "#;
+        let run = |enabled: bool| -> MachineResult<Vec<String>> {
+            let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+            let probe = make_probe_ref();
+            let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+            machine.set_verbose_instances(enabled);
+            machine.run()?;
+            Ok(probe.borrow().print_buffer.clone())
+        };
+        assert_eq!(run(false)?, vec!["<Brioche instance>", "point"]);
+        assert_eq!(run(true)?, vec!["Brioche{jam: \"grape\"}", "point"]);
+        Ok(())
+    }
+
+    #[test]
+    fn print_verbose_self_referencing_instance() -> MachineResult<()> {
+        let code = r#"
+            class A {}
+            var a = A();
+            a.self = a;
+            print a;
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let probe = make_probe_ref();
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        machine.set_verbose_instances(true);
+        machine.run()?;
+        assert_eq!(probe.borrow().print_buffer, vec!["A{self: ...}"]);
+        Ok(())
+    }
+
+    #[test]
+    fn step_by_instruction() -> MachineResult<()> {
+        let func =
+            crate::compile(Rc::new("print 1 + 2;".chars().collect())).expect("Failed to compile");
+        let probe = make_probe_ref();
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        let mut executed = Vec::new();
+        loop {
+            let mut ip = machine.frame()?.ip();
+            let instr = machine
+                .frame()?
+                .chunk()
+                .fetch(&mut ip)
+                .expect("Invalid code");
+            let result = machine.step()?;
+            let printed = probe.borrow().print_buffer.clone();
+            if instr == Instruction::Print {
+                assert_eq!(printed, vec!["3"]);
+            } else if executed.contains(&Instruction::Print) {
+                assert_eq!(printed.len(), 1);
+            } else {
+                assert!(printed.is_empty());
+            }
+            executed.push(instr);
+            match result {
+                StepResult::Running { line, .. } => assert_eq!(line, Some(1)),
+                StepResult::Finished => break,
+            }
+        }
+        assert!(executed.contains(&Instruction::Add));
+        assert_eq!(executed.last(), Some(&Instruction::Return));
+        assert_eq!(machine.step()?, StepResult::Finished);
+        Ok(())
+    }
+
+    #[test]
+    fn pause_on_breakpoint_in_loop() -> MachineResult<()> {
+        let code = r#"
+            var i = 0;
+            while (i < 3) {
+                print i;
+                i = i + 1;
+            }
+            print "done";
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let probe = make_probe_ref();
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        machine.set_breakpoint(5);
+        for expected in [vec!["0"], vec!["0", "1"]] {
+            let result = machine.run_until_breakpoint()?;
+            assert!(matches!(result, StepResult::Running { line: Some(5), .. }));
+            assert_eq!(probe.borrow().print_buffer, expected);
+            assert_eq!(
+                machine.global("i"),
+                Some(Value::number(expected.len() as Double - 1.0))
+            );
+        }
+        machine.remove_breakpoint(5);
+        assert_eq!(machine.run_until_breakpoint()?, StepResult::Finished);
+        assert_eq!(probe.borrow().print_buffer, vec!["0", "1", "2", "done"]);
+        Ok(())
+    }
+
+    /// Receives the error with its trace in a single call
+    #[derive(Default)]
+    struct UncaughtErrorProbe {
+        reports: Vec<(String, Vec<StackTraceElement>)>,
+        events: usize,
+    }
+
+    impl BackendService for UncaughtErrorProbe {
+        fn on_event(&mut self, _event: OutputEvent) {
+            self.events += 1;
+        }
+
+        fn on_uncaught_error(&mut self, error: &MachineError, stack_trace: &[StackTraceElement]) {
+            self.reports
+                .push((error.message().to_string(), stack_trace.to_vec()));
+        }
+    }
+
+    #[test]
+    fn uncaught_error_hook() {
+        let code = r#"
+            fun inner() {
+                return nil + 1;
+            }
+            fun outer() {
+                inner();
+            }
+            outer();
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let probe = shared(UncaughtErrorProbe::default());
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        assert!(machine.run().is_err());
+
+        let probe = probe.borrow();
+        assert_eq!(probe.events, 0);
+        let [(message, trace)] = &probe.reports[..] else {
+            panic!("Expected a single report, got {}", probe.reports.len());
+        };
+        assert_eq!(message, "Operator '+' cannot be applied to nil and number");
+        let frames = trace
+            .iter()
+            .map(|elem| (elem.func_name.as_deref(), elem.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            vec![
+                (Some("inner"), Some(3)),
+                (Some("outer"), Some(6)),
+                (None, Some(8))
+            ]
+        );
+    }
+
+    /// Keeps printed values until they are flushed
+    #[derive(Default)]
+    struct BufferedProbe {
+        pending: Vec<String>,
+        flushed: Vec<String>,
+    }
+
+    impl BackendService for BufferedProbe {
+        fn on_event(&mut self, event: OutputEvent) {
+            if let OutputEvent::Print(value) = event {
+                self.pending.push(value.to_string());
+            }
+        }
+
+        fn flush(&mut self) {
+            self.flushed.append(&mut self.pending);
+        }
+    }
+
+    #[test]
+    fn output_flushed_after_run() -> MachineResult<()> {
+        let code = r#"
+            for (var i = 0; i < 3; i = i + 1) {
+                print i;
+            }
+            fun last() {
+                print "last";
+            }
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let probe = shared(BufferedProbe::default());
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        machine.run()?;
+        assert!(probe.borrow().pending.is_empty());
+        assert_eq!(probe.borrow().flushed, vec!["0", "1", "2"]);
+
+        machine.call_function("last", &[])?;
+        assert!(probe.borrow().pending.is_empty());
+        assert_eq!(
+            probe.borrow().flushed.last().map(|x| x.as_str()),
+            Some("last")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn closure_shared_between_machines_reads_own_globals() -> MachineResult<()> {
+        let compile = |code: &str| crate::compile(Rc::new(code.chars().collect())).unwrap();
+        let first = compile(
+            r#"
+            var a = "A";
+            var b = "B";
+            fun show() {
+                print a;
+                print b;
+            }
+            show();
+        "#,
+        );
+        let first_probe = make_probe_ref();
+        let mut first_machine = Machine::with(first, first_probe.clone(), EmptyNative);
+        first_machine.run()?;
+        first_probe.borrow().assert_output_match(&["A", "B"]);
+        let show = first_machine.global("show").expect("Function is missing");
+
+        // the same names take other slots here
+        let second = compile(r#"var b = "b2"; var a = "a2";"#);
+        let second_probe = make_probe_ref();
+        let mut second_machine = Machine::with(second, second_probe.clone(), EmptyNative);
+        second_machine.run()?;
+        HostContext::set_global(&mut second_machine, Rc::new("show".to_string()), show);
+        second_machine.call_function("show", &[])?;
+        second_probe.borrow().assert_output_match(&["a2", "b2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn validated_random_chunks_run_safely() {
+        // simple LCG keeps the test deterministic without extra dependencies
+        let mut seed: u32 = 0x2545_f491;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as u8
+        };
+        let mut validated = 0;
+        for _ in 0..5000 {
+            let mut chunk = Chunk::new();
+            for _ in 0..next() % 32 {
+                chunk.write_u8(next() % 48, 1);
+            }
+            for _ in 0..next() % 4 {
+                chunk.add_constant(Value::number(1.0));
+            }
+            if chunk.validate().is_err() {
+                continue;
+            }
+            validated += 1;
+            let mut machine = make_machine(chunk, make_probe_ref());
+            // backward jumps may loop forever
+            for _ in 0..1000 {
+                match machine.step() {
+                    Ok(StepResult::Running { .. }) => continue,
+                    Ok(StepResult::Finished) => break,
+                    Err(err) => {
+                        // stack shape isn't validated, but constants and jump targets are
+                        let message = err.message();
+                        assert!(!message.starts_with("Invalid constant index"), "{message}");
+                        assert!(!message.starts_with("Unknown instruction"), "{message}");
+                        assert!(!message.starts_with("Broken instruction"), "{message}");
+                        break;
+                    }
+                }
+            }
+        }
+        assert!(validated > 100);
+    }
+
     fn make_probe_ref() -> Shared<ProbeBackendService> {
         let probe_service = ProbeBackendService::default();
         shared(probe_service)
@@ -1,15 +1,39 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
 
 mod call_frame;
+mod coverage;
+pub use coverage::FuncCoverage;
 mod machine;
 pub use machine::Machine;
+mod random;
+pub use random::RandomNatives;
 mod service;
 pub use service::*;
 
-use crate::NativeFn;
+use crate::{ForeignClass, HostFn, NativeFn, Value};
 
 pub trait NativeFunctionsProvider {
     fn get_functions(&self) -> Vec<(String, NativeFn)>;
+
+    fn get_classes(&self) -> Vec<ForeignClass> {
+        Vec::new()
+    }
+
+    fn get_host_functions(&self) -> Vec<(String, HostFn)> {
+        Vec::new()
+    }
+}
+
+/// Machine state available to host functions
+pub trait HostContext {
+    /// Returns `None` if the global isn't defined
+    fn get_global(&self, name: &Rc<String>) -> Option<Value>;
+
+    /// Defines the global if it doesn't exist yet
+    fn set_global(&mut self, name: Rc<String>, value: Value);
+
+    /// Writes out output buffered by the backend service
+    fn flush_output(&mut self) {}
 }
 
 pub struct EmptyNative;
@@ -50,6 +74,18 @@ impl Display for MachineError {
 
 pub type MachineResult<T> = Result<T, MachineError>;
 
+/// Outcome of `Machine::step`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepResult {
+    /// Paused before the instruction at `ip` of the current function
+    Running {
+        ip: usize,
+        line: Option<usize>,
+    },
+    Finished,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct StackTraceElement {
     pub line: Option<usize>,
     pub func_name: Option<String>,
@@ -0,0 +1,138 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Double, NativeFn, NativeFunctionsProvider, Value};
+
+/// `random()`, `random_int(lo, hi)` and `seed(n)` backed by a xorshift generator.
+/// The state is kept per thread and seeded from the clock until `seed` is called,
+/// so scripts get reproducible sequences only after seeding
+pub struct RandomNatives;
+
+impl NativeFunctionsProvider for RandomNatives {
+    fn get_functions(&self) -> Vec<(String, NativeFn)> {
+        vec![
+            ("random".to_string(), native_random),
+            ("random_int".to_string(), native_random_int),
+            ("seed".to_string(), native_seed),
+        ]
+    }
+}
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(mix(clock_seed()));
+}
+
+fn clock_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// splitmix64 step, spreads close seeds apart and never yields the zero state of xorshift
+fn mix(seed: u64) -> u64 {
+    let mut x = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (x ^ (x >> 31)).max(1)
+}
+
+/// xorshift64*
+fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    })
+}
+
+/// Number in `[0, 1)`
+fn native_random(_args: &[Value]) -> Value {
+    // the mantissa of f32 holds 24 bits, so the result never rounds up to 1
+    let bits = next_u64() >> 40;
+    Value::number(bits as Double / (1u64 << 24) as Double)
+}
+
+/// Largest magnitude of `random_int` bounds, every integer up to it is exact in `Double`
+const MAX_EXACT_INTEGER: i64 = 1 << 24;
+
+/// Integer in `[lo, hi]`, returns nil if bounds aren't integers, exceed `MAX_EXACT_INTEGER`
+/// by magnitude or `lo > hi`
+fn native_random_int(args: &[Value]) -> Value {
+    let [lo, hi] = args else {
+        return Value::Nil;
+    };
+    if !lo.is_integer() || !hi.is_integer() {
+        return Value::Nil;
+    }
+    let (Some(lo), Some(hi)) = (lo.as_number(), hi.as_number()) else {
+        return Value::Nil;
+    };
+    let (lo, hi) = (lo as i64, hi as i64);
+    let range = -MAX_EXACT_INTEGER..=MAX_EXACT_INTEGER;
+    if lo > hi || !range.contains(&lo) || !range.contains(&hi) {
+        return Value::Nil;
+    }
+    let span = (hi - lo) as u64 + 1;
+    let value = lo + (next_u64() % span) as i64;
+    Value::number(value as Double)
+}
+
+/// `seed(n)` restarts the sequence, the same seed gives the same numbers
+fn native_seed(args: &[Value]) -> Value {
+    if let [seed] = args
+        && let Some(seed) = seed.as_number()
+    {
+        STATE.with(|state| state.set(mix(seed.to_bits() as u64)));
+    }
+    Value::Nil
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(seed: Double, count: usize) -> Vec<Value> {
+        native_seed(&[Value::number(seed)]);
+        let bounds = [Value::number(1.0), Value::number(6.0)];
+        (0..count).map(|_| native_random_int(&bounds)).collect()
+    }
+
+    #[test]
+    fn seeded_sequence_is_reproducible() {
+        let first = sequence(42.0, 8);
+        let numbers = first
+            .iter()
+            .map(|x| x.as_number().unwrap() as i64)
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![4, 1, 3, 4, 3, 2, 2, 2]);
+        assert_eq!(first, sequence(42.0, 8));
+        assert_ne!(first, sequence(43.0, 8));
+    }
+
+    #[test]
+    fn random_range() {
+        native_seed(&[Value::number(7.0)]);
+        for _ in 0..1000 {
+            let x = native_random(&[]).as_number().unwrap();
+            assert!((0.0..1.0).contains(&x));
+        }
+        let num = Value::number;
+        assert_eq!(native_random_int(&[num(3.0), num(3.0)]), num(3.0));
+        assert_eq!(native_random_int(&[num(3.0), num(2.0)]), Value::Nil);
+        assert_eq!(native_random_int(&[num(0.5), num(2.0)]), Value::Nil);
+        let negative = native_random_int(&[num(-2.0), num(-1.0)])
+            .as_number()
+            .unwrap();
+        assert!(negative == -2.0 || negative == -1.0);
+        let limit = (1 << 24) as Double;
+        assert!(native_random_int(&[num(-limit), num(limit)]).is_integer());
+        assert_eq!(native_random_int(&[num(-1e20), num(1e20)]), Value::Nil);
+        assert_eq!(native_random_int(&[num(0.0), num(limit * 2.0)]), Value::Nil);
+    }
+}
@@ -1,27 +1,191 @@
-use crate::{MachineError, StackTraceElement, Value};
-
-pub trait BackendService {
-    fn print_value(&mut self, value: Value);
+use std::{
+    fmt::Write as _,
+    io::{Stderr, Stdout, Write},
+};
 
-    fn set_error(&mut self, error: MachineError);
+use crate::{MachineError, StackTraceElement, Value};
 
-    fn set_stack_trace(&mut self, stack_trace: Vec<StackTraceElement>);
+pub enum OutputEvent {
+    Print(Value),
+    Error(MachineError),
+    Locals(Vec<(String, Value)>),
+    Trace(Vec<StackTraceElement>),
+    Diagnostic(String),
 }
 
-pub struct VirtualMachineService;
+pub trait BackendService {
+    fn on_event(&mut self, event: OutputEvent);
 
-impl BackendService for VirtualMachineService {
     fn print_value(&mut self, value: Value) {
-        println!("{value}");
+        self.on_event(OutputEvent::Print(value));
+    }
+
+    /// Called by the machine for each `print`, `location` points to the statement.
+    /// Ignores the location by default
+    fn print_value_at(&mut self, value: Value, _location: StackTraceElement) {
+        self.print_value(value);
     }
 
     fn set_error(&mut self, error: MachineError) {
-        eprintln!("Runtime error: {error}")
+        self.on_event(OutputEvent::Error(error));
+    }
+
+    fn set_locals(&mut self, locals: Vec<(String, Value)>) {
+        self.on_event(OutputEvent::Locals(locals));
     }
 
     fn set_stack_trace(&mut self, stack_trace: Vec<StackTraceElement>) {
-        eprintln!("Trace:");
-        stack_trace.iter().for_each(|elem| eprintln!("> {elem}"));
+        self.on_event(OutputEvent::Trace(stack_trace));
+    }
+
+    /// Called once when the script fails, the innermost call goes first in the trace.
+    /// Reports the error and the trace separately by default
+    fn on_uncaught_error(&mut self, error: &MachineError, stack_trace: &[StackTraceElement]) {
+        self.set_error(error.clone());
+        self.set_stack_trace(stack_trace.to_vec());
+    }
+
+    /// Writes out buffered output, the machine calls it when a run completes
+    fn flush(&mut self) {}
+
+    /// Non-fatal runtime notice, e.g. a method override warning
+    fn set_diagnostic(&mut self, message: String) {
+        self.on_event(OutputEvent::Diagnostic(message));
+    }
+}
+
+/// Size of buffered output that is written without waiting for a flush
+const OUTPUT_BUFFER_LIMIT: usize = 8 * 1024;
+
+/// Prints to stdout, errors and diagnostics go to stderr.
+/// In buffered mode printed values are collected and written in batches
+pub struct VirtualMachineService<O: Write = Stdout, E: Write = Stderr> {
+    out: O,
+    err: E,
+    buffer: Option<String>,
+}
+
+impl VirtualMachineService {
+    pub fn buffered() -> Self {
+        Self::buffered_with(std::io::stdout(), std::io::stderr())
+    }
+}
+
+impl Default for VirtualMachineService {
+    fn default() -> Self {
+        Self::with_writers(std::io::stdout(), std::io::stderr())
+    }
+}
+
+impl<O: Write, E: Write> VirtualMachineService<O, E> {
+    /// Same as the default service but prints to `out`, errors and diagnostics go to `err`
+    pub fn with_writers(out: O, err: E) -> Self {
+        Self {
+            out,
+            err,
+            buffer: None,
+        }
+    }
+
+    /// Same as `buffered` but prints to `out`, errors and diagnostics go to `err`
+    pub fn buffered_with(out: O, err: E) -> Self {
+        Self {
+            out,
+            err,
+            buffer: Some(String::new()),
+        }
+    }
+}
+
+impl<O: Write, E: Write> BackendService for VirtualMachineService<O, E> {
+    fn on_event(&mut self, event: OutputEvent) {
+        if let OutputEvent::Print(value) = &event
+            && let Some(buffer) = &mut self.buffer
+        {
+            _ = writeln!(buffer, "{value}");
+            if buffer.len() >= OUTPUT_BUFFER_LIMIT {
+                self.flush();
+            }
+            return;
+        }
+        // keep stderr messages in order with the printed values
+        self.flush();
+        match event {
+            OutputEvent::Print(value) => _ = writeln!(self.out, "{value}"),
+            event => _ = write_event(&mut self.err, event),
+        }
+    }
+
+    fn flush(&mut self) {
+        let Some(buffer) = &mut self.buffer else {
+            return;
+        };
+        if buffer.is_empty() {
+            return;
+        }
+        _ = self.out.write_all(buffer.as_bytes());
+        _ = self.out.flush();
+        buffer.clear();
+    }
+}
+
+impl<O: Write, E: Write> Drop for VirtualMachineService<O, E> {
+    // output printed before a panic isn't lost
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Sends printed values, errors and diagnostics to a single writer,
+/// e.g. a file or an in-memory buffer of the embedding application
+pub struct WriterService<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriterService<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> BackendService for WriterService<W> {
+    fn on_event(&mut self, event: OutputEvent) {
+        // the service has no way to report write failures
+        _ = write_event(&mut self.writer, event);
+    }
+
+    fn flush(&mut self) {
+        _ = self.writer.flush();
+    }
+}
+
+fn write_event(out: &mut impl Write, event: OutputEvent) -> std::io::Result<()> {
+    match event {
+        OutputEvent::Print(value) => writeln!(out, "{value}"),
+        OutputEvent::Error(error) => writeln!(out, "Runtime error: {error}"),
+        OutputEvent::Locals(locals) => {
+            writeln!(out, "Locals:")?;
+            for (name, value) in locals {
+                writeln!(out, "> {name} = {value}")?;
+            }
+            Ok(())
+        }
+        OutputEvent::Trace(stack_trace) => {
+            writeln!(out, "Trace:")?;
+            for elem in stack_trace {
+                writeln!(out, "> {elem}")?;
+            }
+            Ok(())
+        }
+        OutputEvent::Diagnostic(message) => writeln!(out, "Warning: {message}"),
     }
 }
 
@@ -32,7 +196,9 @@ pub mod probe {
     pub struct ProbeBackendService {
         pub print_buffer: Vec<String>,
         pub error: Option<MachineError>,
+        pub locals: Option<Vec<(String, Value)>>,
         pub stack_trace: Option<Vec<StackTraceElement>>,
+        pub diagnostics: Vec<String>,
     }
 
     impl ProbeBackendService {
@@ -49,16 +215,127 @@ pub mod probe {
     }
 
     impl BackendService for ProbeBackendService {
-        fn print_value(&mut self, value: Value) {
-            self.print_buffer.push(value.to_string());
+        fn on_event(&mut self, event: OutputEvent) {
+            match event {
+                OutputEvent::Print(value) => self.print_buffer.push(value.to_string()),
+                OutputEvent::Error(error) => self.error = Some(error),
+                OutputEvent::Locals(locals) => self.locals = Some(locals),
+                OutputEvent::Trace(stack_trace) => self.stack_trace = Some(stack_trace),
+                OutputEvent::Diagnostic(message) => self.diagnostics.push(message),
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{EmptyNative, Machine, shared};
+
+    /// In-memory writer that can be shared by the output and the error streams
+    #[derive(Clone, Default)]
+    struct SharedSink(Rc<RefCell<Vec<u8>>>);
 
-        fn set_error(&mut self, error: MachineError) {
-            self.error = Some(error);
+    impl SharedSink {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
         }
 
-        fn set_stack_trace(&mut self, stack_trace: Vec<StackTraceElement>) {
-            self.stack_trace = Some(stack_trace);
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
+
+    #[test]
+    fn buffered_service_keeps_prints_before_errors() {
+        let code = r#"
+            print "first";
+            print "second";
+            print nil + 1;
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let sink = SharedSink::default();
+        let service = shared(VirtualMachineService::buffered_with(
+            sink.clone(),
+            sink.clone(),
+        ));
+        let mut machine = Machine::with(func, service, EmptyNative);
+        assert!(machine.run().is_err());
+        assert_eq!(
+            sink.text(),
+            "first\nsecond\n\
+            Runtime error: [line 4] Operator '+' cannot be applied to nil and number\n\
+            Trace:\n\
+            > [line 4] in script\n"
+        );
+    }
+
+    #[test]
+    fn buffered_service_writes_on_flush() {
+        let mut service = VirtualMachineService::buffered_with(Vec::new(), Vec::new());
+        service.print_value(Value::number(1.0));
+        service.print_value(Value::Bool(true));
+        assert!(service.out.is_empty());
+        service.flush();
+        assert_eq!(service.out, b"1\ntrue\n");
+        assert!(service.err.is_empty());
+    }
+
+    #[test]
+    fn buffered_service_writes_full_batches() {
+        let sink = SharedSink::default();
+        let mut service = VirtualMachineService::buffered_with(sink.clone(), Vec::new());
+        let line = "x".repeat(99);
+        let lines_in_batch = OUTPUT_BUFFER_LIMIT / (line.len() + 1);
+        for _ in 0..lines_in_batch {
+            service.print_value(Value::text_from_str(&line));
+        }
+        assert!(sink.text().is_empty());
+        service.print_value(Value::text_from_str(&line));
+        assert_eq!(sink.text().len(), (lines_in_batch + 1) * (line.len() + 1));
+        service.print_value(Value::text_from_str("tail"));
+        drop(service);
+        assert!(sink.text().ends_with("x\ntail\n"));
+    }
+
+    #[test]
+    fn unbuffered_service_splits_streams() {
+        let mut service = VirtualMachineService::with_writers(Vec::new(), Vec::new());
+        service.print_value(Value::number(1.0));
+        assert_eq!(service.out, b"1\n");
+        service.set_diagnostic("careful".to_string());
+        assert_eq!(service.err, b"Warning: careful\n");
+    }
+
+    #[test]
+    fn writer_service_captures_output() {
+        let code = r#"
+            fun greet(name) {
+                return "Hello, " + name;
+            }
+            print greet("fox");
+            print 1 + 2;
+            print nil + 1;
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let service = shared(WriterService::new(Vec::<u8>::new()));
+        let mut machine = Machine::with(func, service.clone(), EmptyNative);
+        assert!(machine.run().is_err());
+        let output = String::from_utf8(service.borrow().writer().clone()).unwrap();
+        assert_eq!(
+            output,
+            "Hello, fox\n3\n\
+            Runtime error: [line 7] Operator '+' cannot be applied to nil and number\n\
+            Trace:\n\
+            > [line 7] in script\n"
+        );
+    }
 }
@@ -0,0 +1,382 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::{Chunk, ChunkError, Double, Func, LocalDebugInfo, SharedConstants, Value};
+
+/// Version of the serialized bytecode format, files of other versions are rejected on load
+pub const BYTECODE_VERSION: u16 = 3;
+
+const MAGIC: &[u8; 4] = b"FOXC";
+
+const TAG_NIL: u8 = 0;
+const TAG_NUMBER: u8 = 1;
+const TAG_BOOL: u8 = 2;
+const TAG_TEXT: u8 = 3;
+const TAG_FUN: u8 = 4;
+
+#[derive(Debug)]
+pub enum BytecodeError {
+    InvalidHeader,
+    VersionMismatch { expected: u16, found: u16 },
+    UnexpectedEnd,
+    InvalidData(&'static str),
+    UnsupportedConstant(String),
+    Chunk(ChunkError),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::InvalidHeader => write!(f, "Not a compiled fox file"),
+            BytecodeError::VersionMismatch { expected, found } => write!(
+                f,
+                "Bytecode version {found} isn't supported, expected version {expected}. Recompile the script"
+            ),
+            BytecodeError::UnexpectedEnd => write!(f, "Unexpected end of bytecode"),
+            BytecodeError::InvalidData(message) => write!(f, "Invalid bytecode: {message}"),
+            BytecodeError::UnsupportedConstant(value) => {
+                write!(f, "Constant {value} can't be serialized")
+            }
+            BytecodeError::Chunk(err) => write!(f, "Invalid bytecode: {err}"),
+        }
+    }
+}
+
+/// Writes the compiled script with all nested functions to bytes
+pub fn serialize_func(func: &Func) -> Result<Vec<u8>, BytecodeError> {
+    let mut writer = Writer {
+        buffer: Vec::new(),
+        shared_constants: func.chunk().shared_constants().cloned(),
+    };
+    writer.buffer.extend_from_slice(MAGIC);
+    writer
+        .buffer
+        .extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+    writer.write_shared_constants()?;
+    writer.write_func(func)?;
+    Ok(writer.buffer)
+}
+
+/// Restores the compiled script produced by `serialize_func`, chunks are validated on load
+pub fn deserialize_func(bytes: &[u8]) -> Result<Func, BytecodeError> {
+    let mut reader = Reader {
+        bytes,
+        offset: 0,
+        shared_constants: None,
+    };
+    if reader.read_bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(BytecodeError::InvalidHeader);
+    }
+    let version = reader.read_bytes(2)?;
+    let found = u16::from_le_bytes([version[0], version[1]]);
+    if found != BYTECODE_VERSION {
+        return Err(BytecodeError::VersionMismatch {
+            expected: BYTECODE_VERSION,
+            found,
+        });
+    }
+    reader.read_shared_constants()?;
+    let func = reader.read_func()?;
+    if reader.offset != bytes.len() {
+        return Err(BytecodeError::InvalidData("trailing bytes"));
+    }
+    Ok(func)
+}
+
+struct Writer {
+    buffer: Vec<u8>,
+    // pool of the compilation unit, written once before the functions
+    shared_constants: Option<Rc<SharedConstants>>,
+}
+
+impl Writer {
+    fn write_shared_constants(&mut self) -> Result<(), BytecodeError> {
+        let Some(pool) = self.shared_constants.clone() else {
+            self.buffer.push(0);
+            return Ok(());
+        };
+        self.buffer.push(1);
+        let values = pool.values();
+        self.write_usize(values.len());
+        for value in &values {
+            self.write_value(value)?;
+        }
+        Ok(())
+    }
+
+    fn write_func(&mut self, func: &Func) -> Result<(), BytecodeError> {
+        self.write_option(func.name.as_ref(), |w, name| w.write_str(name));
+        self.write_usize(func.arity);
+        self.write_usize(func.params.len());
+        func.params.iter().for_each(|param| self.write_str(param));
+        self.write_usize(func.upvalue_count);
+        self.buffer.push(func.is_method as u8);
+        self.write_option(func.debug_locals.as_ref(), |w, locals| {
+            w.write_usize(locals.len());
+            for info in locals {
+                w.write_str(&info.name);
+                w.write_usize(info.slot);
+                w.write_usize(info.start);
+                w.write_option(info.end.as_ref(), |w, end| w.write_usize(*end));
+            }
+        });
+        self.write_chunk(func.chunk())
+    }
+
+    fn write_chunk(&mut self, chunk: &Chunk) -> Result<(), BytecodeError> {
+        match (chunk.shared_constants(), &self.shared_constants) {
+            (None, _) => self.buffer.push(0),
+            (Some(pool), Some(written)) if Rc::ptr_eq(pool, written) => self.buffer.push(1),
+            _ => return Err(BytecodeError::InvalidData("foreign shared constants")),
+        }
+        let code = chunk.code();
+        self.write_usize(code.len());
+        self.buffer.extend_from_slice(code);
+        for offset in 0..code.len() {
+            self.write_usize(chunk.line_number(offset).unwrap_or_default());
+        }
+        let constants = chunk.constants();
+        self.write_usize(constants.len());
+        for value in constants {
+            self.write_value(value)?;
+        }
+        Ok(())
+    }
+
+    fn write_value(&mut self, value: &Value) -> Result<(), BytecodeError> {
+        match value {
+            Value::Nil => self.buffer.push(TAG_NIL),
+            Value::Number(number) => {
+                self.buffer.push(TAG_NUMBER);
+                self.buffer.extend_from_slice(&number.to_le_bytes());
+            }
+            Value::Bool(flag) => {
+                self.buffer.push(TAG_BOOL);
+                self.buffer.push(*flag as u8);
+            }
+            Value::Text(text) => {
+                self.buffer.push(TAG_TEXT);
+                self.write_str(text);
+            }
+            Value::Fun(func) => {
+                self.buffer.push(TAG_FUN);
+                self.write_func(func)?;
+            }
+            _ => return Err(BytecodeError::UnsupportedConstant(value.to_string())),
+        }
+        Ok(())
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.buffer.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_usize(value.len());
+        self.buffer.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        match value {
+            Some(value) => {
+                self.buffer.push(1);
+                write(self, value);
+            }
+            None => self.buffer.push(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    shared_constants: Option<Rc<SharedConstants>>,
+}
+
+impl<'a> Reader<'a> {
+    fn read_shared_constants(&mut self) -> Result<(), BytecodeError> {
+        self.shared_constants = self.read_option(|r| {
+            let pool = SharedConstants::default();
+            let count = r.read_usize()?;
+            for _ in 0..count {
+                let value = r.read_value()?;
+                pool.add(value)
+                    .ok_or(BytecodeError::InvalidData("too many shared constants"))?;
+            }
+            Ok(Rc::new(pool))
+        })?;
+        Ok(())
+    }
+
+    fn read_func(&mut self) -> Result<Func, BytecodeError> {
+        let mut func = Func::default();
+        func.name = self.read_option(|r| r.read_string())?;
+        func.arity = self.read_usize()?;
+        let params_count = self.read_usize()?;
+        for _ in 0..params_count {
+            func.params.push(self.read_string()?);
+        }
+        func.upvalue_count = self.read_usize()?;
+        func.is_method = self.read_u8()? != 0;
+        func.debug_locals = self.read_option(|r| {
+            let count = r.read_usize()?;
+            let mut locals = Vec::new();
+            for _ in 0..count {
+                locals.push(LocalDebugInfo {
+                    name: r.read_string()?,
+                    slot: r.read_usize()?,
+                    start: r.read_usize()?,
+                    end: r.read_option(|r| r.read_usize())?,
+                });
+            }
+            Ok(locals)
+        })?;
+        *func.chunk_mut() = self.read_chunk()?;
+        Ok(func)
+    }
+
+    fn read_chunk(&mut self) -> Result<Chunk, BytecodeError> {
+        let mut chunk = Chunk::new();
+        if self.read_u8()? != 0 {
+            let pool = self
+                .shared_constants
+                .clone()
+                .ok_or(BytecodeError::InvalidData("missing shared constants"))?;
+            chunk.set_shared_constants(pool);
+        }
+        let code_len = self.read_usize()?;
+        let code = self.read_bytes(code_len)?;
+        for byte in code {
+            chunk.write_u8(*byte, self.read_usize()?);
+        }
+        let constants_count = self.read_usize()?;
+        for _ in 0..constants_count {
+            let value = self.read_value()?;
+            chunk.add_constant(value);
+        }
+        chunk.validate().map_err(BytecodeError::Chunk)?;
+        Ok(chunk)
+    }
+
+    fn read_value(&mut self) -> Result<Value, BytecodeError> {
+        let value = match self.read_u8()? {
+            TAG_NIL => Value::Nil,
+            TAG_NUMBER => {
+                let bytes = self.read_bytes(size_of::<Double>())?;
+                let bytes = bytes
+                    .try_into()
+                    .map_err(|_| BytecodeError::InvalidData("number"))?;
+                Value::number(Double::from_le_bytes(bytes))
+            }
+            TAG_BOOL => Value::Bool(self.read_u8()? != 0),
+            TAG_TEXT => Value::text_from_string(self.read_string()?),
+            TAG_FUN => Value::Fun(Rc::new(self.read_func()?)),
+            _ => return Err(BytecodeError::InvalidData("unknown constant type")),
+        };
+        Ok(value)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_usize(&mut self) -> Result<usize, BytecodeError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.read_usize()?;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| BytecodeError::InvalidData("text"))
+    }
+
+    fn read_option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, BytecodeError>,
+    ) -> Result<Option<T>, BytecodeError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            _ => Err(BytecodeError::InvalidData("optional value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(code: &str) -> Func {
+        crate::compile(Rc::new(code.chars().collect())).unwrap()
+    }
+
+    #[test]
+    fn round_trip_keeps_functions_and_constants() {
+        let func = compile(
+            r#"
+            var text = "text";
+            fun add(a, b) {
+                return a + b + 0.5;
+            }
+            print add(1, 2);
+            print nil == false;
+            "#,
+        );
+        let bytes = serialize_func(&func).unwrap();
+        let restored = deserialize_func(&bytes).unwrap();
+        assert_eq!(restored.chunk().code(), func.chunk().code());
+        // functions are compared by reference, the serialized form is checked below
+        assert_eq!(
+            restored.chunk().constants().len(),
+            func.chunk().constants().len()
+        );
+        assert_eq!(restored.chunk().line_number(0), func.chunk().line_number(0));
+        let add = restored
+            .chunk()
+            .constants()
+            .iter()
+            .find_map(|x| x.as_function())
+            .unwrap();
+        assert_eq!(add.params(), &["a".to_string(), "b".to_string()]);
+        assert_eq!(add.arity, 2);
+        assert_eq!(serialize_func(&restored).unwrap(), bytes);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut bytes = serialize_func(&compile("print 1;")).unwrap();
+        bytes[MAGIC.len()] = 0xff;
+        let err = deserialize_func(&bytes).unwrap_err();
+        assert!(matches!(
+            err,
+            BytecodeError::VersionMismatch {
+                expected: BYTECODE_VERSION,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn broken_input() {
+        assert!(matches!(
+            deserialize_func(b"print 1;"),
+            Err(BytecodeError::InvalidHeader)
+        ));
+        let bytes = serialize_func(&compile("print 1;")).unwrap();
+        assert!(matches!(
+            deserialize_func(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::UnexpectedEnd)
+        ));
+    }
+}
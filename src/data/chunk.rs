@@ -1,10 +1,61 @@
-use crate::{FetchResult, Instruction, UpvalueData, Value};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{Display, Write},
+    rc::Rc,
+};
+
+use crate::{
+    FetchError, FetchResult, Instruction, UINT8_COUNT, UpvalueData, Value,
+    utils::{bytes_to_word, word_to_bytes},
+};
+
+/// Text constants of a compilation unit, chunks refer to them with `SharedConstant`.
+/// The pool is filled while the unit is compiled, so functions finished earlier see later entries
+#[derive(Debug, Default)]
+pub struct SharedConstants {
+    values: RefCell<Vec<Value>>,
+}
+
+impl SharedConstants {
+    /// Largest count of entries, indices are stored in two bytes
+    pub const CAPACITY: usize = u16::MAX as usize + 1;
+
+    pub fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.borrow().is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<Value> {
+        self.values.borrow().get(idx).cloned()
+    }
+
+    /// Returns index of the added value, None if the pool is full
+    pub fn add(&self, value: Value) -> Option<usize> {
+        let mut values = self.values.borrow_mut();
+        if values.len() == Self::CAPACITY {
+            return None;
+        }
+        values.push(value);
+        Some(values.len() - 1)
+    }
+
+    pub(crate) fn values(&self) -> Vec<Value> {
+        self.values.borrow().clone()
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct Chunk {
     code: Vec<u8>,
     constants: Vec<Value>,
     line: Vec<usize>,
+    // inline cache: global variable slot for each name constant.
+    // Slots are assigned by the machine, so each entry is tagged with the id of its machine
+    global_slots: Vec<Cell<Option<(usize, usize)>>>,
+    shared_constants: Option<Rc<SharedConstants>>,
 }
 
 impl Chunk {
@@ -23,19 +74,50 @@ impl Chunk {
         self.line.push(line);
     }
 
+    /// Drops the code starting from `len`, constants are kept
+    pub fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        self.line.truncate(len);
+    }
+
     pub fn patch_u8(&mut self, byte: u8, offset: usize) {
         self.code[offset] = byte;
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
         self.constants.push(value);
+        self.global_slots.push(Cell::new(None));
         self.constants.len() - 1
     }
 
+    /// Slot cached by the machine `owner`, a slot of another machine is ignored
+    pub fn cached_global_slot(&self, idx: u8, owner: usize) -> Option<usize> {
+        let (cached_owner, slot) = self.global_slots.get(idx as usize)?.get()?;
+        (cached_owner == owner).then_some(slot)
+    }
+
+    pub fn cache_global_slot(&self, idx: u8, owner: usize, slot: usize) {
+        if let Some(cell) = self.global_slots.get(idx as usize) {
+            cell.set(Some((owner, slot)));
+        }
+    }
+
     pub fn read_const(&self, idx: u8) -> Option<Value> {
         self.constants.get(idx as usize).cloned()
     }
 
+    pub fn set_shared_constants(&mut self, pool: Rc<SharedConstants>) {
+        self.shared_constants = Some(pool);
+    }
+
+    pub fn shared_constants(&self) -> Option<&Rc<SharedConstants>> {
+        self.shared_constants.as_ref()
+    }
+
+    pub fn read_shared_const(&self, idx: usize) -> Option<Value> {
+        self.shared_constants.as_ref()?.get(idx)
+    }
+
     pub fn fetch(&self, offset: &mut usize) -> FetchResult<Instruction> {
         Instruction::fetch(&self.code, offset)
     }
@@ -48,7 +130,409 @@ impl Chunk {
         self.line.get(idx).cloned()
     }
 
-    pub fn size(&self) -> usize {
+    pub(crate) fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub(crate) fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// Size of the bytecode including instruction operands
+    pub fn byte_size(&self) -> usize {
         self.code.len()
     }
+
+    /// Number of decoded instructions, upvalue operands of closures aren't counted.
+    /// Decoding stops at the first broken instruction
+    pub fn instruction_count(&self) -> usize {
+        self.instruction_offsets().len()
+    }
+
+    /// Offsets of decoded instructions, upvalue operands of closures are skipped.
+    /// Decoding stops at the first broken instruction
+    pub fn instruction_offsets(&self) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        loop {
+            let start = offset;
+            let Ok(instr) = self.fetch(&mut offset) else {
+                break;
+            };
+            if let Instruction::Closure(index) = instr {
+                let upvalue_count = self
+                    .read_const(index)
+                    .and_then(|x| x.as_function())
+                    .map(|func| func.upvalue_count)
+                    .unwrap_or_default();
+                for _ in 0..upvalue_count {
+                    _ = self.upvalue_data(&mut offset);
+                }
+            }
+            offsets.push(start);
+        }
+        offsets
+    }
+
+    /// Listing of the instructions with their offsets and lines, e.g. `0007    2 JUMP_IF_FALSE → 0019`.
+    /// Jumps show the absolute target, `→` marks forward jumps and `↺` backward ones
+    pub fn disassemble(&self) -> String {
+        let mut output = String::new();
+        let mut previous_line = None;
+        for offset in self.instruction_offsets() {
+            let mut next = offset;
+            let Ok(instr) = self.fetch(&mut next) else {
+                break;
+            };
+            let line = self.line_number(offset);
+            let line_text = if line == previous_line {
+                "   |".to_string()
+            } else {
+                format!("{:4}", line.unwrap_or_default())
+            };
+            previous_line = line;
+            let (name, mut operands) = mnemonic(&instr);
+            match instr {
+                Instruction::Jump(first, second) | Instruction::JumpIfFalse(first, second) => {
+                    operands = format!("→ {:04}", next + bytes_to_word(first, second));
+                }
+                Instruction::Loop(first, second) => {
+                    let target = next.saturating_sub(bytes_to_word(first, second));
+                    operands = format!("↺ {target:04}");
+                }
+                Instruction::SharedConstant(first, second) => {
+                    let index = bytes_to_word(first, second);
+                    operands = index.to_string();
+                    if let Some(value) = self.read_shared_const(index) {
+                        _ = write!(operands, " '{value}'");
+                    }
+                }
+                _ => {
+                    for index in instr.constant_indices() {
+                        if let Some(value) = self.read_const(index) {
+                            _ = write!(operands, " '{value}'");
+                        }
+                    }
+                }
+            }
+            _ = writeln!(output, "{offset:04} {line_text} {name} {operands}");
+        }
+        output
+    }
+
+    /// Links `other` after this chunk: its constants are appended and the instructions
+    /// are relocated to them. Shared constants of `other` are copied to the pool of this chunk.
+    /// Jumps are relative, so they are copied as is.
+    /// The trailing `nil` `return` of this chunk is dropped to fall through to `other`
+    pub fn append(&mut self, other: &Chunk) -> Result<(), ChunkError> {
+        let base = self.constants.len();
+        if base + other.constants.len() > UINT8_COUNT {
+            return Err(ChunkError::TooManyConstants);
+        }
+        let mut code = Vec::with_capacity(other.code.len());
+        let mut offset = 0;
+        while offset < other.code.len() {
+            let start = offset;
+            let instr = other
+                .fetch(&mut offset)
+                .map_err(|err| ChunkError::Fetch(start, err))?;
+            let relocated = match (&instr, instr.constant_indices().first()) {
+                (Instruction::SharedConstant(first, second), _) => {
+                    let value = other
+                        .read_shared_const(bytes_to_word(*first, *second))
+                        .ok_or(ChunkError::InvalidSharedConstant(start))?;
+                    let pool = self.shared_constants.get_or_insert_default();
+                    let index = pool.add(value).ok_or(ChunkError::TooManyConstants)?;
+                    let (first, second) = word_to_bytes(index);
+                    Instruction::SharedConstant(first, second)
+                }
+                (_, Some(index)) => instr.with_constant_index((base + *index as usize) as u8),
+                (_, None) => instr.clone(),
+            };
+            code.extend(relocated.as_vec());
+            if let Instruction::Closure(index) = instr {
+                let Some(func) = other.read_const(index).and_then(|x| x.as_function()) else {
+                    return Err(ChunkError::InvalidClosure(start));
+                };
+                let upvalues_start = offset;
+                for _ in 0..func.upvalue_count {
+                    if other.upvalue_data(&mut offset).is_none() {
+                        return Err(ChunkError::InvalidClosure(start));
+                    }
+                }
+                code.extend_from_slice(&other.code[upvalues_start..offset]);
+            }
+        }
+
+        let instruction_at = |mut offset: usize| self.fetch(&mut offset).ok();
+        if let [.., nil, ret] = self.instruction_offsets()[..]
+            && instruction_at(nil) == Some(Instruction::Nil)
+            && instruction_at(ret) == Some(Instruction::Return)
+            && ret + Instruction::Return.size() == self.code.len()
+        {
+            self.code.truncate(nil);
+            self.line.truncate(nil);
+        }
+        self.code.extend(code);
+        self.line.extend_from_slice(&other.line);
+        for value in &other.constants {
+            self.add_constant(value.clone());
+        }
+        Ok(())
+    }
+
+    /// Walks through all instructions and checks that constant indices and jump targets
+    /// refer inside the chunk. Returns the first found problem
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let start = offset;
+            let instr = self
+                .fetch(&mut offset)
+                .map_err(|err| ChunkError::Fetch(start, err))?;
+            for index in instr.constant_indices() {
+                if self.read_const(index).is_none() {
+                    return Err(ChunkError::InvalidConstant(start, index));
+                }
+            }
+            match instr {
+                Instruction::SharedConstant(first, second)
+                    if self
+                        .read_shared_const(bytes_to_word(first, second))
+                        .is_none() =>
+                {
+                    return Err(ChunkError::InvalidSharedConstant(start));
+                }
+                Instruction::Jump(first, second) | Instruction::JumpIfFalse(first, second)
+                    if offset + bytes_to_word(first, second) > self.code.len() =>
+                {
+                    return Err(ChunkError::InvalidJump(start));
+                }
+                Instruction::Loop(first, second) if bytes_to_word(first, second) > offset => {
+                    return Err(ChunkError::InvalidJump(start));
+                }
+                Instruction::Closure(index) => {
+                    let Some(func) = self.read_const(index).and_then(|x| x.as_function()) else {
+                        return Err(ChunkError::InvalidClosure(start));
+                    };
+                    for _ in 0..func.upvalue_count {
+                        if self.upvalue_data(&mut offset).is_none() {
+                            return Err(ChunkError::InvalidClosure(start));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ChunkError {
+    Fetch(usize, FetchError),
+    InvalidConstant(usize, u8),
+    InvalidJump(usize),
+    InvalidClosure(usize),
+    InvalidSharedConstant(usize),
+    TooManyConstants,
+}
+
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChunkError::Fetch(offset, err) => write!(f, "{err} at {offset}"),
+            ChunkError::InvalidConstant(offset, index) => {
+                write!(f, "Invalid constant index {index} at {offset}")
+            }
+            ChunkError::InvalidJump(offset) => write!(f, "Jump out of chunk at {offset}"),
+            ChunkError::InvalidClosure(offset) => write!(f, "Broken closure at {offset}"),
+            ChunkError::InvalidSharedConstant(offset) => {
+                write!(f, "Invalid shared constant index at {offset}")
+            }
+            ChunkError::TooManyConstants => write!(f, "Too many constants in one chunk"),
+        }
+    }
+}
+
+/// `JumpIfFalse(0, 7)` is split to `JUMP_IF_FALSE` and `0 7`
+fn mnemonic(instr: &Instruction) -> (String, String) {
+    let text = format!("{instr:?}");
+    let (name, operands) = match text.split_once('(') {
+        Some((name, operands)) => (name, operands.trim_end_matches(')').replace(", ", " ")),
+        None => (text.as_str(), String::new()),
+    };
+    let mut upper = String::new();
+    for (i, ch) in name.chars().enumerate() {
+        if i > 0 && ch.is_ascii_uppercase() {
+            upper.push('_');
+        }
+        upper.push(ch.to_ascii_uppercase());
+    }
+    (upper, operands)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{Double, Func};
+
+    fn chunk_with(instructions: &[Instruction]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for instr in instructions {
+            chunk.write_buffer(&instr.as_vec(), 1);
+        }
+        chunk
+    }
+
+    #[test]
+    fn instruction_count_of_compiled_code() {
+        let compile = |code: &str| crate::compile(Rc::new(code.chars().collect())).unwrap();
+        // Constant, Constant, Add, Print, Nil, Return
+        let func = compile("print 1 + 2;");
+        assert_eq!(func.chunk().instruction_count(), 6);
+        assert_eq!(func.chunk().byte_size(), 8);
+
+        // upvalue operands of the closure are skipped
+        let func = compile("{ var a = 1; fun f() { return a; } }");
+        assert_eq!(func.chunk().instruction_count(), 6);
+    }
+
+    #[test]
+    fn append_compiled_chunks() {
+        use crate::{EmptyNative, Machine, probe::ProbeBackendService, shared};
+
+        let compile = |code: &str| crate::compile(Rc::new(code.chars().collect())).unwrap();
+        let mut first = compile(
+            r#"
+            var greeting = "hello";
+            fun twice(x) { return x * 2; }
+            if (false) { print "skipped"; }
+            "#,
+        );
+        let second = compile(
+            r#"
+            print greeting;
+            print twice(21);
+            class Point {
+                init(x) { this.x = x; }
+                show() { return "x=" + this.x; }
+            }
+            var p = Point(1.5);
+            print p.show();
+            "#,
+        );
+        first.chunk_mut().append(second.chunk()).unwrap();
+        assert!(first.chunk().validate().is_ok());
+
+        let probe = shared(ProbeBackendService::default());
+        let mut machine = Machine::with(first, probe.clone(), EmptyNative);
+        machine.run().unwrap();
+        assert_eq!(probe.borrow().print_buffer, vec!["hello", "42", "x=1.5"]);
+    }
+
+    #[test]
+    fn append_too_many_constants() {
+        let mut chunk = Chunk::new();
+        let mut other = Chunk::new();
+        for i in 0..200 {
+            chunk.add_constant(Value::number(i as Double));
+            other.add_constant(Value::number(i as Double));
+        }
+        assert!(matches!(
+            chunk.append(&other),
+            Err(ChunkError::TooManyConstants)
+        ));
+        assert_eq!(chunk.constants().len(), 200);
+    }
+
+    #[test]
+    fn validate_correct_chunk() {
+        let mut chunk = chunk_with(&[
+            Instruction::Constant(0),
+            Instruction::JumpIfFalse(0, 2),
+            Instruction::Pop,
+            Instruction::Pop,
+            Instruction::Loop(0, 6),
+        ]);
+        chunk.add_constant(Value::number(1.0));
+        assert!(chunk.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_dangling_constant() {
+        let mut chunk = chunk_with(&[Instruction::Constant(0), Instruction::GetGlobal(3)]);
+        chunk.add_constant(Value::number(1.0));
+        let result = chunk.validate();
+        assert!(matches!(result, Err(ChunkError::InvalidConstant(2, 3))));
+    }
+
+    #[test]
+    fn validate_jump_out_of_chunk() {
+        let chunk = chunk_with(&[Instruction::Jump(0, 5), Instruction::Pop]);
+        assert!(matches!(chunk.validate(), Err(ChunkError::InvalidJump(0))));
+
+        let chunk = chunk_with(&[Instruction::Pop, Instruction::Loop(0, 5)]);
+        assert!(matches!(chunk.validate(), Err(ChunkError::InvalidJump(1))));
+    }
+
+    #[test]
+    fn validate_truncated_instruction() {
+        let mut chunk = chunk_with(&[Instruction::Pop]);
+        chunk.write_u8(crate::OPCODE_JUMP, 1);
+        assert!(matches!(
+            chunk.validate(),
+            Err(ChunkError::Fetch(1, FetchError::Broken))
+        ));
+    }
+
+    #[test]
+    fn validate_closure_upvalues() {
+        let mut func = Func::default();
+        func.upvalue_count = 2;
+        let mut chunk = chunk_with(&[Instruction::Closure(0)]);
+        chunk.add_constant(Value::Fun(Rc::new(func)));
+        chunk.write_buffer(&[1, 0], 1);
+        assert!(matches!(
+            chunk.validate(),
+            Err(ChunkError::InvalidClosure(0))
+        ));
+        chunk.write_buffer(&[0, 1], 1);
+        assert!(chunk.validate().is_ok());
+    }
+
+    #[test]
+    fn disassemble_loop_targets() {
+        let code = "var i = 0;\nwhile (i < 3) {\n  i = i + 1;\n}\nprint i;";
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let listing = func.chunk().disassemble();
+        let lines = listing.lines().collect::<Vec<_>>();
+        let target_of = |name: &str, marker: &str| {
+            let line = lines
+                .iter()
+                .find(|line| line.contains(name))
+                .unwrap_or_else(|| panic!("{name} is missing in\n{listing}"));
+            let (_, target) = line.split_once(marker).expect("Jump target is missing");
+            target.trim().to_string()
+        };
+        let loop_target = target_of("LOOP", "↺");
+        let loop_start = lines
+            .iter()
+            .find(|line| line.starts_with(&loop_target))
+            .expect("Loop target isn't an instruction");
+        assert!(loop_start.contains(" 2 GET_GLOBAL 1 'i'"), "{listing}");
+
+        let exit_target = target_of("JUMP_IF_FALSE", "→");
+        let exit = lines
+            .iter()
+            .position(|line| line.starts_with(&exit_target))
+            .expect("Exit target isn't an instruction");
+        assert!(lines[exit - 1].contains("LOOP"), "{listing}");
+        assert!(
+            lines[0].starts_with("0000    1 CONSTANT_SMALL 0"),
+            "{listing}"
+        );
+    }
 }
@@ -1,11 +1,20 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
-
-use crate::{Closure, Value};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::{
+    Closure, Value,
+    data::heap_stats::{HeapObject, track_alloc, track_drop},
+};
 
 #[derive(Debug)]
 pub struct Class {
     name: Rc<String>,
     methods: RefCell<HashMap<Rc<String>, Value>>,
+    superclass: RefCell<Option<Rc<Class>>>,
 }
 
 impl Class {
@@ -13,13 +22,23 @@ impl Class {
         Self {
             name,
             methods: Default::default(),
+            superclass: Default::default(),
         }
     }
 
-    pub fn inherit_methods(&self, parent: &Class) {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inherit_methods(&self, parent: &Rc<Class>) {
         // TODO: replace with try_borrow_mut
         assert!(self.methods.borrow().is_empty());
         *self.methods.borrow_mut() = parent.methods.borrow().clone();
+        *self.superclass.borrow_mut() = Some(parent.clone());
+    }
+
+    pub fn superclass(&self) -> Option<Rc<Class>> {
+        self.superclass.borrow().clone()
     }
 
     pub fn get_method(&self, name: &Rc<String>) -> Option<Value> {
@@ -27,6 +46,19 @@ impl Class {
         self.methods.borrow().get(name).cloned()
     }
 
+    pub fn has_method(&self, name: &Rc<String>) -> bool {
+        // TODO: replace with try_borrow
+        self.methods.borrow().contains_key(name)
+    }
+
+    /// Names of own and inherited methods, sorted
+    pub fn method_names(&self) -> Vec<Rc<String>> {
+        // TODO: replace with try_borrow
+        let mut names = self.methods.borrow().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
     pub fn add_method(&self, name: Rc<String>, value: Value) {
         // TODO: replace with try_borrow_mut
         self.methods.borrow_mut().insert(name, value);
@@ -73,6 +105,7 @@ pub struct Instance {
 
 impl Instance {
     pub fn new(class: Rc<Class>) -> Self {
+        track_alloc(HeapObject::Instance);
         Self {
             class,
             fields: Default::default(),
@@ -92,10 +125,99 @@ impl Instance {
         // TODO: replace with try_borrow_mut
         self.fields.borrow_mut().insert(name, v);
     }
+
+    /// Removing a missing field is a no-op
+    pub fn remove_field(&self, name: &Rc<String>) {
+        // TODO: replace with try_borrow_mut
+        self.fields.borrow_mut().remove(name);
+    }
+
+    /// Snapshot of the instance fields, order isn't specified
+    pub fn fields(&self) -> Vec<(Rc<String>, Value)> {
+        self.fields
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Writes fields recursively, `visited` holds instances being printed up the tree,
+    /// so reference cycles are shown as `...` instead of recursing forever
+    fn write_verbose(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        visited: &mut HashSet<*const Instance>,
+    ) -> std::fmt::Result {
+        let ptr = self as *const Instance;
+        if !visited.insert(ptr) {
+            return write!(f, "...");
+        }
+        let mut fields = self.fields();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        write!(f, "{}{{", self.class.name)?;
+        for (i, (name, value)) in fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{name}: ")?;
+            match value {
+                Value::Text(text) => write!(f, "\"{text}\"")?,
+                Value::Instance(instance) => instance.write_verbose(f, visited)?,
+                _ => write!(f, "{value}")?,
+            }
+        }
+        visited.remove(&ptr);
+        write!(f, "}}")
+    }
 }
 
+/// Alternate format (`{:#}`) lists the fields sorted by name, e.g. `Brioche{jam: "grape"}`
 impl Display for Instance {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<{} instance>", self.class.name)
+        if !f.alternate() {
+            return write!(f, "<{} instance>", self.class.name);
+        }
+        self.write_verbose(f, &mut HashSet::new())
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        track_drop(HeapObject::Instance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_verbose_display() {
+        let class = Rc::new(Class::new(Rc::new("Brioche".to_string())));
+        let instance = Instance::new(class);
+        assert_eq!(format!("{instance:#}"), "Brioche{}");
+        instance.set_field(Rc::new("jam".to_string()), Value::text_from_str("grape"));
+        instance.set_field(Rc::new("count".to_string()), Value::Number(2.0));
+        assert_eq!(format!("{instance}"), "<Brioche instance>");
+        assert_eq!(format!("{instance:#}"), "Brioche{count: 2, jam: \"grape\"}");
+    }
+
+    #[test]
+    fn instance_verbose_display_cycle() {
+        let name = |x: &str| Rc::new(x.to_string());
+        let class = Rc::new(Class::new(name("Node")));
+        let a = Rc::new(Instance::new(class.clone()));
+        let b = Rc::new(Instance::new(class));
+        a.set_field(name("self"), Value::Instance(a.clone()));
+        a.set_field(name("next"), Value::Instance(b.clone()));
+        b.set_field(name("prev"), Value::Instance(a.clone()));
+        b.set_field(name("id"), Value::Number(2.0));
+        assert_eq!(
+            format!("{a:#}"),
+            "Node{next: Node{id: 2, prev: ...}, self: ...}"
+        );
+        // break the cycles, so instances are freed
+        a.remove_field(&name("self"));
+        b.remove_field(&name("prev"));
     }
 }
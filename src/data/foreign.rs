@@ -0,0 +1,92 @@
+use std::{any::Any, cell::RefCell, collections::HashMap, fmt::Display, rc::Rc};
+
+use crate::Value;
+
+pub type ForeignConstructor = fn(&[Value]) -> Box<dyn Any>;
+/// Method of a host class, the closure may capture state of the embedding application
+pub type ForeignMethod = Rc<dyn Fn(&mut dyn Any, &[Value]) -> Value>;
+
+/// Class implemented on the host side.
+/// Calling it from script creates a `ForeignInstance` using `constructor`,
+/// methods receive the instance's state as `&mut dyn Any`
+pub struct ForeignClass {
+    name: Rc<String>,
+    constructor: ForeignConstructor,
+    methods: HashMap<Rc<String>, ForeignMethod>,
+}
+
+impl ForeignClass {
+    pub fn with<T: AsRef<str>>(name: T, constructor: ForeignConstructor) -> Self {
+        Self {
+            name: Rc::new(name.as_ref().to_string()),
+            constructor,
+            methods: Default::default(),
+        }
+    }
+
+    pub fn add_method<T: AsRef<str>>(
+        &mut self,
+        name: T,
+        method: impl Fn(&mut dyn Any, &[Value]) -> Value + 'static,
+    ) {
+        self.methods
+            .insert(Rc::new(name.as_ref().to_string()), Rc::new(method));
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn get_method(&self, name: &Rc<String>) -> Option<ForeignMethod> {
+        self.methods.get(name).cloned()
+    }
+
+    pub fn construct(&self, args: &[Value]) -> Box<dyn Any> {
+        (self.constructor)(args)
+    }
+}
+
+impl std::fmt::Debug for ForeignClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ForeignClass")
+            .field("name", &self.name)
+            .field("methods", &self.methods.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Display for ForeignClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<host class {}>", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct ForeignInstance {
+    class: Rc<ForeignClass>,
+    state: RefCell<Box<dyn Any>>,
+}
+
+impl ForeignInstance {
+    pub fn new(class: Rc<ForeignClass>, state: Box<dyn Any>) -> Self {
+        Self {
+            class,
+            state: RefCell::new(state),
+        }
+    }
+
+    pub fn class(&self) -> Rc<ForeignClass> {
+        self.class.clone()
+    }
+
+    pub fn call(&self, method: &ForeignMethod, args: &[Value]) -> Option<Value> {
+        let mut state = self.state.try_borrow_mut().ok()?;
+        Some(method(state.as_mut(), args))
+    }
+}
+
+impl Display for ForeignInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{} host instance>", self.class.name)
+    }
+}
@@ -3,9 +3,12 @@ use std::{
     rc::Rc,
 };
 
-use crate::{Chunk, Shared, Value, shared};
+use crate::{
+    Chunk, HostContext, Shared, Value,
+    data::heap_stats::{HeapObject, track_alloc, track_drop},
+    shared,
+};
 
-#[derive(Default)]
 pub struct Closure {
     func: Rc<Func>,
     upvalues: Vec<Shared<Upvalue>>,
@@ -13,7 +16,7 @@ pub struct Closure {
 
 impl Display for Closure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<closure {}>", self.func())
+        write!(f, "{}", self.func())
     }
 }
 
@@ -30,6 +33,7 @@ impl Closure {
     pub fn new(func: Rc<Func>) -> Self {
         let count = func.upvalue_count;
         let upvalues = vec![shared(Upvalue::Nil); count];
+        track_alloc(HeapObject::Closure);
         Self { func, upvalues }
     }
 
@@ -37,12 +41,16 @@ impl Closure {
         &self.func
     }
 
+    pub fn func_ref(&self) -> Rc<Func> {
+        self.func.clone()
+    }
+
     pub fn upvalues_count(&self) -> usize {
         self.upvalues.len()
     }
 
-    pub fn upvalue(&self, index: usize) -> Shared<Upvalue> {
-        self.upvalues[index].clone()
+    pub fn upvalue(&self, index: usize) -> Option<Shared<Upvalue>> {
+        self.upvalues.get(index).cloned()
     }
 
     pub fn assign_upvalue(&mut self, index: usize, upvalue: Shared<Upvalue>) {
@@ -50,18 +58,44 @@ impl Closure {
     }
 }
 
+impl Drop for Closure {
+    fn drop(&mut self) {
+        track_drop(HeapObject::Closure);
+    }
+}
+
 pub enum Upvalue {
     Stack(usize),
     Heap(Shared<Value>),
     Nil,
 }
 
+impl Upvalue {
+    /// Moves the captured value off the stack
+    pub fn closed(value: Value) -> Self {
+        track_alloc(HeapObject::Upvalue);
+        Self::Heap(shared(value))
+    }
+}
+
+impl Drop for Upvalue {
+    fn drop(&mut self) {
+        if let Self::Heap(_) = self {
+            track_drop(HeapObject::Upvalue);
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Func {
     pub(crate) arity: usize,
+    pub(crate) params: Vec<String>,
     chunk: Chunk,
     pub(crate) name: Option<String>,
     pub(crate) upvalue_count: usize,
+    /// Methods and initializers keep the receiver in slot 0
+    pub(crate) is_method: bool,
+    pub(crate) debug_locals: Option<Vec<LocalDebugInfo>>,
 }
 
 impl Func {
@@ -72,6 +106,37 @@ impl Func {
     pub fn chunk_mut(&mut self) -> &mut Chunk {
         &mut self.chunk
     }
+
+    /// Parameter names in declaration order
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// Returns debug info of locals which are alive at the `offset` of the chunk.
+    /// None if the function was compiled without debug info
+    pub fn debug_locals_at(&self, offset: usize) -> Option<Vec<&LocalDebugInfo>> {
+        let locals = self.debug_locals.as_ref()?;
+        let alive = locals
+            .iter()
+            .filter(|info| info.is_alive_at(offset))
+            .collect::<Vec<_>>();
+        Some(alive)
+    }
+}
+
+/// Describes local variable's slot and the chunk range where the variable is in the scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalDebugInfo {
+    pub name: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl LocalDebugInfo {
+    fn is_alive_at(&self, offset: usize) -> bool {
+        self.start <= offset && self.end.map(|end| offset < end).unwrap_or(true)
+    }
 }
 
 impl Display for Func {
@@ -93,19 +158,53 @@ pub enum FuncType {
 }
 
 pub type NativeFn = fn(&[Value]) -> Value;
+/// Native function that has access to the machine state
+pub type HostFn = fn(&mut dyn HostContext, &[Value]) -> NativeAction;
+
+/// What the machine does when a native function returns
+#[derive(Debug, PartialEq)]
+pub enum NativeAction {
+    /// Use the value as the call result
+    Return(Value),
+    /// Call the callee with the arguments in place of the native, its result becomes the call result
+    Call(Value, Vec<Value>),
+}
+
+impl From<Value> for NativeAction {
+    fn from(value: Value) -> Self {
+        NativeAction::Return(value)
+    }
+}
+
+#[derive(Debug)]
+enum NativeKind {
+    Plain(NativeFn),
+    Host(HostFn),
+}
 
 #[derive(Debug)]
 pub struct NativeFunc {
-    func: NativeFn,
+    func: NativeKind,
 }
 
 impl NativeFunc {
     pub fn with(func: NativeFn) -> Self {
-        Self { func }
+        Self {
+            func: NativeKind::Plain(func),
+        }
     }
 
-    pub fn call(&self, args: &[Value]) -> Value {
-        (self.func)(args)
+    pub fn with_host(func: HostFn) -> Self {
+        Self {
+            func: NativeKind::Host(func),
+        }
+    }
+
+    pub fn call(&self, context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+        match self.func {
+            NativeKind::Plain(func) => func(args).into(),
+            NativeKind::Host(func) => func(context, args),
+        }
     }
 }
 
@@ -0,0 +1,61 @@
+use std::cell::Cell;
+
+/// Numbers of heap objects alive on the current thread, collected only while enabled with
+/// `set_heap_stats_enabled`. Counts that don't return to the baseline after the machine is dropped
+/// point to reference cycles, e.g. an instance field holding a closure that captured the instance
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HeapStats {
+    pub closures: usize,
+    pub instances: usize,
+    /// Only closed upvalues are counted, open ones refer to the stack
+    pub upvalues: usize,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum HeapObject {
+    Closure,
+    Instance,
+    Upvalue,
+}
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static LIVE: Cell<HeapStats> = Cell::new(HeapStats::default());
+}
+
+/// Turns counting on or off for the current thread and resets the counts.
+/// Objects created before counting is enabled aren't tracked, so enable it before
+/// creating the machine, otherwise drops of older objects lower the counts
+pub fn set_heap_stats_enabled(enabled: bool) {
+    ENABLED.with(|flag| flag.set(enabled));
+    LIVE.with(|live| live.set(HeapStats::default()));
+}
+
+/// Snapshot of the live object counts, all zeros if counting isn't enabled
+pub fn heap_stats() -> HeapStats {
+    LIVE.with(|live| live.get())
+}
+
+pub(crate) fn track_alloc(object: HeapObject) {
+    update(object, |count| *count += 1);
+}
+
+pub(crate) fn track_drop(object: HeapObject) {
+    update(object, |count| *count = count.saturating_sub(1));
+}
+
+fn update(object: HeapObject, change: impl FnOnce(&mut usize)) {
+    if !ENABLED.with(|flag| flag.get()) {
+        return;
+    }
+    LIVE.with(|live| {
+        let mut stats = live.get();
+        let count = match object {
+            HeapObject::Closure => &mut stats.closures,
+            HeapObject::Instance => &mut stats.instances,
+            HeapObject::Upvalue => &mut stats.upvalues,
+        };
+        change(count);
+        live.set(stats);
+    });
+}
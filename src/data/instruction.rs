@@ -40,6 +40,18 @@ pub const OPCODE_INVOKE: u8 = 34;
 pub const OPCODE_INHERIT: u8 = 35;
 pub const OPCODE_GET_SUPER: u8 = 36;
 pub const OPCODE_SUPER_INVOKE: u8 = 37;
+pub const OPCODE_POP_N: u8 = 38;
+pub const OPCODE_FLOOR_DIVIDE: u8 = 39;
+pub const OPCODE_NOT_EQUAL: u8 = 40;
+pub const OPCODE_GREATER_EQUAL: u8 = 41;
+pub const OPCODE_LESS_EQUAL: u8 = 42;
+pub const OPCODE_CONSTANT_SMALL: u8 = 43;
+pub const OPCODE_ASSERT_EQUAL: u8 = 44;
+pub const OPCODE_SHARED_CONSTANT: u8 = 45;
+pub const OPCODE_BUILD_LIST: u8 = 46;
+pub const OPCODE_GET_INDEX: u8 = 47;
+pub const OPCODE_SET_INDEX: u8 = 48;
+pub const OPCODE_UNPACK: u8 = 49;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Instruction {
@@ -81,6 +93,22 @@ pub enum Instruction {
     Inherit,
     GetSuper(u8),
     SuperInvoke(u8, u8),
+    PopN(u8),
+    FloorDivide,
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    /// Integer number which doesn't need a constant pool slot
+    ConstantSmall(i8),
+    AssertEqual,
+    /// Text from the pool shared by the compilation unit, the index is stored as (high, low) pair
+    SharedConstant(u8, u8),
+    /// Replaces the given count of values on top of the stack with a list of them
+    BuildList(u8),
+    GetIndex,
+    SetIndex,
+    /// Replaces a list on top of the stack with its items, the list must have the given length
+    Unpack(u8),
 }
 
 impl Instruction {
@@ -91,6 +119,46 @@ impl Instruction {
     pub fn stub_jump() -> Self {
         Self::Jump(0xff, 0xff)
     }
+
+    /// Indices of the constants the instruction refers to
+    pub fn constant_indices(&self) -> Vec<u8> {
+        match self {
+            Instruction::Constant(index)
+            | Instruction::DefineGlobal(index)
+            | Instruction::GetGlobal(index)
+            | Instruction::SetGlobal(index)
+            | Instruction::Closure(index)
+            | Instruction::Class(index)
+            | Instruction::GetProperty(index)
+            | Instruction::SetProperty(index)
+            | Instruction::Method(index)
+            | Instruction::Invoke(index, _)
+            | Instruction::GetSuper(index)
+            | Instruction::SuperInvoke(index, _) => vec![*index],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Same instruction referring to another constant, instructions without constants are kept
+    pub fn with_constant_index(&self, new_index: u8) -> Self {
+        match self {
+            Instruction::Constant(_) => Instruction::Constant(new_index),
+            Instruction::DefineGlobal(_) => Instruction::DefineGlobal(new_index),
+            Instruction::GetGlobal(_) => Instruction::GetGlobal(new_index),
+            Instruction::SetGlobal(_) => Instruction::SetGlobal(new_index),
+            Instruction::Closure(_) => Instruction::Closure(new_index),
+            Instruction::Class(_) => Instruction::Class(new_index),
+            Instruction::GetProperty(_) => Instruction::GetProperty(new_index),
+            Instruction::SetProperty(_) => Instruction::SetProperty(new_index),
+            Instruction::Method(_) => Instruction::Method(new_index),
+            Instruction::Invoke(_, arg_count) => Instruction::Invoke(new_index, *arg_count),
+            Instruction::GetSuper(_) => Instruction::GetSuper(new_index),
+            Instruction::SuperInvoke(_, arg_count) => {
+                Instruction::SuperInvoke(new_index, *arg_count)
+            }
+            _ => self.clone(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -155,6 +223,18 @@ impl Instruction {
             Instruction::Inherit => vec![OPCODE_INHERIT],
             Instruction::GetSuper(val) => vec![OPCODE_GET_SUPER, *val],
             Instruction::SuperInvoke(name, args) => vec![OPCODE_SUPER_INVOKE, *name, *args],
+            Instruction::PopN(count) => vec![OPCODE_POP_N, *count],
+            Instruction::FloorDivide => vec![OPCODE_FLOOR_DIVIDE],
+            Instruction::NotEqual => vec![OPCODE_NOT_EQUAL],
+            Instruction::GreaterEqual => vec![OPCODE_GREATER_EQUAL],
+            Instruction::LessEqual => vec![OPCODE_LESS_EQUAL],
+            Instruction::ConstantSmall(val) => vec![OPCODE_CONSTANT_SMALL, *val as u8],
+            Instruction::AssertEqual => vec![OPCODE_ASSERT_EQUAL],
+            Instruction::SharedConstant(f, s) => vec![OPCODE_SHARED_CONSTANT, *f, *s],
+            Instruction::BuildList(count) => vec![OPCODE_BUILD_LIST, *count],
+            Instruction::GetIndex => vec![OPCODE_GET_INDEX],
+            Instruction::SetIndex => vec![OPCODE_SET_INDEX],
+            Instruction::Unpack(count) => vec![OPCODE_UNPACK, *count],
         }
     }
 
@@ -172,6 +252,9 @@ impl Instruction {
             OPCODE_EQUAL => Ok(Instruction::Equal),
             OPCODE_GREATER => Ok(Instruction::Greater),
             OPCODE_LESS => Ok(Instruction::Less),
+            OPCODE_NOT_EQUAL => Ok(Instruction::NotEqual),
+            OPCODE_GREATER_EQUAL => Ok(Instruction::GreaterEqual),
+            OPCODE_LESS_EQUAL => Ok(Instruction::LessEqual),
             OPCODE_NEGATE => Ok(Instruction::Negate),
 
             OPCODE_NIL => Ok(Instruction::Nil),
@@ -182,6 +265,7 @@ impl Instruction {
             OPCODE_SUBTRACT => Ok(Instruction::Subtract),
             OPCODE_MULTIPLY => Ok(Instruction::Multiply),
             OPCODE_DIVIDE => Ok(Instruction::Divide),
+            OPCODE_FLOOR_DIVIDE => Ok(Instruction::FloorDivide),
 
             OPCODE_NOT => Ok(Instruction::Not),
 
@@ -273,6 +357,30 @@ impl Instruction {
                 let args = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
                 Ok(Instruction::SuperInvoke(name, args))
             }
+            OPCODE_ASSERT_EQUAL => Ok(Instruction::AssertEqual),
+            OPCODE_CONSTANT_SMALL => {
+                let arg = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
+                Ok(Instruction::ConstantSmall(arg as i8))
+            }
+            OPCODE_POP_N => {
+                let count = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
+                Ok(Instruction::PopN(count))
+            }
+            OPCODE_SHARED_CONSTANT => {
+                let high = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
+                let low = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
+                Ok(Instruction::SharedConstant(high, low))
+            }
+            OPCODE_BUILD_LIST => {
+                let count = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
+                Ok(Instruction::BuildList(count))
+            }
+            OPCODE_GET_INDEX => Ok(Instruction::GetIndex),
+            OPCODE_SET_INDEX => Ok(Instruction::SetIndex),
+            OPCODE_UNPACK => {
+                let count = consume_byte(buffer, offset).ok_or(FetchError::Broken)?;
+                Ok(Instruction::Unpack(count))
+            }
             x => Err(FetchError::Unknown(x)),
         }
     }
@@ -301,6 +409,11 @@ mod tests {
             (OPCODE_SUBTRACT, Instruction::Subtract),
             (OPCODE_MULTIPLY, Instruction::Multiply),
             (OPCODE_DIVIDE, Instruction::Divide),
+            (OPCODE_FLOOR_DIVIDE, Instruction::FloorDivide),
+            (OPCODE_NOT_EQUAL, Instruction::NotEqual),
+            (OPCODE_GREATER_EQUAL, Instruction::GreaterEqual),
+            (OPCODE_LESS_EQUAL, Instruction::LessEqual),
+            (OPCODE_ASSERT_EQUAL, Instruction::AssertEqual),
             (OPCODE_RETURN, Instruction::Return),
             (OPCODE_NOT, Instruction::Not),
             (OPCODE_LESS, Instruction::Less),
@@ -309,6 +422,8 @@ mod tests {
             (OPCODE_DUPLICATE, Instruction::Duplicate),
             (OPCODE_CLOSE_UPVALUE, Instruction::CloseUpvalue),
             (OPCODE_INHERIT, Instruction::Inherit),
+            (OPCODE_GET_INDEX, Instruction::GetIndex),
+            (OPCODE_SET_INDEX, Instruction::SetIndex),
         ];
         let buffer = data.iter().map(|(opcode, _)| *opcode).collect::<Vec<_>>();
         let mut offset = 0;
@@ -337,6 +452,13 @@ mod tests {
             ([OPCODE_SET_PROPERTY, 63], Instruction::SetProperty(63)),
             ([OPCODE_METHOD, 153], Instruction::Method(153)),
             ([OPCODE_GET_SUPER, 184], Instruction::GetSuper(184)),
+            ([OPCODE_POP_N, 5], Instruction::PopN(5)),
+            ([OPCODE_BUILD_LIST, 3], Instruction::BuildList(3)),
+            ([OPCODE_UNPACK, 2], Instruction::Unpack(2)),
+            (
+                [OPCODE_CONSTANT_SMALL, 0xfb],
+                Instruction::ConstantSmall(-5),
+            ),
         ];
         for (inp, exp) in data.iter() {
             let mut offset = 0;
@@ -394,4 +516,146 @@ mod tests {
             _ => panic!("Invalid opcode"),
         }
     }
+
+    /// Every variant with the given operands, the match below fails to compile
+    /// when a new variant isn't listed here
+    fn all_instructions(a: u8, b: u8) -> Vec<Instruction> {
+        let list = vec![
+            Instruction::Constant(a),
+            Instruction::Equal,
+            Instruction::Greater,
+            Instruction::Less,
+            Instruction::Nil,
+            Instruction::True,
+            Instruction::False,
+            Instruction::Negate,
+            Instruction::Add,
+            Instruction::Subtract,
+            Instruction::Multiply,
+            Instruction::Divide,
+            Instruction::Not,
+            Instruction::Print,
+            Instruction::Return,
+            Instruction::Pop,
+            Instruction::DefineGlobal(a),
+            Instruction::GetGlobal(a),
+            Instruction::SetGlobal(a),
+            Instruction::GetLocal(a),
+            Instruction::SetLocal(a),
+            Instruction::JumpIfFalse(a, b),
+            Instruction::Jump(a, b),
+            Instruction::Loop(a, b),
+            Instruction::Duplicate,
+            Instruction::Call(a),
+            Instruction::Closure(a),
+            Instruction::GetUpvalue(a),
+            Instruction::SetUpvalue(a),
+            Instruction::CloseUpvalue,
+            Instruction::Class(a),
+            Instruction::GetProperty(a),
+            Instruction::SetProperty(a),
+            Instruction::Method(a),
+            Instruction::Invoke(a, b),
+            Instruction::Inherit,
+            Instruction::GetSuper(a),
+            Instruction::SuperInvoke(a, b),
+            Instruction::PopN(a),
+            Instruction::FloorDivide,
+            Instruction::NotEqual,
+            Instruction::GreaterEqual,
+            Instruction::LessEqual,
+            Instruction::ConstantSmall(a as i8),
+            Instruction::AssertEqual,
+            Instruction::SharedConstant(a, b),
+            Instruction::BuildList(a),
+            Instruction::GetIndex,
+            Instruction::SetIndex,
+            Instruction::Unpack(a),
+        ];
+        for instr in &list {
+            match instr {
+                Instruction::Constant(_)
+                | Instruction::Equal
+                | Instruction::Greater
+                | Instruction::Less
+                | Instruction::Nil
+                | Instruction::True
+                | Instruction::False
+                | Instruction::Negate
+                | Instruction::Add
+                | Instruction::Subtract
+                | Instruction::Multiply
+                | Instruction::Divide
+                | Instruction::Not
+                | Instruction::Print
+                | Instruction::Return
+                | Instruction::Pop
+                | Instruction::DefineGlobal(_)
+                | Instruction::GetGlobal(_)
+                | Instruction::SetGlobal(_)
+                | Instruction::GetLocal(_)
+                | Instruction::SetLocal(_)
+                | Instruction::JumpIfFalse(_, _)
+                | Instruction::Jump(_, _)
+                | Instruction::Loop(_, _)
+                | Instruction::Duplicate
+                | Instruction::Call(_)
+                | Instruction::Closure(_)
+                | Instruction::GetUpvalue(_)
+                | Instruction::SetUpvalue(_)
+                | Instruction::CloseUpvalue
+                | Instruction::Class(_)
+                | Instruction::GetProperty(_)
+                | Instruction::SetProperty(_)
+                | Instruction::Method(_)
+                | Instruction::Invoke(_, _)
+                | Instruction::Inherit
+                | Instruction::GetSuper(_)
+                | Instruction::SuperInvoke(_, _)
+                | Instruction::PopN(_)
+                | Instruction::FloorDivide
+                | Instruction::NotEqual
+                | Instruction::GreaterEqual
+                | Instruction::LessEqual
+                | Instruction::ConstantSmall(_)
+                | Instruction::AssertEqual
+                | Instruction::SharedConstant(_, _)
+                | Instruction::BuildList(_)
+                | Instruction::GetIndex
+                | Instruction::SetIndex
+                | Instruction::Unpack(_) => {}
+            }
+        }
+        list
+    }
+
+    #[test]
+    fn instruction_round_trip() {
+        // two-byte jumps are stored as (high, low) pair
+        let operands = [(0, 0), (255, 255), (0, 255), (255, 0), (1, 128)];
+        for (a, b) in operands {
+            for instr in all_instructions(a, b) {
+                let buffer = instr.as_vec();
+                let mut offset = 0;
+                let fetched = Instruction::fetch(&buffer, &mut offset);
+                assert_eq!(fetched.ok().as_ref(), Some(&instr));
+                assert_eq!(offset, buffer.len(), "{instr:?} size mismatch");
+            }
+        }
+    }
+
+    #[test]
+    fn every_opcode_round_trip() {
+        let mut opcodes = 0;
+        for opcode in 0..=u8::MAX {
+            let buffer = [opcode, 0xff, 0x00];
+            let mut offset = 0;
+            let Ok(instr) = Instruction::fetch(&buffer, &mut offset) else {
+                continue;
+            };
+            opcodes += 1;
+            assert_eq!(instr.as_vec(), buffer[..offset], "{instr:?}");
+        }
+        assert_eq!(opcodes, all_instructions(0, 0).len());
+    }
 }
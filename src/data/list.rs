@@ -0,0 +1,125 @@
+use std::{cell::RefCell, collections::HashSet, fmt::Display, rc::Rc};
+
+use crate::{Double, Value, data::text::check_arity};
+
+/// Ordered values created with `[a, b]`, lists are shared by reference like instances
+#[derive(Debug, Default)]
+pub struct List {
+    items: RefCell<Vec<Value>>,
+}
+
+impl List {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self {
+            items: RefCell::new(items),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.borrow().is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<Value> {
+        self.items.borrow().get(idx).cloned()
+    }
+
+    /// Returns false if `idx` is out of range
+    pub fn set(&self, idx: usize, value: Value) -> bool {
+        let mut items = self.items.borrow_mut();
+        let Some(item) = items.get_mut(idx) else {
+            return false;
+        };
+        *item = value;
+        true
+    }
+
+    pub fn push(&self, value: Value) {
+        self.items.borrow_mut().push(value);
+    }
+
+    /// Snapshot of the items
+    pub fn items(&self) -> Vec<Value> {
+        self.items.borrow().clone()
+    }
+
+    /// `visited` holds lists being printed up the tree, so a list that contains itself
+    /// is shown as `[...]` instead of recursing forever
+    fn write_items(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        visited: &mut HashSet<*const List>,
+    ) -> std::fmt::Result {
+        let ptr = self as *const List;
+        if !visited.insert(ptr) {
+            return write!(f, "[...]");
+        }
+        write!(f, "[")?;
+        for (i, value) in self.items().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match value {
+                Value::Text(text) => write!(f, "\"{text}\"")?,
+                Value::List(list) => list.write_items(f, visited)?,
+                _ => write!(f, "{value}")?,
+            }
+        }
+        visited.remove(&ptr);
+        write!(f, "]")
+    }
+}
+
+/// Items are separated by commas, texts are quoted, e.g. `[1, "a", nil]`
+impl Display for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_items(f, &mut HashSet::new())
+    }
+}
+
+pub type ListMethod = fn(&Rc<List>, &[Value]) -> Result<Value, String>;
+
+/// Built-in methods available on list values using dot syntax, e.g. `items.push(1)`
+pub fn list_method(name: &str) -> Option<ListMethod> {
+    let method: ListMethod = match name {
+        "length" => length,
+        "push" => push,
+        _ => return None,
+    };
+    Some(method)
+}
+
+fn length(list: &Rc<List>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    Ok(Value::number(list.len() as Double))
+}
+
+fn push(list: &Rc<List>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    list.push(args[0].clone());
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_display() {
+        let list = Rc::new(List::new(vec![
+            Value::number(1.0),
+            Value::text_from_str("a"),
+            Value::Nil,
+        ]));
+        assert_eq!(list.to_string(), "[1, \"a\", nil]");
+        assert_eq!(List::default().to_string(), "[]");
+        list.push(Value::List(list.clone()));
+        assert_eq!(list.to_string(), "[1, \"a\", nil, [...]]");
+        // break the cycle, so the list is freed
+        assert!(list.set(3, Value::Nil));
+        assert!(!list.set(4, Value::Nil));
+    }
+}
@@ -1,20 +1,34 @@
+mod bytecode;
 mod chunk;
 mod class;
+mod foreign;
 mod func;
+mod heap_stats;
 mod instruction;
+mod list;
+mod text;
 mod upvalue_data;
 mod value;
 
-pub use chunk::Chunk;
+pub use bytecode::{BYTECODE_VERSION, BytecodeError, deserialize_func, serialize_func};
+pub use chunk::{Chunk, ChunkError, SharedConstants};
 pub use class::*;
+pub use foreign::*;
 pub use func::*;
+pub use heap_stats::{HeapStats, heap_stats, set_heap_stats_enabled};
 pub use instruction::*;
+pub use list::*;
+pub use text::*;
 pub use upvalue_data::*;
-pub use value::{OperationError, Value, ValueOperation};
+pub use value::{Double, OperationError, Value, ValueOperation};
 
 pub const UINT8_COUNT: usize = 256;
 pub const MAX_FUNCTION_ARGUMENTS: usize = 255;
 pub const INITIALIZER_METHOD_NAME: &str = "init";
+/// Method called by `print` to convert an instance to a printable value
+pub const TO_STRING_METHOD_NAME: &str = "toString";
+/// Method consulted by conditions and logical operators to get truthiness of an instance
+pub const BOOL_METHOD_NAME: &str = "__bool";
 
 fn consume_byte(buffer: &[u8], offset: &mut usize) -> Option<u8> {
     let byte = buffer.get(*offset)?;
@@ -0,0 +1,124 @@
+use std::rc::Rc;
+
+use crate::Value;
+
+pub type TextMethod = fn(&Rc<String>, &[Value]) -> Result<Value, String>;
+
+/// Built-in methods available on string values using dot syntax, e.g. `"abc".upper()`
+pub fn text_method(name: &str) -> Option<TextMethod> {
+    let method: TextMethod = match name {
+        "upper" => upper,
+        "lower" => lower,
+        "trim" => trim,
+        "length" => length,
+        "contains" => contains,
+        "substring" => substring,
+        _ => return None,
+    };
+    Some(method)
+}
+
+fn upper(text: &Rc<String>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    Ok(Value::text_from_string(text.to_uppercase()))
+}
+
+fn lower(text: &Rc<String>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    Ok(Value::text_from_string(text.to_lowercase()))
+}
+
+fn trim(text: &Rc<String>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    Ok(Value::text_from_str(text.trim()))
+}
+
+fn length(text: &Rc<String>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 0)?;
+    Ok(Value::number(text.chars().count() as f32))
+}
+
+fn contains(text: &Rc<String>, args: &[Value]) -> Result<Value, String> {
+    check_arity(args, 1)?;
+    let Some(pattern) = args[0].as_text() else {
+        return Err("Argument must be a string".to_string());
+    };
+    Ok(Value::Bool(text.contains(pattern.as_str())))
+}
+
+/// `substring(start)` or `substring(start, end)`, indices are counted in characters
+fn substring(text: &Rc<String>, args: &[Value]) -> Result<Value, String> {
+    let len = text.chars().count();
+    let (start, end) = match args {
+        [start] => (as_index(start)?, len),
+        [start, end] => (as_index(start)?, as_index(end)?),
+        _ => return Err(format!("Expected 1 or 2 arguments but got {}", args.len())),
+    };
+    if start > end || end > len {
+        return Err("Substring range out of bounds".to_string());
+    }
+    let result = text
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .collect::<String>();
+    Ok(Value::text_from_string(result))
+}
+
+fn as_index(value: &Value) -> Result<usize, String> {
+    match value.as_number() {
+        Some(x) if x >= 0.0 && x.fract() == 0.0 => Ok(x as usize),
+        _ => Err("Index must be a non-negative integer".to_string()),
+    }
+}
+
+pub(crate) fn check_arity(args: &[Value], arity: usize) -> Result<(), String> {
+    if args.len() != arity {
+        return Err(format!(
+            "Expected {} arguments but got {}",
+            arity,
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, text: &str, args: &[Value]) -> Result<Value, String> {
+        let method = text_method(name).expect("Unknown method");
+        method(&Rc::new(text.to_string()), args)
+    }
+
+    #[test]
+    fn substring_ranges() {
+        let num = Value::number;
+        assert_eq!(
+            call("substring", "hello", &[num(1.0)]),
+            Ok(Value::text_from_str("ello"))
+        );
+        assert_eq!(
+            call("substring", "hello", &[num(1.0), num(3.0)]),
+            Ok(Value::text_from_str("el"))
+        );
+        assert_eq!(
+            call("substring", "hello", &[num(5.0)]),
+            Ok(Value::text_from_str(""))
+        );
+        assert!(call("substring", "hello", &[num(3.0), num(1.0)]).is_err());
+        assert!(call("substring", "hello", &[num(1.5)]).is_err());
+        assert!(call("substring", "hello", &[num(0.0), num(6.0)]).is_err());
+    }
+
+    #[test]
+    fn trim_and_lower() {
+        assert_eq!(call("trim", "  Hi \n", &[]), Ok(Value::text_from_str("Hi")));
+        assert_eq!(
+            call("lower", "HeLLo", &[]),
+            Ok(Value::text_from_str("hello"))
+        );
+        assert!(call("lower", "HeLLo", &[Value::Nil]).is_err());
+    }
+}
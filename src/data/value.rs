@@ -1,11 +1,20 @@
-use std::{fmt::Display, num::ParseFloatError, rc::Rc};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    num::ParseFloatError,
+    rc::{Rc, Weak},
+};
 
-use crate::{BoundMethod, Class, Closure, Func, Instance, NativeFn, NativeFunc};
+use crate::{
+    BoundMethod, Class, Closure, ForeignClass, ForeignInstance, Func, HostFn, Instance, List,
+    NativeFn, NativeFunc,
+};
 
 pub type Double = f32;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum Value {
+    #[default]
     Nil,
     Number(Double),
     Bool(bool),
@@ -16,13 +25,13 @@ pub enum Value {
     Class(Rc<Class>),
     Instance(Rc<Instance>),
     BoundMethod(Rc<BoundMethod>),
+    ForeignClass(Rc<ForeignClass>),
+    ForeignInstance(Rc<ForeignInstance>),
+    /// Back-reference that doesn't keep the instance alive, so it doesn't form a cycle
+    Weak(Weak<Instance>),
+    List(Rc<List>),
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Self::Nil
-    }
-}
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -35,24 +44,47 @@ impl PartialEq for Value {
             (Self::Closure(l), Self::Closure(r)) => Rc::ptr_eq(l, r),
             (Self::Class(l), Self::Class(r)) => Rc::ptr_eq(l, r),
             (Self::BoundMethod(l), Self::BoundMethod(r)) => Rc::ptr_eq(l, r),
+            (Self::Instance(l), Self::Instance(r)) => Rc::ptr_eq(l, r),
+            (Self::ForeignClass(l), Self::ForeignClass(r)) => Rc::ptr_eq(l, r),
+            (Self::ForeignInstance(l), Self::ForeignInstance(r)) => Rc::ptr_eq(l, r),
+            (Self::Weak(l), Self::Weak(r)) => Weak::ptr_eq(l, r),
+            (Self::List(l), Self::List(r)) => Rc::ptr_eq(l, r),
             _ => false,
         }
     }
 }
 
+impl Value {
+    /// Equality for map keys: unlike `==`, which follows IEEE, `NaN` equals itself.
+    /// `-0.0` and `0.0` are equal in both modes
+    pub fn total_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Number(l), Self::Number(r)) => l == r || (l.is_nan() && r.is_nan()),
+            _ => self == other,
+        }
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Bool(val) => write!(f, "{val}"),
-            Value::Number(val) => write!(f, "{val}"),
+            Value::Number(val) => write!(f, "{}", format_number(*val)),
             Value::Text(val) => write!(f, "{val}"),
             Value::Fun(val) => write!(f, "{val}"),
             Value::NativeFun(val) => write!(f, "{val}"),
             Value::Closure(val) => write!(f, "{val}"),
             Value::Class(val) => write!(f, "{val}"),
-            Value::Instance(val) => write!(f, "{val}"),
+            Value::Instance(val) => Display::fmt(val, f),
             Value::BoundMethod(val) => write!(f, "{val}"),
+            Value::ForeignClass(val) => write!(f, "{val}"),
+            Value::ForeignInstance(val) => write!(f, "{val}"),
+            Value::Weak(val) => match val.upgrade() {
+                Some(instance) => write!(f, "<weak {instance}>"),
+                None => write!(f, "<weak released>"),
+            },
+            Value::List(val) => write!(f, "{val}"),
         }
     }
 }
@@ -67,6 +99,10 @@ impl Value {
         Value::NativeFun(Rc::new(NativeFunc::with(func)))
     }
 
+    pub fn host_func(func: HostFn) -> Self {
+        Value::NativeFun(Rc::new(NativeFunc::with_host(func)))
+    }
+
     pub fn text_from_str(value: &str) -> Self {
         Self::Text(Rc::new(value.to_string()))
     }
@@ -79,8 +115,9 @@ impl Value {
         Self::Number(value)
     }
 
+    /// Digit separators are ignored, e.g. `1_000`
     pub fn number_from(s: &str) -> Result<Self, ParseFloatError> {
-        let value = s.parse::<Double>()?;
+        let value = s.replace('_', "").parse::<Double>()?;
         Ok(Self::number(value))
     }
 
@@ -91,6 +128,28 @@ impl Value {
         }
     }
 
+    /// Name of the value type for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::Text(_) => "string",
+            Value::Fun(_) | Value::Closure(_) => "function",
+            Value::NativeFun(_) => "native function",
+            Value::Class(_) | Value::ForeignClass(_) => "class",
+            Value::Instance(_) | Value::ForeignInstance(_) => "instance",
+            Value::BoundMethod(_) => "method",
+            Value::Weak(_) => "weak reference",
+            Value::List(_) => "list",
+        }
+    }
+
+    /// Finite number without fractional part, e.g. `4.0`
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Value::Number(x) if x.is_finite() && x.fract() == 0.0)
+    }
+
     pub fn as_function(&self) -> Option<Rc<Func>> {
         match self {
             Value::Fun(func_ref) => Some(func_ref.clone()),
@@ -127,6 +186,17 @@ impl Value {
         }
     }
 
+    pub fn list(items: Vec<Value>) -> Self {
+        Self::List(Rc::new(List::new(items)))
+    }
+
+    pub fn as_list(&self) -> Option<Rc<List>> {
+        match self {
+            Value::List(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
     pub fn as_closure(&self) -> Option<Rc<Closure>> {
         match self {
             Value::Closure(value) => Some(value.clone()),
@@ -135,10 +205,51 @@ impl Value {
     }
 }
 
+/// Formats number the same way as C's `printf("%g", value)` does (clox compatibility)
+fn format_number(value: Double) -> String {
+    const PRECISION: i32 = 6;
+    let value = value as f64;
+    if value.is_nan() {
+        return "nan".to_string();
+    }
+    if value.is_infinite() {
+        let sign = if value.is_sign_negative() { "-" } else { "" };
+        return format!("{sign}inf");
+    }
+    if value == 0.0 {
+        let sign = if value.is_sign_negative() { "-" } else { "" };
+        return format!("{sign}0");
+    }
+    // exponent is taken after rounding to the significant digits
+    let scientific = format!("{:.*e}", (PRECISION - 1) as usize, value);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("Bug: scientific notation without exponent");
+    let exponent = exponent
+        .parse::<i32>()
+        .expect("Bug: invalid exponent in scientific notation");
+
+    if !(-4..PRECISION).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        let mantissa = trim_fraction_zeros(mantissa);
+        return format!("{mantissa}e{sign}{:02}", exponent.abs());
+    }
+    let decimals = (PRECISION - 1 - exponent) as usize;
+    trim_fraction_zeros(&format!("{value:.decimals$}")).to_string()
+}
+
+fn trim_fraction_zeros(text: &str) -> &str {
+    if !text.contains('.') {
+        return text;
+    }
+    text.trim_end_matches('0').trim_end_matches('.')
+}
+
 #[derive(Debug, PartialEq)]
 pub enum OperationError {
     TypeMismatch,
     DivisionByZero,
+    TextTooLong,
 }
 
 pub type ValueOperation = fn(&Value, &Value) -> Result<Value, OperationError>;
@@ -159,6 +270,16 @@ impl Value {
         }
     }
 
+    /// `add` without coercion: only adds two numbers or concatenates two texts
+    pub fn add_strict(a: &Value, b: &Value) -> Result<Value, OperationError> {
+        match (a, b) {
+            (Value::Number(_), Value::Number(_)) | (Value::Text(_), Value::Text(_)) => {
+                Value::add(a, b)
+            }
+            _ => Err(OperationError::TypeMismatch),
+        }
+    }
+
     pub fn subtract(a: &Value, b: &Value) -> Result<Value, OperationError> {
         match (a, b) {
             (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x - y)),
@@ -166,9 +287,12 @@ impl Value {
         }
     }
 
+    /// Text multiplied by a non-negative integer is repeated, e.g. `"ab" * 2` is `"abab"`
     pub fn multiply(a: &Value, b: &Value) -> Result<Value, OperationError> {
         match (a, b) {
             (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x * y)),
+            (Value::Text(text), Value::Number(count))
+            | (Value::Number(count), Value::Text(text)) => repeat_text(text, *count),
             _ => Err(OperationError::TypeMismatch),
         }
     }
@@ -183,6 +307,13 @@ impl Value {
         }
     }
 
+    pub fn floor_divide(a: &Value, b: &Value) -> Result<Value, OperationError> {
+        match Self::divide(a, b)? {
+            Value::Number(x) => Ok(Value::Number(x.floor())),
+            _ => Err(OperationError::TypeMismatch),
+        }
+    }
+
     pub fn equals(a: &Value, b: &Value) -> Result<Value, OperationError> {
         Ok(Value::Bool(a == b))
     }
@@ -200,12 +331,92 @@ impl Value {
             _ => Err(OperationError::TypeMismatch),
         }
     }
+
+    pub fn not_equals(a: &Value, b: &Value) -> Result<Value, OperationError> {
+        Ok(Value::Bool(a != b))
+    }
+
+    /// Negated `less`, so any comparison with `NaN` is true
+    pub fn greater_equal(a: &Value, b: &Value) -> Result<Value, OperationError> {
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => {
+                Ok(Value::Bool(x.partial_cmp(y) != Some(Ordering::Less)))
+            }
+            _ => Err(OperationError::TypeMismatch),
+        }
+    }
+
+    /// Negated `greater`, so any comparison with `NaN` is true
+    pub fn less_equal(a: &Value, b: &Value) -> Result<Value, OperationError> {
+        match (a, b) {
+            (Value::Number(x), Value::Number(y)) => {
+                Ok(Value::Bool(x.partial_cmp(y) != Some(Ordering::Greater)))
+            }
+            _ => Err(OperationError::TypeMismatch),
+        }
+    }
+}
+
+/// Longest text in bytes that repeating may produce, guards against aborting on allocation
+const MAX_REPEATED_TEXT_LENGTH: usize = 1 << 30;
+
+impl Value {
+    /// Length in bytes of `text * count` or `count * text`, None if operands aren't a text
+    /// and a non-negative integer. The result may exceed the text length limit
+    pub(crate) fn repeated_text_length(a: &Value, b: &Value) -> Option<usize> {
+        let (text, count) = match (a, b) {
+            (Value::Text(text), Value::Number(count))
+            | (Value::Number(count), Value::Text(text)) => (text, count),
+            _ => return None,
+        };
+        if *count < 0.0 || count.fract() != 0.0 {
+            return None;
+        }
+        Some(text.len().saturating_mul(*count as usize))
+    }
+}
+
+fn repeat_text(text: &str, count: Double) -> Result<Value, OperationError> {
+    if count < 0.0 || count.fract() != 0.0 {
+        return Err(OperationError::TypeMismatch);
+    }
+    let count = count as usize;
+    match text.len().checked_mul(count) {
+        Some(length) if length <= MAX_REPEATED_TEXT_LENGTH => {
+            Ok(Value::text_from_string(text.repeat(count)))
+        }
+        _ => Err(OperationError::TextTooLong),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn add_strict_requires_same_types() {
+        let one = Value::number(1.0);
+        let text = Value::text_from_str("x");
+        assert_eq!(Value::add(&one, &text), Ok(Value::text_from_str("1x")));
+        assert_eq!(
+            Value::add(&Value::Nil, &text),
+            Ok(Value::text_from_str("nilx"))
+        );
+        assert_eq!(Value::add_strict(&one, &one), Ok(Value::number(2.0)));
+        assert_eq!(
+            Value::add_strict(&text, &text),
+            Ok(Value::text_from_str("xx"))
+        );
+        assert_eq!(
+            Value::add_strict(&one, &text),
+            Err(OperationError::TypeMismatch)
+        );
+        assert_eq!(
+            Value::add_strict(&text, &Value::Nil),
+            Err(OperationError::TypeMismatch)
+        );
+    }
+
     #[test]
     fn equality_text() {
         let text = "abc";
@@ -218,4 +429,92 @@ mod test {
         assert_eq!(Value::equals(&a, &b), Ok(Value::Bool(true)));
         assert_eq!(Value::equals(&a, &c), Ok(Value::Bool(false)));
     }
+
+    #[test]
+    fn combined_comparisons_match_negated() {
+        let numbers = [-1.0, 0.0, 2.5, Double::NAN];
+        for x in numbers {
+            for y in numbers {
+                let (a, b) = (Value::number(x), Value::number(y));
+                let negated = |op: ValueOperation| op(&a, &b).map(|v| Value::Bool(!v.as_bool()));
+                assert_eq!(Value::not_equals(&a, &b), negated(Value::equals));
+                assert_eq!(Value::greater_equal(&a, &b), negated(Value::less));
+                assert_eq!(Value::less_equal(&a, &b), negated(Value::greater));
+            }
+        }
+    }
+
+    #[test]
+    fn text_repeat() {
+        let text = Value::text_from_str("ab");
+        let repeat = |a: &Value, b: &Value| Value::multiply(a, b);
+        assert_eq!(
+            repeat(&text, &Value::number(3.0)),
+            Ok(Value::text_from_str("ababab"))
+        );
+        assert_eq!(
+            repeat(&Value::number(2.0), &text),
+            Ok(Value::text_from_str("abab"))
+        );
+        assert_eq!(
+            repeat(&text, &Value::number(0.0)),
+            Ok(Value::text_from_str(""))
+        );
+        for count in [-1.0, 1.5, Double::NAN] {
+            assert_eq!(
+                repeat(&text, &Value::number(count)),
+                Err(OperationError::TypeMismatch)
+            );
+        }
+        assert_eq!(repeat(&text, &text), Err(OperationError::TypeMismatch));
+        assert_eq!(
+            repeat(&text, &Value::number(1e14)),
+            Err(OperationError::TextTooLong)
+        );
+        assert_eq!(
+            Value::repeated_text_length(&Value::number(1e6), &text),
+            Some(2_000_000)
+        );
+    }
+
+    #[test]
+    fn number_format_matches_clox() {
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (1.5, "1.5"),
+            (-2.5, "-2.5"),
+            (100.0, "100"),
+            (123456.0, "123456"),
+            (1234567.0, "1.23457e+06"),
+            (100000000.0, "1e+08"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (0.0000001, "1e-07"),
+            (1.0 / 3.0, "0.333333"),
+            (999999.5, "1e+06"),
+            (Double::INFINITY, "inf"),
+            (Double::NEG_INFINITY, "-inf"),
+            (Double::NAN, "nan"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(Value::number(value).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn nan_equality() {
+        let nan = Value::Number(Double::NAN);
+        assert_ne!(nan, nan.clone());
+        assert!(nan.total_eq(&nan.clone()));
+        assert!(!nan.total_eq(&Value::Number(1.0)));
+    }
+
+    #[test]
+    fn signed_zero_equality() {
+        let zero = Value::Number(0.0);
+        let neg_zero = Value::Number(-0.0);
+        assert_eq!(zero, neg_zero);
+        assert!(zero.total_eq(&neg_zero));
+    }
 }
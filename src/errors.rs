@@ -2,14 +2,35 @@ use std::rc::Rc;
 
 use crate::frontend::Token;
 
+/// Category of a compile error for programmatic consumers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompileErrorKind {
+    /// Malformed or otherwise invalid code
+    #[default]
+    Syntax,
+    TooManyConstants,
+    TooManyLocals,
+    TooManyUpvalues,
+    TooManyParameters,
+    TooManyArguments,
+    JumpTooLarge,
+    /// Bug of the compiler itself
+    Internal,
+}
+
 #[derive(Debug, Clone)]
 pub struct ErrorInfo {
     position: Option<CodePosition>,
     message: String,
+    kind: CompileErrorKind,
 }
 
 impl ErrorInfo {
     pub fn with(token: Token, message: &str) -> Self {
+        Self::with_kind(token, message, CompileErrorKind::Syntax)
+    }
+
+    pub fn with_kind(token: Token, message: &str, kind: CompileErrorKind) -> Self {
         let append = |arr: &mut Vec<String>, value: String| {
             if value.is_empty() {
                 return;
@@ -28,12 +49,34 @@ impl ErrorInfo {
         Self {
             position: Some(token.position),
             message: text,
+            kind,
         }
     }
 
     pub fn message(&self) -> &str {
         self.message.as_str()
     }
+
+    pub fn kind(&self) -> CompileErrorKind {
+        self.kind
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.position.as_ref().map(|p| p.line)
+    }
+
+    fn sort_key(&self) -> (usize, usize) {
+        self.position
+            .as_ref()
+            .map(|p| (p.line, p.absolute_index))
+            .unwrap_or((usize::MAX, usize::MAX))
+    }
+}
+
+/// Orders errors by their position in the code, errors without position go last.
+/// Sorting is stable, so errors at the same position keep the discovery order
+pub fn sort_errors(errors: &mut [ErrorInfo]) {
+    errors.sort_by_key(|err| err.sort_key());
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -42,13 +85,73 @@ pub struct CodePosition {
     pub absolute_index: usize,
 }
 
+/// Compile error with separate position fields for tools like IDEs.
+/// Lines and columns start from 1, position is `None` if the error isn't bound to the code
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    pub kind: CompileErrorKind,
+    absolute_index: Option<usize>,
+}
+
+impl CompileError {
+    pub fn with_info(info: &ErrorInfo, code: &[char]) -> Self {
+        let column = info.position.as_ref().map(|p| {
+            let index = p.absolute_index.min(code.len());
+            let line_start = code[..index]
+                .iter()
+                .rposition(|ch| *ch == '\n')
+                .map(|pos| pos + 1)
+                .unwrap_or(0);
+            index - line_start + 1
+        });
+        Self {
+            line: info.line(),
+            column,
+            message: info.message.clone(),
+            kind: info.kind,
+            absolute_index: info.position.as_ref().map(|p| p.absolute_index),
+        }
+    }
+}
+
+impl From<CompileError> for ErrorInfo {
+    fn from(error: CompileError) -> Self {
+        let position = error
+            .line
+            .zip(error.absolute_index)
+            .map(|(line, absolute_index)| CodePosition {
+                line,
+                absolute_index,
+            });
+        Self {
+            position,
+            message: error.message,
+            kind: error.kind,
+        }
+    }
+}
+
+const DEFAULT_TAB_WIDTH: usize = 4;
+
 pub struct ErrorFormatter {
     code: Rc<Vec<char>>,
+    tab_width: usize,
 }
 
 impl ErrorFormatter {
     pub fn with(code: Rc<Vec<char>>) -> Self {
-        Self { code }
+        Self {
+            code,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// Tabs of the displayed line are expanded to this width, so the caret stays aligned
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width.max(1);
     }
 
     pub fn format_error(&self, info: &ErrorInfo) -> String {
@@ -75,12 +178,16 @@ impl ErrorFormatter {
     }
 
     fn extract_line(&self, position: &CodePosition) -> (usize, String) {
-        let mut left = position.absolute_index;
+        let len = self.code.len();
+        if len == 0 {
+            return (0, String::new());
+        }
+        let index = position.absolute_index.min(len - 1);
+        let mut left = index;
         let mut right = left;
 
         let is_terminator = |ch: char| -> bool { ch == '\n' || ch == '\r' };
 
-        let len = self.code.len();
         let mut is_moving = true;
         while is_moving {
             is_moving = false;
@@ -95,10 +202,28 @@ impl ErrorFormatter {
             }
         }
 
-        (
-            position.absolute_index - left,
-            self.code[left..=right].iter().collect::<String>(),
-        )
+        self.expand_tabs(&self.code[left..=right], index - left)
+    }
+
+    /// Returns the column of the character at `offset` along with the expanded line
+    fn expand_tabs(&self, line: &[char], offset: usize) -> (usize, String) {
+        let mut column = 0;
+        let mut expanded = String::new();
+        for (i, ch) in line.iter().enumerate() {
+            if i == offset {
+                column = expanded.chars().count();
+            }
+            if *ch == '\t' {
+                let width = self.tab_width - expanded.chars().count() % self.tab_width;
+                expanded.push_str(&" ".repeat(width));
+            } else {
+                expanded.push(*ch);
+            }
+        }
+        if offset >= line.len() {
+            column = expanded.chars().count();
+        }
+        (column, expanded)
     }
 }
 
@@ -111,6 +236,7 @@ mod test {
             Self {
                 position: None,
                 message: m.to_string(),
+                kind: CompileErrorKind::Syntax,
             }
         }
 
@@ -118,15 +244,14 @@ mod test {
             Self {
                 position: Some(p),
                 message: m.to_string(),
+                kind: CompileErrorKind::Syntax,
             }
         }
     }
 
     fn formatter_with_code(source: &str) -> ErrorFormatter {
         let code: Vec<char> = source.chars().collect();
-        ErrorFormatter {
-            code: Rc::new(code),
-        }
+        ErrorFormatter::with(Rc::new(code))
     }
 
     #[test]
@@ -150,4 +275,84 @@ mod test {
         assert!(!output.contains("2nd line"));
         assert!(output.ends_with("Message"))
     }
+
+    #[test]
+    fn format_error_tab_indented_line() {
+        let mut formatter = formatter_with_code("var a;\n\tif\t(a) b;");
+        let pos = CodePosition {
+            line: 2,
+            absolute_index: 11,
+        };
+        let info = ErrorInfo::new(pos, "Message");
+        let lines = formatter.format_error(&info);
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "2 |    if  (a) b;");
+        assert_eq!(lines[1], "           ▲");
+
+        formatter.set_tab_width(2);
+        let lines = formatter.format_error(&info);
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "2 |  if  (a) b;");
+        assert_eq!(lines[1], "         ▲");
+    }
+
+    #[test]
+    fn sort_errors_by_position() {
+        let pos = |line, absolute_index| CodePosition {
+            line,
+            absolute_index,
+        };
+        let mut errors = vec![
+            ErrorInfo::new(pos(3, 40), "third"),
+            ErrorInfo::with_message("no position"),
+            ErrorInfo::new(pos(1, 7), "second"),
+            ErrorInfo::new(pos(1, 2), "first"),
+            ErrorInfo::new(pos(3, 40), "third duplicate"),
+        ];
+        sort_errors(&mut errors);
+        let messages = errors.iter().map(|x| x.message()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec!["first", "second", "third", "third duplicate", "no position"]
+        );
+    }
+
+    #[test]
+    fn compile_error_conversion() {
+        let code = "var a = 1;\nvar b = ;".chars().collect::<Vec<_>>();
+        let pos = CodePosition {
+            line: 2,
+            absolute_index: 19,
+        };
+        let info = ErrorInfo::new(pos.clone(), "Expect expression");
+        let error = CompileError::with_info(&info, &code);
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.column, Some(9));
+        assert_eq!(error.kind, CompileErrorKind::Syntax);
+
+        let info = ErrorInfo::from(error);
+        assert_eq!(info.position, Some(pos));
+        assert_eq!(info.message(), "Expect expression");
+    }
+
+    #[test]
+    fn format_error_position_out_of_code() {
+        let formatter = formatter_with_code("fun f() {");
+        let pos = CodePosition {
+            line: 1,
+            absolute_index: 9,
+        };
+        let info = ErrorInfo::new(pos, "Message");
+        let output = formatter.format_error(&info);
+        assert!(output.starts_with("1 |fun f() {"));
+
+        let formatter = formatter_with_code("");
+        let pos = CodePosition {
+            line: 1,
+            absolute_index: 0,
+        };
+        let info = ErrorInfo::new(pos, "Message");
+        let output = formatter.format_error(&info);
+        assert!(output.ends_with("Message"));
+    }
 }
@@ -1,14 +1,18 @@
-use std::rc::Rc;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
-    ErrorInfo, Func, FuncType, INITIALIZER_METHOD_NAME, Instruction, MAX_FUNCTION_ARGUMENTS, Value,
+    CompileErrorKind, Double, ErrorInfo, Func, FuncType, INITIALIZER_METHOD_NAME, Instruction,
+    MAX_FUNCTION_ARGUMENTS, SharedConstants, Value,
     frontend::{
-        Token, TokenType,
+        CompileOptions, Token, TokenType,
         compiler::{Compiler, Local},
         rule::Precedence,
         scanner::TokenSource,
     },
-    utils::word_to_bytes,
+    utils::{bytes_to_word, word_to_bytes},
 };
 
 type ParseRule = super::rule::ParseRule<Assembler>;
@@ -21,29 +25,50 @@ struct ClassCompiler {
 pub struct Assembler {
     current: Token,
     previous: Token,
+    next: Option<Token>,
     compiler: Option<Box<Compiler>>,
     scanner: Box<dyn TokenSource>,
     panic_mode: bool,
     errors: Vec<ErrorInfo>,
+    warnings: Vec<ErrorInfo>,
     loop_stack: Vec<LoopData>,
     class_compilers: Vec<ClassCompiler>,
+    /// Globals declared so far, used for shadowing diagnostics
+    global_names: HashSet<String>,
+    /// First tokens of the statements being compiled, innermost last
+    statement_starts: Vec<Token>,
+    /// Text constants of the compilation unit, chunks refer to a single copy of each string
+    text_pool: HashMap<String, Rc<String>>,
+    /// String literals pool of the compilation unit if `CompileOptions::shared_constants` is set
+    shared_constants: Option<Rc<SharedConstants>>,
+    shared_indices: HashMap<String, usize>,
+    options: CompileOptions,
 }
 
 impl Assembler {
-    pub fn new(scanner: Box<dyn TokenSource>) -> Self {
+    pub fn new(scanner: Box<dyn TokenSource>, options: CompileOptions) -> Self {
         Self {
             current: Token::undefined(),
             previous: Token::undefined(),
+            next: None,
             compiler: None,
             scanner,
             panic_mode: false,
             errors: Vec::new(),
+            warnings: Vec::new(),
             loop_stack: Vec::new(),
             class_compilers: Vec::new(),
+            global_names: HashSet::new(),
+            statement_starts: Vec::new(),
+            text_pool: HashMap::new(),
+            shared_constants: options.shared_constants.then(Default::default),
+            shared_indices: HashMap::new(),
+            options,
         }
     }
 
-    pub fn compile(mut self) -> Result<Func, Vec<ErrorInfo>> {
+    /// Returns compiled script or errors, warnings are reported in both cases
+    pub fn compile(mut self) -> (Result<Func, Vec<ErrorInfo>>, Vec<ErrorInfo>) {
         self.init_compiler(FuncType::Script);
         self.advance();
         while !self.is_match(TokenType::Eof) {
@@ -52,17 +77,20 @@ impl Assembler {
         let func = self.end_compiler().function_consumed();
 
         if !self.errors.is_empty() {
-            return Err(self.errors);
+            return (Err(self.errors), self.warnings);
         }
 
-        Ok(func)
+        (Ok(func), self.warnings)
     }
 
     fn advance(&mut self) {
         self.update_previous();
         let mut looping = true;
         while looping {
-            let token = self.scanner.scan_token();
+            let token = self
+                .next
+                .take()
+                .unwrap_or_else(|| self.scanner.scan_token());
             let is_err = token.is_err();
             self.set_current(token);
             if is_err {
@@ -84,6 +112,17 @@ impl Assembler {
         self.cur_token_type() == t_type
     }
 
+    /// Looks one token past the current one without consuming it.
+    /// Contextual keywords (e.g. `in`) are scanned as identifiers and remain valid variable names
+    fn check_next_keyword(&mut self, keyword: &str) -> bool {
+        let token = self.peek_next();
+        token.t_type == TokenType::Identifier && token.text == keyword
+    }
+
+    fn peek_next(&mut self) -> &Token {
+        self.next.get_or_insert_with(|| self.scanner.scan_token())
+    }
+
     fn declaration(&mut self) {
         if self.is_match(TokenType::Class) {
             self.class_declaration();
@@ -113,6 +152,7 @@ impl Assembler {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
+                | TokenType::AssertEq
                 | TokenType::Return => return,
                 _ => {}
             }
@@ -125,10 +165,36 @@ impl Assembler {
             self.advance();
             return;
         };
-
+        if t_type == TokenType::Semicolon && self.is_statement_boundary() {
+            // parse further as if the semicolon were present, so the next statement isn't skipped
+            self.recoverable_error(message.as_ref());
+            return;
+        }
         self.error_at_current(message.as_ref());
     }
 
+    /// Current token can't continue the previous statement, e.g. it's a keyword or on the next line
+    fn is_statement_boundary(&self) -> bool {
+        use TokenType::*;
+        matches!(
+            self.cur_token_type(),
+            Class
+                | Fun
+                | Var
+                | For
+                | If
+                | While
+                | Print
+                | AssertEq
+                | Return
+                | Break
+                | Continue
+                | Switch
+                | RightBrace
+                | Eof
+        ) || self.current.position.line > self.previous.position.line
+    }
+
     fn end_compiler(&mut self) -> Compiler {
         self.emit_return();
         let Some(mut compiler) = self.compiler.take() else {
@@ -146,6 +212,12 @@ impl Assembler {
         if !matches!(func_type, FuncType::Script) {
             compiler.assign_name(self.prev_token_name());
         }
+        if self.options.debug_info {
+            compiler.enable_debug_info();
+        }
+        if let Some(pool) = &self.shared_constants {
+            compiler.chunk_mut().set_shared_constants(pool.clone());
+        }
         self.compiler = Some(Box::new(compiler));
     }
 
@@ -165,9 +237,15 @@ impl Assembler {
             loop {
                 self.compiler_mut().function_mut().arity += 1;
                 if self.compiler().function().arity > MAX_FUNCTION_ARGUMENTS {
-                    self.error_at_current("Can't have more than 255 parameters");
+                    self.push_error_info(
+                        self.current.clone(),
+                        "Can't have more than 255 parameters",
+                        CompileErrorKind::TooManyParameters,
+                    );
                 }
                 let constant = self.parse_variable("Expect parameter name");
+                let name = self.prev_token_name();
+                self.compiler_mut().function_mut().params.push(name);
                 self.define_variable(constant);
 
                 if !self.is_match(TokenType::Comma) {
@@ -233,9 +311,12 @@ impl Assembler {
             LeftParenthesis => {
                 ParseRule::new(Some(Self::grouping), Some(Self::call), Precedence::Call)
             }
+            LeftBracket => ParseRule::new(Some(Self::list), Some(Self::index), Precedence::Call),
             Minus => ParseRule::new(Some(Self::unary), Some(Self::binary), Precedence::Term),
             Plus => ParseRule::new(None, Some(Self::binary), Precedence::Term),
-            Slash | Star => ParseRule::new(None, Some(Self::binary), Precedence::Factor),
+            Slash | TildeSlash | Star => {
+                ParseRule::new(None, Some(Self::binary), Precedence::Factor)
+            }
             Number => ParseRule::new(Some(Self::number), None, Precedence::None),
             Nil | False | True => ParseRule::new(Some(Self::literal), None, Precedence::None),
             Bang => ParseRule::new(Some(Self::unary), None, Precedence::None),
@@ -250,8 +331,10 @@ impl Assembler {
             And => ParseRule::new(None, Some(Self::and), Precedence::And),
             Or => ParseRule::new(None, Some(Self::or), Precedence::Or),
             Dot => ParseRule::new(None, Some(Self::dot), Precedence::Call),
+            QuestionDot => ParseRule::new(None, Some(Self::safe_dot), Precedence::Call),
             This => ParseRule::new(Some(Self::this), None, Precedence::None),
             Super => ParseRule::new(Some(Self::super_keyword), None, Precedence::None),
+            While | For => ParseRule::new(Some(Self::misplaced_loop), None, Precedence::None),
             _ => Default::default(),
         }
     }
@@ -272,9 +355,15 @@ impl Assembler {
         let mut arg_count = 0;
         if !self.check(TokenType::RightParenthesis) {
             loop {
+                // point to the start of the extra argument, not to its last token
+                let argument = self.current.clone();
                 self.expression();
                 if arg_count == MAX_FUNCTION_ARGUMENTS {
-                    self.error("Can't have more than 255 arguments");
+                    self.push_error_info(
+                        argument,
+                        "Can't have more than 255 arguments",
+                        CompileErrorKind::TooManyArguments,
+                    );
                 }
                 arg_count += 1;
                 if !self.is_match(TokenType::Comma) {
@@ -286,22 +375,53 @@ impl Assembler {
         arg_count
     }
 
+    /// List literal, e.g. `[1, "a", nil]`
+    fn list(&mut self, _can_assign: bool) {
+        let mut count = 0;
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                self.expression();
+                if count == u8::MAX as usize {
+                    self.error("Can't have more than 255 items in a list literal");
+                }
+                count += 1;
+                if !self.is_match(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after list items");
+        self.emit_instruction(&Instruction::BuildList(count as u8));
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RightBracket, "Expect ']' after index");
+        if can_assign && self.is_match(TokenType::Equal) {
+            self.expression();
+            self.emit_instruction(&Instruction::SetIndex);
+        } else {
+            self.emit_instruction(&Instruction::GetIndex);
+        }
+    }
+
     fn binary(&mut self, _can_assign: bool) {
         let operator_type = self.prev_token_type();
         let rule = self.get_rule(operator_type);
         self.parse_precedence(rule.precedence.increased());
 
         let array: &[Instruction] = match operator_type {
-            TokenType::BangEqual => &[Instruction::Equal, Instruction::Not],
+            TokenType::BangEqual => &[Instruction::NotEqual],
             TokenType::EqualEqual => &[Instruction::Equal],
             TokenType::Greater => &[Instruction::Greater],
-            TokenType::GreaterEqual => &[Instruction::Less, Instruction::Not],
+            TokenType::GreaterEqual => &[Instruction::GreaterEqual],
             TokenType::Less => &[Instruction::Less],
-            TokenType::LessEqual => &[Instruction::Greater, Instruction::Not],
+            TokenType::LessEqual => &[Instruction::LessEqual],
             TokenType::Plus => &[Instruction::Add],
             TokenType::Minus => &[Instruction::Subtract],
             TokenType::Star => &[Instruction::Multiply],
             TokenType::Slash => &[Instruction::Divide],
+            TokenType::TildeSlash => &[Instruction::FloorDivide],
             x => unreachable!("Unexpected binary operator {x:?}"),
         };
         self.emit_instructions(array);
@@ -322,6 +442,27 @@ impl Assembler {
         }
     }
 
+    /// `a?.b` is nil if `a` is nil, otherwise the same as `a.b`.
+    /// The rest of the chain (`a?.b.c()`) is skipped when `a` is nil, it can't be assigned
+    fn safe_dot(&mut self, _can_assign: bool) {
+        self.emit_instructions(&[Instruction::Duplicate, Instruction::Nil, Instruction::Equal]);
+        let access_jump = self.emit_instruction(&Instruction::stub_jump_if_false());
+        self.emit_instruction(&Instruction::Pop);
+        let end_jump = self.emit_instruction(&Instruction::stub_jump());
+        self.patch_jump(access_jump);
+        self.emit_instruction(&Instruction::Pop);
+        self.dot(false);
+        while Precedence::Call.le(&self.get_rule(self.cur_token_type()).precedence) {
+            self.advance();
+            let infix_rule = self
+                .get_rule(self.prev_token_type())
+                .infix
+                .expect("Infix is none");
+            infix_rule(self, false);
+        }
+        self.patch_jump(end_jump);
+    }
+
     fn grouping(&mut self, _can_assign: bool) {
         self.expression();
         self.consume(TokenType::RightParenthesis, "Expect ')' after expression");
@@ -336,11 +477,31 @@ impl Assembler {
         };
     }
 
+    fn misplaced_loop(&mut self, _can_assign: bool) {
+        // values of other expressions may be on the stack, so the loop has no fixed value slot
+        self.error("Loop expression is allowed as a variable initializer only");
+        // parse the loop anyway to continue after it, the code is dropped with the failed compilation
+        if self.prev_token_type() == TokenType::While {
+            self.while_statement(Some(0));
+        } else {
+            self.for_statement(Some(0));
+        }
+    }
+
     fn number(&mut self, _can_assign: bool) {
-        // I don't like this approach
-        // according to strtod it returns 0.0 as fallback
-        let text = &self.previous.text;
-        let value = Value::number_from(text).unwrap_or(Value::Number(0.0));
+        // the scanner produces valid lexemes only, so a parse failure is a bug rather than 0.0
+        let text = self.previous.text.clone();
+        let Ok(value) = Value::number_from(&text) else {
+            self.error_of_kind(
+                CompileErrorKind::Internal,
+                &format!("Bug: malformed number '{text}'"),
+            );
+            return;
+        };
+        if let Some(small) = value.as_number().and_then(small_integer) {
+            self.emit_instruction(&Instruction::ConstantSmall(small));
+            return;
+        }
         self.emit_constant(value);
     }
 
@@ -357,8 +518,12 @@ impl Assembler {
 
     fn string(&mut self, _can_assign: bool) {
         let s = &self.previous.text;
-        let text = &s[1..s.len() - 1];
-        self.emit_constant(Value::text_from_str(text));
+        let s = s.strip_prefix('r').unwrap_or(s);
+        let text = s[1..s.len() - 1].to_string();
+        match self.shared_constants.clone() {
+            Some(pool) => self.emit_shared_constant(&pool, &text),
+            None => self.emit_constant(Value::text_from_string(text)),
+        }
     }
 
     fn super_keyword(&mut self, _can_assign: bool) {
@@ -445,7 +610,7 @@ impl Assembler {
             super::compiler::UpvalueResolve::NotFound => None,
             super::compiler::UpvalueResolve::Index(index) => Some(index),
             super::compiler::UpvalueResolve::Error(err) => {
-                self.error(err);
+                self.error_of_kind(CompileErrorKind::TooManyUpvalues, err);
                 Some(0)
             }
         }
@@ -455,10 +620,18 @@ impl Assembler {
 /// Variables
 impl Assembler {
     fn var_declaration(&mut self) {
+        if self.is_match(TokenType::LeftParenthesis) {
+            self.destructuring_declaration();
+            return;
+        }
         let global = self.parse_variable("Expect variable name");
 
         if self.is_match(TokenType::Equal) {
-            self.expression();
+            if self.check(TokenType::While) || self.check(TokenType::For) {
+                self.loop_expression();
+            } else {
+                self.expression();
+            }
         } else {
             self.emit_instruction(&Instruction::Nil);
         }
@@ -470,6 +643,69 @@ impl Assembler {
         self.define_variable(global);
     }
 
+    /// `var x = while (...) {...};` evaluates to the value of `break value;`,
+    /// it's nil if the loop ends by its condition or by a plain `break;`.
+    /// Other expression positions, e.g. `x = while (...) {...};`, are rejected
+    fn loop_expression(&mut self) {
+        let is_global = self.compiler().is_global_scope();
+        self.emit_instruction(&Instruction::Nil);
+        if is_global {
+            // space in the name makes the local unreachable from the script
+            self.begin_scope();
+            self.add_local(" loop value".to_string());
+            self.compiler_mut().mark_initialized();
+        }
+        // otherwise the declared variable isn't initialized yet, its slot receives the value
+        let slot = self.compiler().last_local_slot();
+        if self.is_match(TokenType::While) {
+            self.while_statement(Some(slot));
+        } else if self.is_match(TokenType::For) {
+            self.for_statement(Some(slot));
+        }
+        if is_global {
+            self.compiler_mut().end_scope_keeping_value();
+        }
+    }
+
+    /// `var (x, y) = f();` unpacks a list into new variables, its length must match the names
+    fn destructuring_declaration(&mut self) {
+        let mut globals = Vec::new();
+        let mut names = HashSet::new();
+        loop {
+            // the declared locals aren't initialized yet, so the scope check doesn't see them
+            if self.check(TokenType::Identifier) && !names.insert(self.current.text.clone()) {
+                self.error_at_current("Already a variable with this name in this scope");
+            }
+            globals.push(self.parse_variable("Expect variable name"));
+            if !self.is_match(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(
+            TokenType::RightParenthesis,
+            "Expect ')' after variable names",
+        );
+        self.consume(TokenType::Equal, "Expect '=' after variable names");
+        self.expression();
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration",
+        );
+        if globals.len() > u8::MAX as usize {
+            self.error("Can't unpack more than 255 variables");
+            return;
+        }
+        self.emit_instruction(&Instruction::Unpack(globals.len() as u8));
+        if self.compiler().is_local_scope() {
+            self.compiler_mut().mark_last_initialized(globals.len());
+            return;
+        }
+        // the last value is on top of the stack
+        for global in globals.into_iter().rev() {
+            self.emit_instruction(&Instruction::DefineGlobal(global));
+        }
+    }
+
     fn parse_variable(&mut self, message: &str) -> u8 {
         self.consume(TokenType::Identifier, message);
         self.declare_variable();
@@ -481,18 +717,27 @@ impl Assembler {
 
     fn declare_variable(&mut self) {
         if self.compiler().is_global_scope() {
+            self.global_names.insert(self.prev_token_name());
             return;
         }
         let token = self.prev_token_owned();
         if self.compiler().has_declared_variable(&token) {
             self.error("Already a variable with this name in this scope");
+        } else if self.options.warn_shadowing
+            && (self.global_names.contains(&token.text)
+                || self.compiler().has_visible_local(&token.text))
+        {
+            self.warning(&format!("'{}' shadows an outer binding", token.text));
         }
         self.add_local(token.text);
     }
 
     fn add_local(&mut self, name: String) {
         if !self.compiler().has_capacity() {
-            self.error("Too many local variables in function");
+            self.error_of_kind(
+                CompileErrorKind::TooManyLocals,
+                "Too many local variables in function",
+            );
             return;
         }
         let local = Local::with_name(name);
@@ -583,10 +828,20 @@ impl Assembler {
 /// Statements
 impl Assembler {
     fn statement(&mut self) {
+        self.statement_starts.push(self.current.clone());
+        self.statement_of_type();
+        self.statement_starts.pop();
+    }
+
+    fn statement_of_type(&mut self) {
         if self.is_match(TokenType::Print) {
             self.print_statement();
             return;
         }
+        if self.is_match(TokenType::AssertEq) {
+            self.assert_eq_statement();
+            return;
+        }
         if self.is_match(TokenType::Break) {
             self.break_statement();
             return;
@@ -596,7 +851,7 @@ impl Assembler {
             return;
         }
         if self.is_match(TokenType::For) {
-            self.for_statement();
+            self.for_statement(None);
             return;
         }
         if self.is_match(TokenType::If) {
@@ -612,7 +867,7 @@ impl Assembler {
             return;
         }
         if self.is_match(TokenType::While) {
-            self.while_statement();
+            self.while_statement(None);
             return;
         }
         if self.is_match(TokenType::LeftBrace) {
@@ -632,7 +887,7 @@ impl Assembler {
 
     fn if_statement(&mut self) {
         self.consume(TokenType::LeftParenthesis, "Expect '(' after 'if'");
-        self.expression();
+        self.condition_expression();
         self.consume(TokenType::RightParenthesis, "Expect ')' after condition");
 
         let then_jump = self.emit_instruction(&Instruction::stub_jump_if_false());
@@ -660,18 +915,31 @@ impl Assembler {
     /// because their values are not known at compile time.
     /// As result, it may lead to unexpected behavior when
     /// different case entries will be associated with the same value
+    ///
+    /// Subject is compared with each case by value using `Value::equals`,
+    /// so strings match by content while functions, classes and instances match by reference
     fn switch_statement(&mut self) {
-        self.consume(TokenType::LeftParenthesis, "Expect '(' after 'if'");
+        self.consume(TokenType::LeftParenthesis, "Expect '(' after 'switch'");
         self.expression();
         self.consume(TokenType::RightParenthesis, "Expect ')' after condition");
         self.consume(TokenType::LeftBrace, "Expect '{' after 'switch' statement");
 
         let mut exit_jumps: Vec<usize> = Vec::new();
         let mut default_offset: Option<usize> = None;
+        // literal case values, a repeated one can never match
+        let mut case_values: Vec<Value> = Vec::new();
         loop {
             if self.is_match(TokenType::Case) {
                 self.emit_instruction(&Instruction::Duplicate);
+                let start = self.chunk_position();
                 self.expression();
+                if let Some(value) = self.literal_since(start) {
+                    if case_values.contains(&value) {
+                        self.warning("Duplicate case value");
+                    } else {
+                        case_values.push(value);
+                    }
+                }
                 self.consume(TokenType::Colon, "Expect ':' after case expression");
                 self.emit_instruction(&Instruction::Equal);
                 let next_case = self.emit_instruction(&Instruction::stub_jump_if_false());
@@ -702,6 +970,9 @@ impl Assembler {
         self.consume(TokenType::RightBrace, "Expect '}' after 'switch' block");
         if let Some(offset) = default_offset {
             self.emit_loop(offset);
+        } else {
+            // no case matched, drop the subject value
+            self.emit_instruction(&Instruction::Pop);
         }
         exit_jumps
             .into_iter()
@@ -721,12 +992,64 @@ impl Assembler {
         }
     }
 
-    fn while_statement(&mut self) {
-        let loop_start = self.mark_start_loop();
-        self.consume(TokenType::LeftParenthesis, "Expect '(' after 'while'");
+    /// Parses the condition, in strict mode warns if it's a literal of non-boolean type
+    fn condition_expression(&mut self) {
+        let start = self.chunk_position();
         self.expression();
+        if !self.options.strict_conditions {
+            return;
+        }
+        let is_literal = self
+            .literal_since(start)
+            .is_some_and(|value| !matches!(value, Value::Bool(_)));
+        if is_literal {
+            self.warning("Condition is a literal of non-boolean type");
+        }
+    }
+
+    /// Value of the literal if it's the only instruction emitted since `start`
+    fn literal_since(&self, start: usize) -> Option<Value> {
+        let (instr, size) = self.compiler().fetch_instruction(start);
+        if start + size != self.chunk_position() {
+            // not a single instruction
+            return None;
+        }
+        match instr.ok()? {
+            Instruction::Nil => Some(Value::Nil),
+            Instruction::True => Some(Value::Bool(true)),
+            Instruction::False => Some(Value::Bool(false)),
+            Instruction::ConstantSmall(value) => Some(Value::number(value as Double)),
+            Instruction::Constant(index) => self
+                .compiler()
+                .chunk()
+                .read_const(index)
+                .filter(|value| value.as_number().is_some() || value.as_text().is_some()),
+            Instruction::SharedConstant(first, second) => self
+                .compiler()
+                .chunk()
+                .read_shared_const(bytes_to_word(first, second)),
+            _ => None,
+        }
+    }
+
+    fn while_statement(&mut self, value_slot: Option<u8>) {
+        let loop_start = self.mark_start_loop(value_slot);
+        self.consume(TokenType::LeftParenthesis, "Expect '(' after 'while'");
+        let literal = self.literal_loop_condition(TokenType::RightParenthesis);
+        if literal.is_none() {
+            self.condition_expression();
+        }
         self.consume(TokenType::RightParenthesis, "Expect ')' after condition");
 
+        if literal.is_some() {
+            self.statement();
+            self.emit_loop(loop_start);
+            if literal == Some(false) {
+                self.discard_loop_code(loop_start);
+            }
+            self.flush_loop();
+            return;
+        }
         let exit_jump = self.emit_instruction(&Instruction::stub_jump_if_false());
         self.emit_instruction(&Instruction::Pop);
         self.statement();
@@ -737,9 +1060,40 @@ impl Assembler {
         self.flush_loop();
     }
 
-    fn for_statement(&mut self) {
+    /// Consumes a literal `true` or `false` condition followed by `terminator`.
+    /// A `true` loop runs without checking the condition and exits with `break` only,
+    /// a `false` loop is compiled to report errors but its code is dropped
+    fn literal_loop_condition(&mut self, terminator: TokenType) -> Option<bool> {
+        let value = match self.cur_token_type() {
+            TokenType::True => true,
+            TokenType::False => false,
+            _ => return None,
+        };
+        if self.peek_next().t_type != terminator {
+            return None;
+        }
+        self.advance();
+        if !value {
+            self.warning("Loop body is unreachable, condition is always false");
+        }
+        Some(value)
+    }
+
+    fn discard_loop_code(&mut self, loop_start: usize) {
+        self.compiler_mut().chunk_mut().truncate(loop_start);
+        if let Some(data) = self.loop_stack.last_mut() {
+            data.breaks.clear();
+        }
+    }
+
+    fn for_statement(&mut self, value_slot: Option<u8>) {
         self.begin_scope();
         self.consume(TokenType::LeftParenthesis, "Expect '(' after 'for'");
+        if self.check(TokenType::Identifier) && self.check_next_keyword("in") {
+            self.for_in_statement(value_slot);
+            self.end_scope();
+            return;
+        }
         if self.is_match(TokenType::Semicolon) {
             // no initializer
         } else if self.is_match(TokenType::Var) {
@@ -748,13 +1102,20 @@ impl Assembler {
             self.expression_statement();
         }
 
-        let mut loop_start = self.mark_start_loop();
+        let mut loop_start = self.mark_start_loop(value_slot);
+        let condition_start = loop_start;
         let mut exit_jump: Option<usize> = None;
+        let mut literal: Option<bool> = None;
         if !self.is_match(TokenType::Semicolon) {
-            self.expression();
+            literal = self.literal_loop_condition(TokenType::Semicolon);
+            if literal.is_none() {
+                self.condition_expression();
+            }
             self.consume(TokenType::Semicolon, "Expect ';' after loop condition");
-            exit_jump = Some(self.emit_instruction(&Instruction::stub_jump_if_false()));
-            self.emit_instruction(&Instruction::Pop);
+            if literal.is_none() {
+                exit_jump = Some(self.emit_instruction(&Instruction::stub_jump_if_false()));
+                self.emit_instruction(&Instruction::Pop);
+            }
         }
 
         if !self.is_match(TokenType::RightParenthesis) {
@@ -766,6 +1127,10 @@ impl Assembler {
 
             self.emit_loop(loop_start);
             loop_start = increment_start;
+            // 'continue' has to run the increment clause
+            if let Some(data) = self.loop_stack.last_mut() {
+                data.start = increment_start;
+            }
             self.patch_jump(body_jump);
         }
 
@@ -776,20 +1141,115 @@ impl Assembler {
             self.patch_jump(exit_jump);
             self.emit_instruction(&Instruction::Pop); // condition
         }
+        if literal == Some(false) {
+            self.discard_loop_code(condition_start);
+        }
 
         self.flush_loop();
         self.end_scope();
     }
 
+    /// `for (i in start..end)` is lowered to a numeric loop over `[start, end)`,
+    /// the end bound is evaluated once and kept in a hidden local.
+    /// `for (x in items)` walks a list by a hidden counter and reads the length
+    /// on every step, so items pushed by the body are visited too
+    fn for_in_statement(&mut self, value_slot: Option<u8>) {
+        self.consume(TokenType::Identifier, "Expect loop variable name");
+        self.declare_variable();
+        let variable = self.prev_token_name();
+        self.advance(); // 'in'
+        let Some(index) = self.compiler().resolve_local(&variable).map(|x| x.index) else {
+            return;
+        };
+        self.expression();
+        self.compiler_mut().mark_initialized();
+        // space in the name makes the locals unreachable from the script
+        let (counter, list) = if self.is_match(TokenType::DotDot) {
+            self.add_local(" range end".to_string());
+            self.expression();
+            self.compiler_mut().mark_initialized();
+            self.consume(TokenType::RightParenthesis, "Expect ')' after range");
+            (index, None)
+        } else {
+            self.consume(TokenType::RightParenthesis, "Expect ')' after list");
+            self.add_local(" list".to_string());
+            self.emit_instruction(&Instruction::GetLocal(index));
+            self.compiler_mut().mark_initialized();
+            self.add_local(" index".to_string());
+            self.emit_instruction(&Instruction::ConstantSmall(0));
+            self.compiler_mut().mark_initialized();
+            (index + 2, Some(index + 1))
+        };
+
+        let loop_start = self.mark_start_loop(value_slot);
+        self.emit_instruction(&Instruction::GetLocal(counter));
+        if let Some(list) = list {
+            let length = self.identifier_constant("length".to_string());
+            self.emit_instruction(&Instruction::GetLocal(list));
+            self.emit_instruction(&Instruction::Invoke(length, 0));
+        } else {
+            self.emit_instruction(&Instruction::GetLocal(index + 1));
+        }
+        self.emit_instruction(&Instruction::Less);
+        let exit_jump = self.emit_instruction(&Instruction::stub_jump_if_false());
+        self.emit_instruction(&Instruction::Pop);
+        let body_jump = self.emit_instruction(&Instruction::stub_jump());
+
+        let increment_start = self.chunk_position();
+        self.emit_instruction(&Instruction::GetLocal(counter));
+        self.emit_instruction(&Instruction::ConstantSmall(1));
+        self.emit_instruction(&Instruction::Add);
+        self.emit_instruction(&Instruction::SetLocal(counter));
+        self.emit_instruction(&Instruction::Pop);
+        self.emit_loop(loop_start);
+        self.patch_jump(body_jump);
+
+        if let Some(list) = list {
+            self.emit_instruction(&Instruction::GetLocal(list));
+            self.emit_instruction(&Instruction::GetLocal(counter));
+            self.emit_instruction(&Instruction::GetIndex);
+            self.emit_instruction(&Instruction::SetLocal(index));
+            self.emit_instruction(&Instruction::Pop);
+        }
+        // 'continue' has to step to the next value
+        if let Some(data) = self.loop_stack.last_mut() {
+            data.start = increment_start;
+        }
+        self.statement();
+        self.emit_loop(increment_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_instruction(&Instruction::Pop); // condition
+        self.flush_loop();
+    }
+
     fn break_statement(&mut self) {
+        let has_value = !self.check(TokenType::Semicolon);
+        if has_value {
+            self.expression();
+        }
         self.consume(TokenType::Semicolon, "Expect ';' after 'break'");
-        if self.loop_stack.is_empty() {
+        let Some((depth, value_slot)) = self
+            .loop_stack
+            .last()
+            .map(|data| (data.depth, data.value_slot))
+        else {
             self.error("'break' statement allowed inside loops only");
+            return;
+        };
+        if has_value {
+            let Some(slot) = value_slot else {
+                self.error("'break' with a value is allowed in loop expressions only");
+                return;
+            };
+            self.emit_instruction(&Instruction::SetLocal(slot));
+            self.emit_instruction(&Instruction::Pop);
         }
+        self.discard_loop_locals(depth);
         let offset = self.emit_instruction(&Instruction::stub_jump());
         self.emit_instruction(&Instruction::Pop);
         let Some(data) = self.loop_stack.last_mut() else {
-            self.error("Bug: loop stack became empty");
+            self.error_of_kind(CompileErrorKind::Internal, "Bug: loop stack became empty");
             return;
         };
         data.breaks.push(offset);
@@ -797,23 +1257,30 @@ impl Assembler {
 
     fn continue_statement(&mut self) {
         self.consume(TokenType::Semicolon, "Expect ';' after 'continue'");
-        let Some(data) = self.loop_stack.last() else {
+        let Some((start, depth)) = self.loop_stack.last().map(|data| (data.start, data.depth))
+        else {
             self.error("'continue' statement allowed inside loops only");
             return;
         };
-        self.emit_loop(data.start);
+        self.discard_loop_locals(depth);
+        self.emit_loop(start);
     }
 
-    fn mark_start_loop(&mut self) -> usize {
+    fn discard_loop_locals(&mut self, depth: usize) {
+        let line = self.get_line();
+        self.compiler_mut().discard_locals(depth, line);
+    }
+
+    fn mark_start_loop(&mut self, value_slot: Option<u8>) -> usize {
         let start = self.chunk_position();
-        let data = LoopData::new(start);
+        let data = LoopData::new(start, self.compiler().depth(), value_slot);
         self.loop_stack.push(data);
         start
     }
 
     fn flush_loop(&mut self) {
         let Some(val) = self.loop_stack.pop() else {
-            self.error("Bug: loop_stack is broken");
+            self.error_of_kind(CompileErrorKind::Internal, "Bug: loop_stack is broken");
             return;
         };
         for exit_jump in val.breaks {
@@ -826,7 +1293,25 @@ impl Assembler {
     }
 
     fn block(&mut self) {
+        // keyword of the statement that makes the rest of the block unreachable
+        let mut terminator: Option<String> = None;
+        let mut reported = false;
         while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            if let Some(keyword) = &terminator
+                && !reported
+            {
+                let message = format!("Unreachable code after {keyword}");
+                self.warning_at_current(&message);
+                reported = true;
+            }
+            if terminator.is_none()
+                && matches!(
+                    self.cur_token_type(),
+                    TokenType::Return | TokenType::Break | TokenType::Continue
+                )
+            {
+                terminator = Some(self.current.text.clone());
+            }
             self.declaration();
         }
         self.consume(TokenType::RightBrace, "Expect '}' after block");
@@ -837,6 +1322,19 @@ impl Assembler {
         self.compiler_mut().end_scope(line);
     }
 
+    fn assert_eq_statement(&mut self) {
+        self.consume(TokenType::LeftParenthesis, "Expect '(' after 'assert_eq'");
+        self.expression();
+        self.consume(TokenType::Comma, "Expect ',' after the first value");
+        self.expression();
+        self.consume(
+            TokenType::RightParenthesis,
+            "Expect ')' after the second value",
+        );
+        self.consume(TokenType::Semicolon, "Expect ';' after 'assert_eq'");
+        self.emit_instruction(&Instruction::AssertEqual);
+    }
+
     fn print_statement(&mut self) {
         self.expression();
         self.consume(TokenType::Semicolon, "Expect ';' after value");
@@ -855,6 +1353,18 @@ impl Assembler {
                 self.error("Can't return a value from an initializer");
             }
             self.expression();
+            // `return a, b;` returns a list of the values
+            let mut count = 1;
+            while self.is_match(TokenType::Comma) {
+                self.expression();
+                if count == u8::MAX as usize {
+                    self.error("Can't return more than 255 values");
+                }
+                count += 1;
+            }
+            if count > 1 {
+                self.emit_instruction(&Instruction::BuildList(count as u8));
+            }
             self.consume(TokenType::Semicolon, "Expect ';' after return value");
             self.emit_instruction(&Instruction::Return);
         }
@@ -864,9 +1374,16 @@ impl Assembler {
 /// Emit functions
 impl Assembler {
     fn make_constant(&mut self, value: Value) -> u8 {
+        let value = match value {
+            Value::Text(text) => Value::Text(self.pooled_text(text)),
+            value => value,
+        };
         let idx = self.compiler_mut().add_constant(value);
         if idx > u8::MAX as usize {
-            self.error("Too many constants in one chunk");
+            self.error_of_kind(
+                CompileErrorKind::TooManyConstants,
+                "Too many constants in one chunk",
+            );
             // don't think it's a good decision
             // but this index seems doesn't reachable
             return 0;
@@ -874,11 +1391,39 @@ impl Assembler {
         idx as u8
     }
 
+    fn pooled_text(&mut self, text: Rc<String>) -> Rc<String> {
+        if let Some(pooled) = self.text_pool.get(text.as_str()) {
+            return pooled.clone();
+        }
+        self.text_pool.insert(text.to_string(), text.clone());
+        text
+    }
+
     fn emit_constant(&mut self, value: Value) {
         let idx = self.make_constant(value);
         self.emit_instruction(&Instruction::Constant(idx));
     }
 
+    /// Equal texts refer to the same entry of the pool
+    fn emit_shared_constant(&mut self, pool: &SharedConstants, text: &str) {
+        let index = match self.shared_indices.get(text) {
+            Some(index) => *index,
+            None => {
+                let Some(index) = pool.add(Value::text_from_str(text)) else {
+                    self.error_of_kind(
+                        CompileErrorKind::TooManyConstants,
+                        "Too many shared constants",
+                    );
+                    return;
+                };
+                self.shared_indices.insert(text.to_string(), index);
+                index
+            }
+        };
+        let (first, second) = word_to_bytes(index);
+        self.emit_instruction(&Instruction::SharedConstant(first, second));
+    }
+
     fn emit_return(&mut self) -> usize {
         let instruction = match self.compiler().func_type() {
             FuncType::Initializer => Instruction::GetLocal(0),
@@ -905,7 +1450,7 @@ impl Assembler {
         let size = instr.size();
         let offset = self.chunk_position() - loop_start + size;
         if offset > u16::MAX as usize {
-            self.error("Jump size is too large");
+            self.jump_error("Jump size is too large");
         }
         let (f, s) = word_to_bytes(offset);
         self.emit_instruction(&Instruction::Loop(f, s));
@@ -916,18 +1461,21 @@ impl Assembler {
 
         let jump = self.chunk_position() - offset - size;
         if jump > u16::MAX as usize {
-            self.error("Too much code to jump over");
+            self.jump_error("Too much code to jump over");
         }
         let (first, second) = word_to_bytes(jump);
         let instr = match fetch_result {
             Ok(Instruction::JumpIfFalse(_, _)) => Instruction::JumpIfFalse(first, second),
             Ok(Instruction::Jump(_, _)) => Instruction::Jump(first, second),
             Err(err) => {
-                self.error(&format!("Bug: {err}"));
+                self.error_of_kind(CompileErrorKind::Internal, &format!("Bug: {err}"));
                 return;
             }
             _ => {
-                self.error("Bug: Attempt to patch non-jump instruction in 'path_jump' function");
+                self.error_of_kind(
+                    CompileErrorKind::Internal,
+                    "Bug: Attempt to patch non-jump instruction in 'path_jump' function",
+                );
                 return;
             }
         };
@@ -942,20 +1490,55 @@ impl Assembler {
 // Errors
 impl Assembler {
     fn error_at_current(&mut self, message: &str) {
-        self.push_error_info(self.current.clone(), message);
+        self.push_error_info(self.current.clone(), message, CompileErrorKind::Syntax);
     }
 
     fn error(&mut self, message: &str) {
-        self.push_error_info(self.prev_token_owned(), message);
+        self.error_of_kind(CompileErrorKind::Syntax, message);
+    }
+
+    fn error_of_kind(&mut self, kind: CompileErrorKind, message: &str) {
+        self.push_error_info(self.prev_token_owned(), message, kind);
+    }
+
+    /// Jumps are emitted and patched after the code they cross is compiled,
+    /// so the error points to the statement that owns the jump
+    fn jump_error(&mut self, message: &str) {
+        let token = self
+            .statement_starts
+            .last()
+            .cloned()
+            .unwrap_or_else(|| self.prev_token_owned());
+        self.push_error_info(token, message, CompileErrorKind::JumpTooLarge);
+    }
+
+    /// Reports an error at the previous token without entering panic mode
+    fn recoverable_error(&mut self, message: &str) {
+        if self.panic_mode {
+            return;
+        }
+        let info = ErrorInfo::with(self.prev_token_owned(), message);
+        self.errors.push(info);
+    }
+
+    /// Non-fatal diagnostic, doesn't affect panic mode
+    fn warning_at_current(&mut self, message: &str) {
+        let info = ErrorInfo::with(self.current.clone(), message);
+        self.warnings.push(info);
+    }
+
+    fn warning(&mut self, message: &str) {
+        let info = ErrorInfo::with(self.prev_token_owned(), message);
+        self.warnings.push(info);
     }
 
     // convenience function
-    fn push_error_info(&mut self, elem: Token, message: &str) {
+    fn push_error_info(&mut self, elem: Token, message: &str, kind: CompileErrorKind) {
         if self.panic_mode {
             return;
         }
         self.panic_mode = true;
-        let info = ErrorInfo::with(elem, message);
+        let info = ErrorInfo::with_kind(elem, message, kind);
         self.errors.push(info);
     }
 }
@@ -1005,17 +1588,31 @@ impl Assembler {
 struct LoopData {
     start: usize,
     breaks: Vec<usize>,
+    /// Scope depth outside of the loop body, deeper locals are dropped by `break` and `continue`
+    depth: usize,
+    /// Local slot that receives `break value;` of a loop expression
+    value_slot: Option<u8>,
 }
 
 impl LoopData {
-    fn new(start: usize) -> Self {
+    fn new(start: usize, depth: usize, value_slot: Option<u8>) -> Self {
         Self {
             start,
             breaks: Default::default(),
+            depth,
+            value_slot,
         }
     }
 }
 
+/// Integer numbers in `i8` range are emitted without the constant pool
+fn small_integer(value: Double) -> Option<i8> {
+    let is_small = value.fract() == 0.0
+        && (i8::MIN as Double..=i8::MAX as Double).contains(&value)
+        && !(value == 0.0 && value.is_sign_negative());
+    is_small.then_some(value as i8)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -1079,6 +1676,15 @@ mod tests {
         assert_eq!(frontend.prev_token_owned(), frontend.previous);
     }
 
+    #[test]
+    fn malformed_number_token_is_error() {
+        let input = vec![Token::number("1.2.3"), Token::semicolon()];
+        let parser = compose_frontend_with_tokens(input);
+        let errors = parser.compile().0.expect_err("Must fail");
+        assert_eq!(errors[0].kind(), CompileErrorKind::Internal);
+        assert_eq!(errors[0].message(), "Bug: malformed number '1.2.3'");
+    }
+
     //
     fn compose_frontend_with_tokens(tokens: Vec<Token>) -> Assembler {
         let scanner = ScannerMock::new(tokens);
@@ -1086,7 +1692,7 @@ mod tests {
     }
 
     fn compose_frontend(scanner: Box<dyn TokenSource>) -> Assembler {
-        Assembler::new(scanner)
+        Assembler::new(scanner, CompileOptions::default())
     }
 
     // legacy test group
@@ -1106,7 +1712,7 @@ mod tests {
         let data = [
             (
                 Token::make(TokenType::BangEqual, "!="),
-                vec![Instruction::Equal, Instruction::Not],
+                vec![Instruction::NotEqual],
             ),
             (
                 Token::make(TokenType::EqualEqual, "=="),
@@ -1118,12 +1724,12 @@ mod tests {
             ),
             (
                 Token::make(TokenType::GreaterEqual, ">="),
-                vec![Instruction::Less, Instruction::Not],
+                vec![Instruction::GreaterEqual],
             ),
             (Token::make(TokenType::Less, "<"), vec![Instruction::Less]),
             (
                 Token::make(TokenType::LessEqual, "<="),
-                vec![Instruction::Greater, Instruction::Not],
+                vec![Instruction::LessEqual],
             ),
             (Token::minus(), vec![Instruction::Subtract]),
             (Token::plus(), vec![Instruction::Add]),
@@ -1137,13 +1743,14 @@ mod tests {
                 Token::number("5.0"),
                 Token::semicolon(),
             ];
-            let mut instructions = vec![Instruction::Constant(0), Instruction::Constant(1)];
+            let mut instructions =
+                vec![Instruction::ConstantSmall(3), Instruction::ConstantSmall(5)];
 
             for exp_instr in expected_instr {
                 instructions.push(exp_instr.clone());
             }
             let expectation = Expectation {
-                constants: vec![Value::number(3.0), Value::number(5.0)],
+                constants: vec![],
                 instructions,
             };
             state_expectation_test(input, expectation);
@@ -1168,12 +1775,12 @@ mod tests {
 
     #[test]
     fn emit_grouping_chunk() {
-        // 3 * (5 + 7)
+        // 3 * (5.5 + 7)
         let input = vec![
             Token::number("3"),
             Token::multiply(),
             Token::with_type(TokenType::LeftParenthesis),
-            Token::number("5.0"),
+            Token::number("5.5"),
             Token::plus(),
             Token::number("7"),
             Token::with_type(TokenType::RightParenthesis),
@@ -1181,11 +1788,11 @@ mod tests {
         ];
 
         let expectation = Expectation {
-            constants: vec![Value::number(3.0), Value::number(5.0), Value::number(7.0)],
+            constants: vec![Value::number(5.5)],
             instructions: vec![
+                Instruction::ConstantSmall(3),
                 Instruction::Constant(0),
-                Instruction::Constant(1),
-                Instruction::Constant(2),
+                Instruction::ConstantSmall(7),
                 Instruction::Add,
                 Instruction::Multiply,
             ],
@@ -1194,6 +1801,29 @@ mod tests {
         state_expectation_test(input, expectation);
     }
 
+    #[test]
+    fn emit_small_integer_constants() {
+        let data = [
+            ("0", Instruction::ConstantSmall(0)),
+            ("5", Instruction::ConstantSmall(5)),
+            ("127", Instruction::ConstantSmall(127)),
+            ("128", Instruction::Constant(0)),
+            ("1.5", Instruction::Constant(0)),
+        ];
+        for (text, instruction) in data {
+            let input = vec![Token::number(text), Token::semicolon()];
+            let constants = match instruction {
+                Instruction::Constant(_) => vec![Value::number_from(text).unwrap()],
+                _ => vec![],
+            };
+            let expectation = Expectation {
+                constants,
+                instructions: vec![instruction],
+            };
+            state_expectation_test(input, expectation);
+        }
+    }
+
     #[test]
     fn emit_string_constant() {
         let input = vec![
@@ -1207,10 +1837,202 @@ mod tests {
         state_expectation_test(input, expectation);
     }
 
+    #[test]
+    fn missing_semicolon_recovery() {
+        let code = r#"
+            var a = 1
+            print a
+            var b = a + 1;
+            print ;
+        "#;
+        let errors = crate::compile(Rc::new(code.chars().collect())).expect_err("Must fail");
+        let errors = errors
+            .iter()
+            .map(|err| (err.line(), err.message()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (Some(2), "Expect ';' after variable declaration"),
+                (Some(3), "Expect ';' after value"),
+                (Some(5), "Expect expression"),
+            ]
+        );
+    }
+
+    #[test]
+    fn print_statement_not_followed_by_pop() {
+        let code = r#"
+            var x = 1;
+            print x;
+            print x + 1;
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while let Ok(instr) = func.chunk().fetch(&mut offset) {
+            instructions.push(instr);
+        }
+        assert!(
+            instructions
+                .windows(2)
+                .all(|pair| pair != [Instruction::Print, Instruction::Pop])
+        );
+    }
+
+    #[test]
+    fn functions_share_text_constants() {
+        let code = r#"
+            fun first() { return "init"; }
+            fun second() { print "init"; }
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let texts = func
+            .chunk()
+            .constants()
+            .iter()
+            .filter_map(|x| x.as_function())
+            .map(|func| {
+                func.chunk()
+                    .constants()
+                    .iter()
+                    .find_map(|x| x.as_text())
+                    .expect("Text constant is missing")
+            })
+            .collect::<Vec<_>>();
+        let [first, second] = &texts[..] else {
+            panic!("Expected 2 functions, got {}", texts.len());
+        };
+        assert_eq!(first.as_str(), "init");
+        assert!(Rc::ptr_eq(first, second));
+    }
+
+    #[test]
+    fn shared_constants_pool_is_common_for_functions() {
+        let code = r#"
+            fun first() { return "init"; }
+            fun second() { print "init"; }
+        "#;
+        let options = CompileOptions {
+            shared_constants: true,
+            ..Default::default()
+        };
+        let func = crate::compile_with(Rc::new(code.chars().collect()), options)
+            .expect("Failed to compile");
+        let pool = func.chunk().shared_constants().expect("Pool is missing");
+        assert_eq!(pool.len(), 1);
+        for func in func
+            .chunk()
+            .constants()
+            .iter()
+            .filter_map(|x| x.as_function())
+        {
+            let chunk = func.chunk();
+            assert!(Rc::ptr_eq(pool, chunk.shared_constants().unwrap()));
+            assert!(chunk.constants().iter().all(|x| x.as_text().is_none()));
+            assert_eq!(
+                chunk.fetch(&mut 0).unwrap(),
+                Instruction::SharedConstant(0, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn range_loop_step_uses_no_constant() {
+        let code = "for (i in 0..3) print i;";
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        assert!(func.chunk().constants().is_empty());
+    }
+
+    #[test]
+    fn end_scope_batches_pops() {
+        let code = r#"
+            {
+                var a = 1;
+                var b = 2;
+                fun f() { return b; }
+                var c = 3;
+                var d = 4;
+            }
+        "#;
+        let func = crate::compile(Rc::new(code.chars().collect())).expect("Failed to compile");
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+        while let Ok(instr) = func.chunk().fetch(&mut offset) {
+            if let Instruction::Closure(_) = instr {
+                // skip upvalue data of `f`
+                offset += 2;
+            }
+            instructions.push(instr);
+        }
+        let tail = &instructions[instructions.len() - 5..];
+        assert_eq!(
+            tail,
+            &[
+                Instruction::PopN(3),
+                Instruction::CloseUpvalue,
+                Instruction::Pop,
+                Instruction::Nil,
+                Instruction::Return,
+            ][..]
+        );
+    }
+
+    #[test]
+    fn literal_loop_conditions() {
+        let instructions = |code: &str| {
+            let (result, warnings) = crate::compile_with_warnings(
+                Rc::new(code.chars().collect()),
+                CompileOptions::default(),
+            );
+            let func = result.expect("Failed to compile");
+            let mut instructions = Vec::new();
+            let mut offset = 0;
+            while let Ok(instr) = func.chunk().fetch(&mut offset) {
+                instructions.push(instr);
+            }
+            let warnings = warnings
+                .iter()
+                .map(|x| x.message().to_string())
+                .collect::<Vec<_>>();
+            (instructions, warnings)
+        };
+
+        let (code, warnings) = instructions("while (true) { print 1; break; }");
+        assert!(warnings.is_empty());
+        assert!(
+            !code
+                .iter()
+                .any(|x| matches!(x, Instruction::JumpIfFalse(_, _) | Instruction::True))
+        );
+        assert!(code.iter().any(|x| matches!(x, Instruction::Loop(_, _))));
+
+        let unreachable = vec!["Loop body is unreachable, condition is always false".to_string()];
+        for code in [
+            "while (false) { print 1; break; }",
+            "for (;false; 1) { print 1; continue; }",
+        ] {
+            let (code, warnings) = instructions(code);
+            assert_eq!(code, vec![Instruction::Nil, Instruction::Return]);
+            assert_eq!(warnings, unreachable);
+        }
+        // the loop variable is still initialized and dropped
+        let (code, _) = instructions("for (var i = 0; false; i = i + 1) print i;");
+        assert_eq!(
+            code,
+            vec![
+                Instruction::ConstantSmall(0),
+                Instruction::Pop,
+                Instruction::Nil,
+                Instruction::Return
+            ]
+        );
+    }
+
     fn state_expectation_test(input: Vec<Token>, expectation: Expectation) {
         let mock = ScannerMock::new(input);
-        let parser = Assembler::new(Box::new(mock));
-        let compiler = parser.compile().expect("Failed to perform expectation");
+        let parser = Assembler::new(Box::new(mock), CompileOptions::default());
+        let compiler = parser.compile().0.expect("Failed to perform expectation");
 
         for (i, x) in expectation.constants.iter().enumerate() {
             assert_eq!(compiler.chunk().read_const(i as u8), Some(x.clone()));
@@ -1,6 +1,6 @@
 use crate::{
-    Chunk, FetchResult, Func, FuncType, Instruction, UINT8_COUNT, UpvalueData, Value,
-    frontend::Token,
+    Chunk, FetchResult, Func, FuncType, Instruction, LocalDebugInfo, UINT8_COUNT, UpvalueData,
+    Value, frontend::Token,
 };
 
 pub const MAX_SCOPE_SIZE: usize = UINT8_COUNT;
@@ -17,8 +17,10 @@ pub struct Compiler {
 
 impl Compiler {
     pub fn with(func_type: FuncType, enclosing: Option<Box<Compiler>>) -> Self {
+        let mut func = Box::<Func>::default();
+        func.is_method = matches!(func_type, FuncType::Method | FuncType::Initializer);
         Self {
-            func: Default::default(),
+            func,
             func_type,
             locals: vec![Local::reserved(func_type)],
             depth: Default::default(),
@@ -34,6 +36,10 @@ impl Compiler {
     pub fn func_type(&self) -> &FuncType {
         &self.func_type
     }
+
+    pub fn enable_debug_info(&mut self) {
+        self.func.debug_locals = Some(Vec::new());
+    }
 }
 
 /// Shorthands
@@ -63,7 +69,7 @@ impl Compiler {
     }
 
     pub fn chunk_position(&self) -> usize {
-        self.func.chunk().size()
+        self.func.chunk().byte_size()
     }
 }
 
@@ -107,14 +113,73 @@ impl Compiler {
 
     pub fn end_scope(&mut self, line: usize) {
         self.depth -= 1;
+        // consecutive non-captured locals are dropped by a single instruction
+        let mut pop_count: u8 = 0;
         while self.is_last_out_of_scope() {
             let is_captured = self.locals.last().map(|x| x.is_captured).unwrap_or(false);
+            let end = if is_captured {
+                self.emit_pops(pop_count, line);
+                pop_count = 0;
+                self.emit_instruction_at_line(&Instruction::CloseUpvalue, line)
+            } else {
+                if pop_count == u8::MAX {
+                    self.emit_pops(pop_count, line);
+                    pop_count = 0;
+                }
+                pop_count += 1;
+                self.chunk_position()
+            };
+            self.locals.pop();
+            self.close_debug_local(end);
+        }
+        self.emit_pops(pop_count, line);
+    }
+
+    /// Leaves the scope of a single local without popping it,
+    /// so its value becomes the result of the expression that opened the scope
+    pub fn end_scope_keeping_value(&mut self) {
+        self.depth -= 1;
+        let end = self.chunk_position();
+        self.locals.pop();
+        self.close_debug_local(end);
+    }
+
+    /// Drops locals deeper than `depth` from the stack but keeps them declared,
+    /// used by jumps out of nested scopes like `break` and `continue`
+    pub fn discard_locals(&mut self, depth: usize, line: usize) {
+        let captured = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_some_and(|x| x > depth))
+            .map(|local| local.is_captured)
+            .collect::<Vec<_>>();
+        let mut pop_count: u8 = 0;
+        for is_captured in captured {
             if is_captured {
+                self.emit_pops(pop_count, line);
+                pop_count = 0;
                 self.emit_instruction_at_line(&Instruction::CloseUpvalue, line);
-            } else {
-                self.emit_instruction_at_line(&Instruction::Pop, line);
+                continue;
             }
-            self.locals.pop();
+            if pop_count == u8::MAX {
+                self.emit_pops(pop_count, line);
+                pop_count = 0;
+            }
+            pop_count += 1;
+        }
+        self.emit_pops(pop_count, line);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn emit_pops(&mut self, count: u8, line: usize) {
+        match count {
+            0 => {}
+            1 => _ = self.emit_instruction_at_line(&Instruction::Pop, line),
+            _ => _ = self.emit_instruction_at_line(&Instruction::PopN(count), line),
         }
     }
 
@@ -126,6 +191,10 @@ impl Compiler {
         self.depth > 0
     }
 
+    pub fn last_local_slot(&self) -> u8 {
+        (self.locals.len() - 1) as u8
+    }
+
     pub fn has_capacity(&self) -> bool {
         self.locals.len() < MAX_SCOPE_SIZE
     }
@@ -149,6 +218,15 @@ impl Compiler {
         false
     }
 
+    /// Checks locals of all scopes including enclosing functions
+    pub fn has_visible_local(&self, name: &str) -> bool {
+        self.locals.iter().any(|local| local.name == name)
+            || self
+                .enclosing
+                .as_ref()
+                .is_some_and(|enclosing| enclosing.has_visible_local(name))
+    }
+
     pub fn resolve_local(&self, name: &str) -> Option<LocalData> {
         for (i, local) in self.locals.iter().enumerate().rev() {
             if local.name == name {
@@ -212,13 +290,51 @@ impl Compiler {
     }
 
     pub fn mark_initialized(&mut self) {
+        self.mark_last_initialized(1);
+    }
+
+    /// Marks the given count of the last declared locals, e.g. names of `var (x, y) = ...`
+    pub fn mark_last_initialized(&mut self, count: usize) {
         if self.depth == 0 {
             return;
         }
-        let Some(local) = self.locals.last_mut() else {
+        let Some(start) = self.locals.len().checked_sub(count) else {
             panic!();
         };
-        local.depth = Some(self.depth);
+        for slot in start..self.locals.len() {
+            self.locals[slot].depth = Some(self.depth);
+            self.open_debug_local(slot);
+        }
+    }
+
+    fn open_debug_local(&mut self, slot: usize) {
+        let start = self.chunk_position();
+        let Some(name) = self.locals.get(slot).map(|x| x.name.clone()) else {
+            return;
+        };
+        let Some(debug_locals) = self.func.debug_locals.as_mut() else {
+            return;
+        };
+        debug_locals.push(LocalDebugInfo {
+            name,
+            slot,
+            start,
+            end: None,
+        });
+    }
+
+    fn close_debug_local(&mut self, end: usize) {
+        let slot = self.locals.len();
+        let Some(debug_locals) = self.func.debug_locals.as_mut() else {
+            return;
+        };
+        if let Some(info) = debug_locals
+            .iter_mut()
+            .rev()
+            .find(|info| info.slot == slot && info.end.is_none())
+        {
+            info.end = Some(end);
+        }
     }
 }
 
@@ -9,11 +9,81 @@ use std::rc::Rc;
 use scanner::*;
 pub use token::*;
 
-use crate::{Func, errors::ErrorInfo, frontend::assembler::Assembler};
+use crate::{
+    Func,
+    errors::{CompileError, ErrorInfo, sort_errors},
+    frontend::assembler::Assembler,
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct CompileOptions {
+    /// Keep names of local variables to report them on runtime errors
+    pub debug_info: bool,
+    /// Order compile errors by position instead of discovery order
+    pub sort_errors: bool,
+    /// Warn about literal non-boolean loop and branch conditions, e.g. `while (5)`
+    pub strict_conditions: bool,
+    /// Warn when a local variable shadows a global or a variable of an outer scope
+    pub warn_shadowing: bool,
+    /// Put string literals of all functions into one pool of the compilation unit.
+    /// Equal literals take a single entry and don't use constant slots of the function,
+    /// so the limit of 256 constants per function doesn't apply to them
+    pub shared_constants: bool,
+}
+
+/// Streams tokens of the code without compiling it.
+/// Error tokens are yielded as well, the last token is always `Eof`
+pub fn tokenize(code: Rc<Vec<char>>) -> impl Iterator<Item = Token> {
+    let mut scanner = Scanner::new(code);
+    let mut is_finished = false;
+    std::iter::from_fn(move || {
+        if is_finished {
+            return None;
+        }
+        let token = scanner.scan_token();
+        is_finished = token.t_type == TokenType::Eof;
+        Some(token)
+    })
+}
 
 pub fn compile(code: Rc<Vec<char>>) -> Result<Func, Vec<ErrorInfo>> {
+    compile_with(code, CompileOptions::default())
+}
+
+pub fn compile_with(code: Rc<Vec<char>>, options: CompileOptions) -> Result<Func, Vec<ErrorInfo>> {
+    compile_with_warnings(code, options).0
+}
+
+/// Same as `compile_with` but errors have separate position fields and a kind
+pub fn compile_detailed(
+    code: Rc<Vec<char>>,
+    options: CompileOptions,
+) -> Result<Func, Vec<CompileError>> {
+    compile_with(code.clone(), options).map_err(|errors| {
+        errors
+            .iter()
+            .map(|info| CompileError::with_info(info, &code))
+            .collect()
+    })
+}
+
+/// Same as `compile_with` but also returns non-fatal warnings, e.g. unreachable code
+pub fn compile_with_warnings(
+    code: Rc<Vec<char>>,
+    options: CompileOptions,
+) -> (Result<Func, Vec<ErrorInfo>>, Vec<ErrorInfo>) {
     let scanner = Scanner::new(code);
-    let frontend = Assembler::new(Box::new(scanner));
-    let func = frontend.compile()?;
-    Ok(func)
+    let sort = options.sort_errors;
+    let frontend = Assembler::new(Box::new(scanner), options);
+    let (result, mut warnings) = frontend.compile();
+    let result = result.map_err(|mut errors| {
+        if sort {
+            sort_errors(&mut errors);
+        }
+        errors
+    });
+    if sort {
+        sort_errors(&mut warnings);
+    }
+    (result, warnings)
 }
@@ -16,7 +16,7 @@ pub(crate) enum Precedence {
     Equality,   // == !=
     Comparison, // < > <= >=
     Term,       // + -
-    Factor,     // * /
+    Factor,     // * / ~/
     Unary,      // ! -
     Call,       // . ()
     Primary,
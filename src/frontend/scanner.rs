@@ -8,76 +8,123 @@ pub trait TokenSource {
     fn scan_token(&mut self) -> Token;
 }
 
+/// Punctuation and operators. The longest match wins regardless of the order,
+/// so adding e.g. `<<=` doesn't break scanning of `<` and `<=`
+const OPERATORS: &[(&str, TokenType)] = &[
+    ("(", TokenType::LeftParenthesis),
+    (")", TokenType::RightParenthesis),
+    ("{", TokenType::LeftBrace),
+    ("}", TokenType::RightBrace),
+    ("[", TokenType::LeftBracket),
+    ("]", TokenType::RightBracket),
+    (":", TokenType::Colon),
+    (";", TokenType::Semicolon),
+    (",", TokenType::Comma),
+    (".", TokenType::Dot),
+    ("..", TokenType::DotDot),
+    ("?.", TokenType::QuestionDot),
+    ("-", TokenType::Minus),
+    ("+", TokenType::Plus),
+    ("/", TokenType::Slash),
+    ("*", TokenType::Star),
+    ("~/", TokenType::TildeSlash),
+    ("!", TokenType::Bang),
+    ("!=", TokenType::BangEqual),
+    ("=", TokenType::Equal),
+    ("==", TokenType::EqualEqual),
+    ("<", TokenType::Less),
+    ("<=", TokenType::LessEqual),
+    (">", TokenType::Greater),
+    (">=", TokenType::GreaterEqual),
+];
+
 pub struct Scanner {
     line: usize,
+    start_line: usize,
     code: Rc<Vec<char>>,
     code_start_idx: usize,
     code_current_idx: usize,
+    last_position: CodePosition,
 }
 
 impl TokenSource for Scanner {
     fn scan_token(&mut self) -> Token {
-        self.fetch_next_token()
+        let mut token = self.fetch_next_token();
+        // EOF has no text in the code, so it refers to the last real token instead
+        if token.t_type == TokenType::Eof {
+            token.position = self.last_position.clone();
+        } else {
+            self.last_position = token.position.clone();
+        }
+        token
     }
 }
 
 impl Scanner {
     pub fn new(code: Rc<Vec<char>>) -> Self {
-        Self {
+        let mut scanner = Self {
             line: 1,
+            start_line: 1,
             code,
             code_start_idx: 0,
             code_current_idx: 0,
+            last_position: CodePosition {
+                line: 1,
+                absolute_index: 0,
+            },
+        };
+        scanner.skip_shebang_line();
+        scanner
+    }
+
+    /// Allows running scripts directly with `#!/usr/bin/env fox-bytecode` first line
+    fn skip_shebang_line(&mut self) {
+        if (Some('#'), Some('!')) != (self.peek_char(), self.peek_next_char()) {
+            return;
+        }
+        while let Some(ch) = self.peek_char() {
+            if ch == '\n' {
+                break;
+            }
+            self.advance_char();
         }
     }
 
     fn fetch_next_token(&mut self) -> Token {
-        self.skip_non_code();
+        if let Err(message) = self.skip_non_code() {
+            return self.make_error_token(message);
+        }
         self.code_start_idx = self.code_current_idx;
+        self.start_line = self.line;
 
+        if let Some(token) = self.advance_operator() {
+            return token;
+        }
         let Some(ch) = self.advance_char() else {
             return self.make_token(TokenType::Eof);
         };
 
-        use TokenType::*;
         match ch {
-            '(' => self.make_token(LeftParenthesis),
-            ')' => self.make_token(RightParenthesis),
-            '{' => self.make_token(LeftBrace),
-            '}' => self.make_token(RightBrace),
-            ':' => self.make_token(Colon),
-            ';' => self.make_token(Semicolon),
-            ',' => self.make_token(Comma),
-            '.' => self.make_token(Dot),
-            '-' => self.make_token(Minus),
-            '+' => self.make_token(Plus),
-            '/' => self.make_token(Slash),
-            '*' => self.make_token(Star),
-            '!' => {
-                let cond = self.match_char('=');
-                self.condition_make_token(cond, BangEqual, Bang)
-            }
-            '=' => {
-                let cond = self.match_char('=');
-                self.condition_make_token(cond, EqualEqual, Equal)
-            }
-            '<' => {
-                let cond = self.match_char('=');
-                self.condition_make_token(cond, LessEqual, Less)
-            }
-            '>' => {
-                let cond = self.match_char('=');
-                self.condition_make_token(cond, GreaterEqual, Greater)
-            }
             '"' => self.advance_string_token(),
+            // raw string, backslashes are kept verbatim
+            'r' if self.peek_char() == Some('"') => {
+                self.advance_char();
+                self.advance_string_token()
+            }
             x if is_alphabetic(x) => self.advance_identifier(),
             x if x.is_ascii_digit() => self.advance_number_token(),
             x => self.make_error_token(&format!("Unexpected character '{x}'")),
         }
     }
 
-    fn skip_non_code(&mut self) {
-        while self.skip_whitespace() || self.skip_comment_line() {}
+    fn skip_non_code(&mut self) -> Result<(), &'static str> {
+        loop {
+            let is_skipped =
+                self.skip_whitespace() || self.skip_comment_line() || self.skip_block_comment()?;
+            if !is_skipped {
+                return Ok(());
+            }
+        }
     }
 
     fn skip_whitespace(&mut self) -> bool {
@@ -99,15 +146,38 @@ impl Scanner {
         if (Some('/'), Some('/')) != (self.peek_char(), self.peek_next_char()) {
             return false;
         }
+        // the line break is left for `skip_whitespace` to count the line
         while let Some(ch) = self.peek_char() {
-            self.advance_char();
             if ch == '\n' {
                 break;
             }
+            self.advance_char();
         }
         true
     }
 
+    /// `/* ... */` may span several lines, comments don't nest
+    fn skip_block_comment(&mut self) -> Result<bool, &'static str> {
+        if (Some('/'), Some('*')) != (self.peek_char(), self.peek_next_char()) {
+            return Ok(false);
+        }
+        // errors point to the comment start
+        self.code_start_idx = self.code_current_idx;
+        self.start_line = self.line;
+        self.code_current_idx += 2;
+        loop {
+            match self.advance_char() {
+                None => return Err("Unterminated comment"),
+                Some('*') if self.peek_char() == Some('/') => {
+                    self.advance_char();
+                    return Ok(true);
+                }
+                Some('\n') => self.line += 1,
+                _ => {}
+            }
+        }
+    }
+
     fn peek_char(&self) -> Option<char> {
         self.code.get(self.code_current_idx).cloned()
     }
@@ -137,6 +207,7 @@ impl Scanner {
         use TokenType::*;
         let t_type = match value.as_str() {
             "and" => And,
+            "assert_eq" => AssertEq,
             "break" => Break,
             "case" => Case,
             "class" => Class,
@@ -185,12 +256,7 @@ impl Scanner {
     }
 
     fn advance_number_token(&mut self) -> Token {
-        while let Some(ch) = self.peek_char() {
-            if !ch.is_ascii_digit() {
-                break;
-            }
-            self.advance_char();
-        }
+        let mut is_valid = self.advance_digits();
 
         if Some('.') == self.peek_char()
             && self
@@ -199,37 +265,49 @@ impl Scanner {
                 .unwrap_or(false)
         {
             self.advance_char();
+            is_valid &= self.advance_digits();
         }
 
+        if !is_valid {
+            return self.make_error_token("Digit separator '_' must be between digits");
+        }
+        self.make_token(TokenType::Number)
+    }
+
+    /// Skips digits which may be separated by single underscores, e.g. `1_000`.
+    /// Returns false if a separator isn't followed by a digit
+    fn advance_digits(&mut self) -> bool {
+        let mut is_valid = true;
         while let Some(ch) = self.peek_char() {
-            if !ch.is_ascii_digit() {
-                break;
+            match ch {
+                '_' => {
+                    is_valid &= self
+                        .peek_next_char()
+                        .map(|ch| ch.is_ascii_digit())
+                        .unwrap_or(false);
+                }
+                x if x.is_ascii_digit() => {}
+                _ => break,
             }
             self.advance_char();
         }
-
-        self.make_token(TokenType::Number)
+        is_valid
     }
 
-    fn match_char(&mut self, expected: char) -> bool {
-        let Some(ch) = self.peek_char() else {
-            return false;
-        };
-        if ch != expected {
-            return false;
-        }
-        self.code_current_idx += 1;
-        true
+    /// Longest operator from `OPERATORS` starting at the current position
+    fn advance_operator(&mut self) -> Option<Token> {
+        let (text, t_type) = OPERATORS
+            .iter()
+            .filter(|(text, _)| self.is_text_ahead(text))
+            .max_by_key(|(text, _)| text.len())?;
+        self.code_current_idx += text.chars().count();
+        Some(self.make_token(*t_type))
     }
 
-    fn condition_make_token(
-        &self,
-        condition: bool,
-        true_case: TokenType,
-        false_case: TokenType,
-    ) -> Token {
-        let t_type = if condition { true_case } else { false_case };
-        self.make_token(t_type)
+    fn is_text_ahead(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, ch)| self.code.get(self.code_current_idx + i) == Some(&ch))
     }
 
     fn make_token(&self, t_type: TokenType) -> Token {
@@ -250,7 +328,7 @@ impl Scanner {
 
     fn code_position(&self) -> CodePosition {
         CodePosition {
-            line: self.line,
+            line: self.start_line,
             absolute_index: self.code_start_idx,
         }
     }
@@ -281,6 +359,8 @@ mod test {
             (')', TokenType::RightParenthesis),
             ('{', TokenType::LeftBrace),
             ('}', TokenType::RightBrace),
+            ('[', TokenType::LeftBracket),
+            (']', TokenType::RightBracket),
             (':', TokenType::Colon),
             (';', TokenType::Semicolon),
             (',', TokenType::Comma),
@@ -347,6 +427,23 @@ mod test {
         assert_eq!(scanner.scan_token().t_type, TokenType::Comma);
     }
 
+    #[test]
+    fn scan_skip_shebang_line() {
+        let code = "#!/usr/bin/env fox-bytecode\n.".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let token = scanner.scan_token();
+        assert_eq!(token.t_type, TokenType::Dot);
+        assert_eq!(token.position.line, 2);
+    }
+
+    #[test]
+    fn scan_shebang_not_first_line() {
+        let code = ".\n#!/usr/bin/env fox-bytecode".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        assert_eq!(scanner.scan_token().t_type, TokenType::Dot);
+        assert!(scanner.scan_token().is_err());
+    }
+
     #[test]
     fn scan_literal_token() {
         let code = "\"1234\"".chars().collect::<Vec<_>>();
@@ -356,6 +453,25 @@ mod test {
         assert_eq!(token.text, "\"1234\"");
     }
 
+    #[test]
+    fn scan_raw_string_token() {
+        let code = r#"r"C:\path\n" r"#.chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let token = scanner.scan_token();
+        assert_eq!(token.t_type, TokenType::String);
+        assert_eq!(token.text, r#"r"C:\path\n""#);
+        let token = scanner.scan_token();
+        assert_eq!(token.t_type, TokenType::Identifier);
+        assert_eq!(token.text, "r");
+    }
+
+    #[test]
+    fn scan_raw_string_unterminated() {
+        let code = "r\"C:\\path".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        assert!(scanner.scan_token().is_err());
+    }
+
     #[test]
     fn scan_literal_token_unterminated() {
         let code = "\"1234".chars().collect::<Vec<_>>();
@@ -363,6 +479,34 @@ mod test {
         assert!(scanner.scan_token().is_err());
     }
 
+    #[test]
+    fn scan_eof_position_refers_last_token() {
+        let code = "fun f() {\n\n   \n".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let mut token = scanner.scan_token();
+        while token.t_type != TokenType::Eof {
+            token = scanner.scan_token();
+        }
+        assert_eq!(token.position.line, 1);
+        assert_eq!(token.position.absolute_index, 8);
+    }
+
+    #[test]
+    fn scan_unterminated_string_position() {
+        let code = "print 1;\nprint \"abc\n\ndef".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let mut token = scanner.scan_token();
+        while !token.is_err() {
+            token = scanner.scan_token();
+        }
+        assert_eq!(token.position.line, 2);
+        assert_eq!(token.position.absolute_index, 15);
+
+        let token = scanner.scan_token();
+        assert_eq!(token.t_type, TokenType::Eof);
+        assert_eq!(token.position.line, 2);
+    }
+
     #[test]
     fn scan_numbers() {
         let cases = ["1", "123", "12.23"];
@@ -374,6 +518,146 @@ mod test {
         }
     }
 
+    #[test]
+    fn scan_numbers_with_separators() {
+        for case in ["1_000", "1_000.000_1", "1_2_3"] {
+            let code = case.chars().collect::<Vec<_>>();
+            let mut scanner = Scanner::with_raw_code(code);
+            let token = scanner.scan_token();
+            assert_eq!(token.t_type, TokenType::Number);
+            assert_eq!(token.text, case);
+        }
+        for case in ["1_", "1__0", "1_.5", "1.5_"] {
+            let code = case.chars().collect::<Vec<_>>();
+            let mut scanner = Scanner::with_raw_code(code);
+            assert!(scanner.scan_token().is_err(), "{case}");
+        }
+        // leading underscore makes an identifier
+        let code = "_1".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        assert_eq!(scanner.scan_token().t_type, TokenType::Identifier);
+    }
+
+    #[test]
+    fn scan_range() {
+        let code = "0..10".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let types = (0..3)
+            .map(|_| scanner.scan_token().t_type)
+            .collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(types, vec![Number, DotDot, Number]);
+    }
+
+    #[test]
+    fn scan_safe_dot() {
+        let code = "a?.b ?".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let types = (0..4)
+            .map(|_| scanner.scan_token().t_type)
+            .collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(types, vec![Identifier, QuestionDot, Identifier, Error]);
+    }
+
+    #[test]
+    fn scan_floor_division() {
+        let code = "7 ~/ 2; // comment\n(a)~/b // comment"
+            .chars()
+            .collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let types = (0..10)
+            .map(|_| scanner.scan_token().t_type)
+            .collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(
+            types,
+            vec![
+                Number,
+                TildeSlash,
+                Number,
+                Semicolon,
+                LeftParenthesis,
+                Identifier,
+                RightParenthesis,
+                TildeSlash,
+                Identifier,
+                Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_comment_sequences_in_strings() {
+        let code = r#""http://x" "/*" /* "*/" */ "*/""#.chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let texts = (0..4)
+            .map(|_| {
+                let token = scanner.scan_token();
+                (token.t_type, token.text)
+            })
+            .collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(
+            texts,
+            vec![
+                (String, r#""http://x""#.to_string()),
+                (String, r#""/*""#.to_string()),
+                // the comment ends inside the quotes, so the rest is a string opening at `" */`
+                (String, r#"" */ ""#.to_string()),
+                (Star, "*".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_block_comment() {
+        let code = "a /* one\ntwo */ + // line\n/**/b /* open"
+            .chars()
+            .collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let tokens = (0..4).map(|_| scanner.scan_token()).collect::<Vec<_>>();
+        let lines = tokens
+            .iter()
+            .map(|token| (token.t_type, token.position.line))
+            .collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(
+            lines,
+            vec![(Identifier, 1), (Plus, 2), (Identifier, 3), (Error, 3)]
+        );
+        assert_eq!(tokens[3].text, "Unterminated comment");
+        assert_eq!(tokens[3].position.absolute_index, 32);
+    }
+
+    #[test]
+    fn scan_operators_longest_match() {
+        let code = "<<=<= < ===!=!...?.?".chars().collect::<Vec<_>>();
+        let mut scanner = Scanner::with_raw_code(code);
+        let types = (0..13)
+            .map(|_| scanner.scan_token().t_type)
+            .collect::<Vec<_>>();
+        use TokenType::*;
+        assert_eq!(
+            types,
+            vec![
+                Less,
+                LessEqual,
+                LessEqual,
+                Less,
+                EqualEqual,
+                Equal,
+                BangEqual,
+                Bang,
+                DotDot,
+                Dot,
+                QuestionDot,
+                Error,
+                Eof
+            ]
+        );
+    }
+
     #[test]
     fn scan_keywords_identifiers() {
         use TokenType::*;
@@ -399,6 +683,7 @@ mod test {
             ("true", True),
             ("var", Var),
             ("while", While),
+            ("assert_eq", AssertEq),
             ("my_identifier", Identifier),
             ("__myId2", Identifier),
         ];
@@ -15,6 +15,8 @@ pub enum TokenType {
     RightParenthesis,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Case,
     Colon,
     Comma,
@@ -34,12 +36,16 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    DotDot,
+    TildeSlash,
+    QuestionDot,
     // literals
     Identifier,
     String,
     Number,
     // Keywords
     And,
+    AssertEq,
     Break,
     Class,
     Continue,
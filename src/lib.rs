@@ -10,7 +10,12 @@ pub use utils::*;
 mod backend;
 pub use backend::*;
 
-pub use frontend::compile;
+pub use frontend::{
+    CompileOptions, Token, TokenType, compile, compile_detailed, compile_with,
+    compile_with_warnings, tokenize,
+};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub fn interpret(
     code_ref: Rc<Vec<char>>,
@@ -18,26 +23,40 @@ pub fn interpret(
     backend_service: Shared<dyn BackendService>,
     native_fn_provider: impl NativeFunctionsProvider,
 ) {
-    let result = compile(code_ref.clone());
+    let (result, warnings) = compile_with_warnings(code_ref.clone(), CompileOptions::default());
+    if !warnings.is_empty() {
+        interpreter_service
+            .borrow_mut()
+            .set_compile_warnings(&warnings);
+    }
     match result {
-        Ok(func) => {
-            let mut vm = Machine::with(func, backend_service.clone(), native_fn_provider);
-            let result = vm.run();
-
-            if result.is_err() {
-                backend_service
-                    .borrow_mut()
-                    .print_value(Value::text_from_str(
-                        "Completed with errors. See messages above",
-                    ));
-            }
-        }
+        Ok(func) => interpret_func(func, backend_service, native_fn_provider),
         Err(arr) => {
             interpreter_service.borrow_mut().set_compile_errors(&arr);
         }
     }
 }
 
+/// Runs an already compiled script, e.g. restored by `deserialize_func`
+pub fn interpret_func(
+    func: Func,
+    backend_service: Shared<dyn BackendService>,
+    native_fn_provider: impl NativeFunctionsProvider,
+) {
+    let mut vm = Machine::with(func, backend_service.clone(), native_fn_provider);
+    let result = vm.run();
+
+    if result.is_err() {
+        let mut service = backend_service.borrow_mut();
+        service.print_value(Value::text_from_str(
+            "Completed with errors. See messages above",
+        ));
+        service.flush();
+    }
+}
+
 pub trait InterpreterService {
     fn set_compile_errors(&mut self, errors: &[ErrorInfo]);
+
+    fn set_compile_warnings(&mut self, _warnings: &[ErrorInfo]) {}
 }
@@ -1,19 +1,30 @@
 mod native;
 use native::ProductionNativeFunctions;
 
-use std::{process::exit, rc::Rc};
+use std::{path::Path, process::exit, rc::Rc};
 
 use fox_bytecode::*;
 
+const BYTECODE_EXTENSION: &str = "foxc";
+
 fn main() {
     let args = std::env::args().collect::<Vec<_>>();
     match args.len() {
-        2 => run_file(&args[1]),
+        2 if is_bytecode_file(&args[1]) => run_bytecode_file(&args[1]),
+        2 => run_file(&args[1], VirtualMachineService::default()),
+        3 if args[1] == "--buffered" => run_file(&args[2], VirtualMachineService::buffered()),
+        3 if args[1] == "--emit-bytecode" => emit_bytecode(&args[2]),
         _ => show_usage(),
     }
 }
 
-fn run_file<T: AsRef<str>>(path: T) {
+fn is_bytecode_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == BYTECODE_EXTENSION)
+}
+
+fn run_file<T: AsRef<str>>(path: T, be_service: VirtualMachineService) {
     let Ok(code) = file_to_chars(&path) else {
         eprintln!("Failed to open file {}", path.as_ref());
         exit(-1);
@@ -21,7 +32,6 @@ fn run_file<T: AsRef<str>>(path: T) {
     let code_ref = Rc::new(code);
     let formatter = ErrorFormatter::with(code_ref.clone());
     let int_service = RuntimeInterpreterService::new(formatter);
-    let be_service = VirtualMachineService;
     interpret(
         code_ref,
         shared(int_service),
@@ -30,8 +40,60 @@ fn run_file<T: AsRef<str>>(path: T) {
     );
 }
 
+fn emit_bytecode(path: &str) {
+    let Ok(code) = file_to_chars(path) else {
+        eprintln!("Failed to open file {path}");
+        exit(-1);
+    };
+    let code_ref = Rc::new(code);
+    let mut int_service = RuntimeInterpreterService::new(ErrorFormatter::with(code_ref.clone()));
+    let (result, warnings) = compile_with_warnings(code_ref, CompileOptions::default());
+    int_service.set_compile_warnings(&warnings);
+    let func = match result {
+        Ok(func) => func,
+        Err(errors) => {
+            int_service.set_compile_errors(&errors);
+            exit(-1);
+        }
+    };
+    let bytes = match serialize_func(&func) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(-1);
+        }
+    };
+    let output = Path::new(path).with_extension(BYTECODE_EXTENSION);
+    if let Err(err) = std::fs::write(&output, bytes) {
+        eprintln!("Failed to write file {}: {err}", output.display());
+        exit(-1);
+    }
+}
+
+fn run_bytecode_file(path: &str) {
+    let Ok(bytes) = std::fs::read(path) else {
+        eprintln!("Failed to open file {path}");
+        exit(-1);
+    };
+    let func = match deserialize_func(&bytes) {
+        Ok(func) => func,
+        Err(err) => {
+            eprintln!("Failed to load {path}: {err}");
+            exit(-1);
+        }
+    };
+    interpret_func(
+        func,
+        shared(VirtualMachineService::default()),
+        ProductionNativeFunctions,
+    );
+}
+
 fn show_usage() {
     println!("Usage: fox-bytecode <script.fox>");
+    println!("       fox-bytecode <script.{BYTECODE_EXTENSION}>");
+    println!("       fox-bytecode --buffered <script.fox>");
+    println!("       fox-bytecode --emit-bytecode <script.fox>");
 }
 
 struct RuntimeInterpreterService {
@@ -51,4 +113,11 @@ impl InterpreterService for RuntimeInterpreterService {
             eprintln!("{text}");
         }
     }
+
+    fn set_compile_warnings(&mut self, warnings: &[ErrorInfo]) {
+        for warning in warnings {
+            let text = self.formatter.format_error(warning);
+            eprintln!("Warning:\n{text}");
+        }
+    }
 }
@@ -1,44 +1,796 @@
-use std::io::Write;
+use std::{io::Write, rc::Rc};
 
-use crate::{NativeFn, NativeFunctionsProvider, Value};
+use crate::{
+    Double, HostContext, HostFn, NativeAction, NativeFn, NativeFunctionsProvider, RandomNatives,
+    VERSION, Value,
+};
 
 pub struct ProductionNativeFunctions;
 
 impl NativeFunctionsProvider for ProductionNativeFunctions {
     fn get_functions(&self) -> Vec<(String, NativeFn)> {
+        let mut functions: Vec<(String, NativeFn)> = vec![
+            ("trunc".to_string(), native_trunc),
+            ("floor".to_string(), native_floor),
+            ("ceil".to_string(), native_ceil),
+            ("round".to_string(), native_round),
+            ("is_integer".to_string(), native_is_integer),
+            ("inf".to_string(), native_inf),
+            ("nan".to_string(), native_nan),
+            ("to_int".to_string(), native_to_int),
+            ("version".to_string(), native_version),
+            ("format_number".to_string(), native_format_number),
+            ("sprintf".to_string(), native_sprintf),
+            ("split".to_string(), native_split),
+            ("approx_equal".to_string(), native_approx_equal),
+            ("clamp".to_string(), native_clamp),
+            ("remove_field".to_string(), native_remove_field),
+            ("inspect".to_string(), native_inspect),
+            ("methods".to_string(), native_methods),
+            ("has_method".to_string(), native_has_method),
+            ("same_function".to_string(), native_same_function),
+            ("weak".to_string(), native_weak),
+            ("deref".to_string(), native_deref),
+        ];
+        functions.extend(RandomNatives.get_functions());
+        functions
+    }
+
+    fn get_host_functions(&self) -> Vec<(String, HostFn)> {
         vec![
+            ("get_global".to_string(), native_get_global),
+            ("set_global".to_string(), native_set_global),
+            ("apply".to_string(), native_apply),
             ("write".to_string(), native_write),
             ("writeln".to_string(), native_writeln),
             ("readln".to_string(), native_read_line),
+            ("flush".to_string(), native_flush),
         ]
     }
 }
 
-fn native_write(args: &[Value]) -> Value {
+/// `get_global(name)` reads a global by its name, returns nil if it isn't defined
+fn native_get_global(context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+    let [name] = args else {
+        return Value::Nil.into();
+    };
+    name.as_text()
+        .and_then(|name| context.get_global(&name))
+        .unwrap_or_default()
+        .into()
+}
+
+/// `set_global(name, value)` assigns or defines a global, returns the assigned value
+fn native_set_global(context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+    let [name, value] = args else {
+        return Value::Nil.into();
+    };
+    let Some(name) = name.as_text() else {
+        return Value::Nil.into();
+    };
+    context.set_global(name, value.clone());
+    value.clone().into()
+}
+
+/// `apply(f, [a, b])` calls `f(a, b)`, returns nil if the arguments aren't given as a list
+fn native_apply(_context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+    let [callee, args] = args else {
+        return Value::Nil.into();
+    };
+    let Some(args) = args.as_list() else {
+        return Value::Nil.into();
+    };
+    NativeAction::Call(callee.clone(), args.items())
+}
+
+/// `flush()` writes out prints buffered by the backend
+fn native_flush(context: &mut dyn HostContext, _args: &[Value]) -> NativeAction {
+    context.flush_output();
+    Value::Nil.into()
+}
+
+// Functions below write to stdout directly, so buffered prints go first
+
+fn native_write(context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+    context.flush_output();
     args.iter().for_each(|x| print!("{x}"));
-    Value::Nil
+    Value::Nil.into()
 }
 
-fn native_writeln(args: &[Value]) -> Value {
-    native_write(args);
+fn native_writeln(context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+    native_write(context, args);
     println!();
-    Value::Nil
+    Value::Nil.into()
 }
 
 /// args are prompt
-fn native_read_line(args: &[Value]) -> Value {
-    native_write(args);
+fn native_read_line(context: &mut dyn HostContext, args: &[Value]) -> NativeAction {
+    native_write(context, args);
     if std::io::stdout().flush().is_err() {
-        return Value::Nil;
+        return Value::Nil.into();
     }
 
     // Wait for user input
     let stdin = std::io::stdin();
     let mut input = String::new();
     if stdin.read_line(&mut input).is_err() {
-        return Value::Nil;
+        return Value::Nil.into();
     }
     // don't include eof character, maybe windows will produce \r\n
     let len = input.len();
-    Value::text_from_str(&input[0..len - 1])
+    Value::text_from_str(&input[0..len - 1]).into()
+}
+
+fn native_version(_args: &[Value]) -> Value {
+    Value::text_from_str(VERSION)
+}
+
+/// Rounds toward zero: `trunc(-2.7)` is `-2`
+fn native_trunc(args: &[Value]) -> Value {
+    unary_math(args, Double::trunc)
+}
+
+/// Rounds toward negative infinity: `floor(-2.7)` is `-3`
+fn native_floor(args: &[Value]) -> Value {
+    unary_math(args, Double::floor)
+}
+
+/// Rounds toward positive infinity: `ceil(-2.7)` is `-2`
+fn native_ceil(args: &[Value]) -> Value {
+    unary_math(args, Double::ceil)
+}
+
+/// Rounds to the nearest integer, half-way cases away from zero: `round(-2.5)` is `-3`
+fn native_round(args: &[Value]) -> Value {
+    unary_math(args, Double::round)
+}
+
+/// Positive infinity. Division by zero is still an error, so `inf()` is the way to get one.
+/// Arithmetic follows IEEE 754: `inf() - inf()` is nan, `-inf() < 0` is true
+fn native_inf(_args: &[Value]) -> Value {
+    Value::number(Double::INFINITY)
+}
+
+/// Not-a-number, it isn't equal to any value including itself: `nan() == nan()` is false
+fn native_nan(_args: &[Value]) -> Value {
+    Value::number(Double::NAN)
+}
+
+/// `is_integer(4.0)` is true, `is_integer(4.5)` is false. Returns nil for non-numbers
+fn native_is_integer(args: &[Value]) -> Value {
+    let [arg] = args else {
+        return Value::Nil;
+    };
+    if arg.as_number().is_none() {
+        return Value::Nil;
+    }
+    Value::Bool(arg.is_integer())
+}
+
+/// Truncates toward zero like `trunc`: `to_int(-4.9)` is `-4`
+fn native_to_int(args: &[Value]) -> Value {
+    unary_math(args, Double::trunc)
+}
+
+/// Limits the padding, so a script can't request a huge string
+const MAX_DECIMALS: Double = 32.0;
+
+/// Formats number with fixed count of decimal places: `format_number(5, 3)` is `"5.000"`.
+/// Returns nil if `decimals` isn't an integer in `0..=MAX_DECIMALS`
+fn native_format_number(args: &[Value]) -> Value {
+    let [number, decimals] = args else {
+        return Value::Nil;
+    };
+    let (Some(x), Some(decimals)) = (number.as_number(), decimals.as_number()) else {
+        return Value::Nil;
+    };
+    if decimals < 0.0 || decimals.fract() != 0.0 || decimals > MAX_DECIMALS {
+        return Value::Nil;
+    }
+    Value::text_from_string(format!("{:.*}", decimals as usize, x))
+}
+
+/// Verbose text representation, instances are shown with their fields
+fn native_inspect(args: &[Value]) -> Value {
+    let [value] = args else {
+        return Value::Nil;
+    };
+    Value::text_from_string(format!("{value:#}"))
+}
+
+/// `remove_field(instance, "name")` deletes the field, so access falls back to methods again
+fn native_remove_field(args: &[Value]) -> Value {
+    if let [instance, name] = args
+        && let (Some(instance), Some(name)) = (instance.as_instance(), name.as_text())
+    {
+        instance.remove_field(&name);
+    }
+    Value::Nil
+}
+
+/// `methods(instance)` returns a list of sorted method names of the instance class.
+/// Returns nil for non-instances
+fn native_methods(args: &[Value]) -> Value {
+    let [instance] = args else {
+        return Value::Nil;
+    };
+    let Some(instance) = instance.as_instance() else {
+        return Value::Nil;
+    };
+    let names = instance
+        .class()
+        .method_names()
+        .into_iter()
+        .map(Value::Text)
+        .collect();
+    Value::list(names)
+}
+
+/// `has_method(instance, "name")` checks the class method table, fields aren't taken into account
+fn native_has_method(args: &[Value]) -> Value {
+    let [instance, name] = args else {
+        return Value::Nil;
+    };
+    let (Some(instance), Some(name)) = (instance.as_instance(), name.as_text()) else {
+        return Value::Nil;
+    };
+    Value::Bool(instance.class().has_method(&name))
+}
+
+/// `split("a,b", ",")` returns a list of the parts, an empty separator splits into characters.
+/// A trailing separator gives a trailing empty text. Returns nil if arguments aren't texts
+fn native_split(args: &[Value]) -> Value {
+    let [text, separator] = args else {
+        return Value::Nil;
+    };
+    let (Some(text), Some(separator)) = (text.as_text(), separator.as_text()) else {
+        return Value::Nil;
+    };
+    let parts = if separator.is_empty() {
+        text.chars()
+            .map(|ch| Value::text_from_string(ch.to_string()))
+            .collect()
+    } else {
+        text.split(separator.as_str())
+            .map(Value::text_from_str)
+            .collect()
+    };
+    Value::list(parts)
+}
+
+/// `sprintf("%s=%d", "x", 5.7)` is `"x=5"`. Supports `%s` for any value, `%d` for numbers
+/// truncated to integers, `%f` for numbers with 6 decimals and `%%`.
+/// Returns nil if arguments don't match the specifiers
+fn native_sprintf(args: &[Value]) -> Value {
+    let Some((format, mut args)) = args.split_first() else {
+        return Value::Nil;
+    };
+    let Some(format) = format.as_text() else {
+        return Value::Nil;
+    };
+    let mut result = String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            result.push(ch);
+            continue;
+        }
+        let spec = chars.next();
+        if spec == Some('%') {
+            result.push('%');
+            continue;
+        }
+        let Some((arg, rest)) = args.split_first() else {
+            return Value::Nil;
+        };
+        args = rest;
+        match (spec, arg.as_number()) {
+            (Some('s'), _) => result.push_str(&arg.to_string()),
+            (Some('d'), Some(x)) => result.push_str(&format!("{}", x.trunc())),
+            (Some('f'), Some(x)) => result.push_str(&format!("{x:.6}")),
+            _ => return Value::Nil,
+        }
+    }
+    if !args.is_empty() {
+        return Value::Nil;
+    }
+    Value::text_from_string(result)
+}
+
+/// `same_function(a, b)` is true if both closures are made of the same function declaration,
+/// while `a == b` compares closures by identity. Returns nil for non-closures
+fn native_same_function(args: &[Value]) -> Value {
+    let [a, b] = args else {
+        return Value::Nil;
+    };
+    let (Some(a), Some(b)) = (a.as_closure(), b.as_closure()) else {
+        return Value::Nil;
+    };
+    Value::Bool(Rc::ptr_eq(&a.func_ref(), &b.func_ref()))
+}
+
+/// `weak(instance)` makes a reference that doesn't keep the instance alive,
+/// so back-references like `child.parent = weak(parent)` don't leak. Returns nil for non-instances
+fn native_weak(args: &[Value]) -> Value {
+    let [instance] = args else {
+        return Value::Nil;
+    };
+    instance
+        .as_instance()
+        .map(|instance| Value::Weak(Rc::downgrade(&instance)))
+        .unwrap_or_default()
+}
+
+/// `deref(ref)` returns the instance of a weak reference or nil if it's already released
+fn native_deref(args: &[Value]) -> Value {
+    let [Value::Weak(instance)] = args else {
+        return Value::Nil;
+    };
+    instance.upgrade().map(Value::Instance).unwrap_or_default()
+}
+
+/// Tolerant comparison `|a - b| <= epsilon`, returns nil for non-numeric arguments
+fn native_approx_equal(args: &[Value]) -> Value {
+    let [a, b, epsilon] = args else {
+        return Value::Nil;
+    };
+    let (Some(a), Some(b), Some(epsilon)) = (a.as_number(), b.as_number(), epsilon.as_number())
+    else {
+        return Value::Nil;
+    };
+    Value::Bool((a - b).abs() <= epsilon)
+}
+
+/// `clamp(x, lo, hi)` bounds `x` to `[lo, hi]`. An empty range (`lo > hi`) is treated as
+/// a script mistake and gives nil instead of swapping the bounds, as do non-numeric arguments
+fn native_clamp(args: &[Value]) -> Value {
+    let [x, lo, hi] = args else {
+        return Value::Nil;
+    };
+    let (Some(x), Some(lo), Some(hi)) = (x.as_number(), lo.as_number(), hi.as_number()) else {
+        return Value::Nil;
+    };
+    // `Double::clamp` panics on such bounds
+    if lo.is_nan() || hi.is_nan() || lo > hi {
+        return Value::Nil;
+    }
+    Value::number(x.clamp(lo, hi))
+}
+
+/// Applies `op` to the single numeric argument, returns nil for any other input
+fn unary_math(args: &[Value], op: fn(Double) -> Double) -> Value {
+    let [arg] = args else {
+        return Value::Nil;
+    };
+    let Some(x) = arg.as_number() else {
+        return Value::Nil;
+    };
+    Value::number(op(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, rc::Rc};
+
+    use fox_bytecode::{
+        Class, Closure, Func, Instance, Machine, OperationError, WriterService, heap_stats,
+        set_heap_stats_enabled, shared,
+    };
+
+    use super::*;
+
+    #[test]
+    fn version_matches_crate() {
+        let value = native_version(&[]);
+        let text = value.as_text().expect("version must be a text");
+        assert!(!text.is_empty());
+        assert_eq!(text.as_str(), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn rounding_negative_numbers() {
+        let cases: [(NativeFn, Double, Double); 8] = [
+            (native_trunc, -2.7, -2.0),
+            (native_floor, -2.7, -3.0),
+            (native_ceil, -2.7, -2.0),
+            (native_round, -2.7, -3.0),
+            (native_trunc, -2.5, -2.0),
+            (native_floor, -2.5, -3.0),
+            (native_ceil, -2.5, -2.0),
+            (native_round, -2.5, -3.0),
+        ];
+        for (func, inp, exp) in cases {
+            assert_eq!(func(&[Value::number(inp)]), Value::number(exp));
+        }
+    }
+
+    #[test]
+    fn rounding_positive_numbers() {
+        let cases: [(NativeFn, Double, Double); 4] = [
+            (native_trunc, 2.7, 2.0),
+            (native_floor, 2.7, 2.0),
+            (native_ceil, 2.2, 3.0),
+            (native_round, 2.5, 3.0),
+        ];
+        for (func, inp, exp) in cases {
+            assert_eq!(func(&[Value::number(inp)]), Value::number(exp));
+        }
+    }
+
+    #[test]
+    fn integer_natives() {
+        let is_integer = |x: Double| native_is_integer(&[Value::number(x)]);
+        assert_eq!(is_integer(4.0), Value::Bool(true));
+        assert_eq!(is_integer(-3.0), Value::Bool(true));
+        assert_eq!(is_integer(4.5), Value::Bool(false));
+        assert_eq!(is_integer(Double::INFINITY), Value::Bool(false));
+        assert_eq!(is_integer(Double::NAN), Value::Bool(false));
+        assert_eq!(native_is_integer(&[Value::text_from_str("4")]), Value::Nil);
+        assert_eq!(native_is_integer(&[]), Value::Nil);
+
+        let to_int = |x: Double| native_to_int(&[Value::number(x)]);
+        assert_eq!(to_int(4.9), Value::number(4.0));
+        assert_eq!(to_int(-4.9), Value::number(-4.0));
+        assert_eq!(native_to_int(&[Value::Bool(true)]), Value::Nil);
+    }
+
+    #[test]
+    fn format_number_decimals() {
+        let format = |x: Double, decimals: Double| {
+            native_format_number(&[Value::number(x), Value::number(decimals)])
+        };
+        assert_eq!(format(5.0, 3.0), Value::text_from_str("5.000"));
+        assert_eq!(format(2.5, 0.0), Value::text_from_str("2"));
+        assert_eq!(format(-1.25, 1.0), Value::text_from_str("-1.2"));
+        assert_eq!(format(1.0, -1.0), Value::Nil);
+        assert_eq!(format(1.0, 1.5), Value::Nil);
+        assert_eq!(
+            format(1.0, 32.0),
+            Value::text_from_string(format!("1.{}", "0".repeat(32)))
+        );
+        assert_eq!(format(1.0, 33.0), Value::Nil);
+        assert_eq!(native_format_number(&[Value::number(1.0)]), Value::Nil);
+    }
+
+    #[test]
+    fn format_number_in_script() {
+        let src = r#"
+            print format_number(3.14159, 2);
+            print format_number(5, 3);
+        "#;
+        assert_eq!(
+            run_script(src),
+            Ok(vec!["3.14".to_string(), "5.000".to_string()])
+        );
+    }
+
+    #[test]
+    fn approx_equal_tolerance() {
+        let sum = Value::number(0.1 + 0.6);
+        let expected = Value::number(0.7);
+        assert_ne!(sum, expected);
+        let epsilon = Value::number(1e-6);
+        assert_eq!(
+            native_approx_equal(&[sum.clone(), expected.clone(), epsilon]),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            native_approx_equal(&[Value::number(1.0), expected.clone(), Value::number(0.1)]),
+            Value::Bool(false)
+        );
+        assert_eq!(native_approx_equal(&[sum, expected]), Value::Nil);
+    }
+
+    #[test]
+    fn clamp_range() {
+        let clamp = |x: Double, lo: Double, hi: Double| {
+            native_clamp(&[Value::number(x), Value::number(lo), Value::number(hi)])
+        };
+        assert_eq!(clamp(-5.0, 0.0, 10.0), Value::number(0.0));
+        assert_eq!(clamp(5.0, 0.0, 10.0), Value::number(5.0));
+        assert_eq!(clamp(15.0, 0.0, 10.0), Value::number(10.0));
+        assert_eq!(clamp(3.0, 3.0, 3.0), Value::number(3.0));
+        // invalid range
+        assert_eq!(clamp(5.0, 10.0, 0.0), Value::Nil);
+        assert_eq!(clamp(5.0, Double::NAN, 10.0), Value::Nil);
+        let args = [
+            Value::text_from_str("5"),
+            Value::number(0.0),
+            Value::number(1.0),
+        ];
+        assert_eq!(native_clamp(&args), Value::Nil);
+        assert_eq!(native_clamp(&args[1..]), Value::Nil);
+    }
+
+    #[test]
+    fn remove_field_from_instance() {
+        let class = Rc::new(Class::new(Rc::new("Toast".to_string())));
+        let instance = Rc::new(Instance::new(class));
+        let name = Rc::new("jam".to_string());
+        instance.set_field(name.clone(), Value::text_from_str("grape"));
+        let args = [
+            Value::Instance(instance.clone()),
+            Value::text_from_str("jam"),
+        ];
+        assert_eq!(native_remove_field(&args), Value::Nil);
+        assert!(instance.get_field(&name).is_none());
+        // missing field
+        assert_eq!(native_remove_field(&args), Value::Nil);
+        assert!(instance.fields().is_empty());
+    }
+
+    #[test]
+    fn inspect_self_referencing_instance() {
+        let class = Rc::new(Class::new(Rc::new("A".to_string())));
+        let instance = Rc::new(Instance::new(class));
+        let name = Rc::new("me".to_string());
+        instance.set_field(name.clone(), Value::Instance(instance.clone()));
+        let text = native_inspect(&[Value::Instance(instance.clone())]);
+        assert_eq!(text, Value::text_from_str("A{me: ...}"));
+        instance.remove_field(&name);
+    }
+
+    #[test]
+    fn special_numbers() -> Result<(), OperationError> {
+        let inf = native_inf(&[]);
+        let nan = native_nan(&[]);
+        assert_eq!(inf.to_string(), "inf");
+        let zero = Value::number(0.0);
+        assert_eq!(Value::subtract(&zero, &inf)?.to_string(), "-inf");
+        assert_eq!(nan.to_string(), "nan");
+        assert_eq!(Value::equals(&nan, &nan)?, Value::Bool(false));
+        assert_eq!(Value::not_equals(&nan, &nan)?, Value::Bool(true));
+        assert_eq!(Value::subtract(&inf, &inf)?.to_string(), "nan");
+        assert_eq!(Value::less(&Value::number(1e30), &inf)?, Value::Bool(true));
+        Ok(())
+    }
+
+    #[test]
+    fn sprintf_specifiers() {
+        let text = Value::text_from_str;
+        let num = Value::number;
+        assert_eq!(
+            native_sprintf(&[text("%s=%d"), text("x"), num(5.0)]),
+            text("x=5")
+        );
+        assert_eq!(
+            native_sprintf(&[text("%d%% of %f"), num(-7.9), num(0.5)]),
+            text("-7% of 0.500000")
+        );
+        assert_eq!(
+            native_sprintf(&[text("%s %s"), Value::Nil, Value::Bool(true)]),
+            text("nil true")
+        );
+        // mismatches
+        assert_eq!(native_sprintf(&[text("%s=%d"), text("x")]), Value::Nil);
+        assert_eq!(
+            native_sprintf(&[text("%s"), text("x"), num(5.0)]),
+            Value::Nil
+        );
+        assert_eq!(native_sprintf(&[text("%d"), text("x")]), Value::Nil);
+        assert_eq!(native_sprintf(&[text("%x"), num(1.0)]), Value::Nil);
+        assert_eq!(native_sprintf(&[text("100%")]), Value::Nil);
+    }
+
+    #[test]
+    fn split_parts() {
+        let text = Value::text_from_str;
+        let split = |value, separator| native_split(&[text(value), text(separator)]).to_string();
+        assert_eq!(split("a,b,c", ","), "[\"a\", \"b\", \"c\"]");
+        assert_eq!(split("a::b::", "::"), "[\"a\", \"b\", \"\"]");
+        assert_eq!(split("fox", ""), "[\"f\", \"o\", \"x\"]");
+        assert_eq!(split("fox", ","), "[\"fox\"]");
+        assert_eq!(split("", ""), "[]");
+        assert_eq!(native_split(&[text("a"), Value::Nil]), Value::Nil);
+        let parts = native_split(&[text("x y"), text(" ")]).as_list().unwrap();
+        assert_eq!(parts.get(1), Some(text("y")));
+    }
+
+    #[test]
+    fn same_function_closures() {
+        let counter = Rc::new(Func::default());
+        let closure = |func: &Rc<Func>| Value::Closure(Rc::new(Closure::new(func.clone())));
+        let (first, second) = (closure(&counter), closure(&counter));
+        assert_ne!(first, second);
+        assert_eq!(
+            native_same_function(&[first.clone(), second]),
+            Value::Bool(true)
+        );
+        let other = closure(&Rc::new(Func::default()));
+        assert_eq!(
+            native_same_function(&[first.clone(), other]),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            native_same_function(&[first, Value::number(1.0)]),
+            Value::Nil
+        );
+    }
+
+    #[test]
+    fn weak_back_reference_is_released() {
+        set_heap_stats_enabled(true);
+        let baseline = heap_stats();
+        let class = Rc::new(Class::new(Rc::new("Node".to_string())));
+        let parent = Rc::new(Instance::new(class.clone()));
+        let child = Rc::new(Instance::new(class));
+        parent.set_field(Rc::new("child".to_string()), Value::Instance(child.clone()));
+        let back = native_weak(&[Value::Instance(parent.clone())]);
+        child.set_field(Rc::new("parent".to_string()), back.clone());
+        assert_eq!(heap_stats().instances, baseline.instances + 2);
+        assert_eq!(
+            native_deref(std::slice::from_ref(&back)),
+            Value::Instance(parent.clone())
+        );
+
+        drop(parent);
+        assert_eq!(heap_stats().instances, baseline.instances + 1);
+        assert_eq!(native_deref(std::slice::from_ref(&back)), Value::Nil);
+        assert_eq!(back.to_string(), "<weak released>");
+        drop(child);
+        assert_eq!(heap_stats(), baseline);
+
+        assert_eq!(native_weak(&[Value::number(1.0)]), Value::Nil);
+        assert_eq!(native_deref(&[Value::number(1.0)]), Value::Nil);
+    }
+
+    #[test]
+    fn method_reflection() {
+        let class = Rc::new(Class::new(Rc::new("Toast".to_string())));
+        for name in ["spread", "bake"] {
+            class.add_method(Rc::new(name.to_string()), Value::Nil);
+        }
+        let instance = Value::Instance(Rc::new(Instance::new(class)));
+        let names = native_methods(std::slice::from_ref(&instance));
+        assert_eq!(names.to_string(), "[\"bake\", \"spread\"]");
+        assert_eq!(native_methods(&[Value::number(1.0)]), Value::Nil);
+        let has = |name| native_has_method(&[instance.clone(), Value::text_from_str(name)]);
+        assert_eq!(has("bake"), Value::Bool(true));
+        assert_eq!(has("spread"), Value::Bool(true));
+        assert_eq!(has("toast"), Value::Bool(false));
+        let not_instance = [Value::number(1.0), Value::text_from_str("bake")];
+        assert_eq!(native_has_method(&not_instance), Value::Nil);
+    }
+
+    #[derive(Default)]
+    struct Globals(HashMap<Rc<String>, Value>);
+
+    impl HostContext for Globals {
+        fn get_global(&self, name: &Rc<String>) -> Option<Value> {
+            self.0.get(name).cloned()
+        }
+
+        fn set_global(&mut self, name: Rc<String>, value: Value) {
+            self.0.insert(name, value);
+        }
+    }
+
+    #[test]
+    fn dynamic_globals_access() {
+        let mut globals = Globals::default();
+        let name = Value::text_from_str("answer");
+        assert_eq!(
+            native_get_global(&mut globals, std::slice::from_ref(&name)),
+            Value::Nil.into()
+        );
+        let value = Value::number(42.0);
+        assert_eq!(
+            native_set_global(&mut globals, &[name.clone(), value.clone()]),
+            value.clone().into()
+        );
+        assert_eq!(
+            native_get_global(&mut globals, &[name]),
+            value.clone().into()
+        );
+        assert_eq!(native_get_global(&mut globals, &[value]), Value::Nil.into());
+    }
+
+    #[test]
+    fn apply_requests_call() {
+        let mut globals = Globals::default();
+        let callee = Value::text_from_str("f");
+        let list = Value::list(vec![Value::number(1.0), Value::number(2.0)]);
+        assert_eq!(
+            native_apply(&mut globals, &[callee.clone(), list]),
+            NativeAction::Call(callee.clone(), vec![Value::number(1.0), Value::number(2.0)])
+        );
+        assert_eq!(native_apply(&mut globals, &[]), Value::Nil.into());
+        let spread = [callee, Value::number(1.0), Value::number(2.0)];
+        assert_eq!(native_apply(&mut globals, &spread), Value::Nil.into());
+    }
+
+    /// Runs the script with the production natives, returns printed lines or the runtime error
+    fn run_script(src: &str) -> Result<Vec<String>, String> {
+        let func =
+            fox_bytecode::compile(Rc::new(src.chars().collect())).expect("Failed to compile");
+        let service = shared(WriterService::new(Vec::new()));
+        let mut machine = Machine::with(func, service.clone(), ProductionNativeFunctions);
+        machine.run().map_err(|err| err.message().to_string())?;
+        let output = String::from_utf8(service.borrow().writer().clone()).unwrap();
+        Ok(output.lines().map(|line| line.to_string()).collect())
+    }
+
+    #[test]
+    fn script_globals_access() {
+        let src = r#"
+            var defined = "value";
+            print get_global("defined");
+            print get_global("missing");
+            set_global("created", 42);
+            print created;
+            set_global("defined", "changed");
+            print defined;
+        "#;
+        assert_eq!(
+            run_script(src),
+            Ok(vec![
+                "value".to_string(),
+                "nil".to_string(),
+                "42".to_string(),
+                "changed".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn script_methods() {
+        let src = r#"
+            class Bread {
+                bake() {}
+                spread() {}
+            }
+            var names = methods(Bread());
+            print names;
+            print names.length();
+        "#;
+        assert_eq!(
+            run_script(src),
+            Ok(vec!["[\"bake\", \"spread\"]".to_string(), "2".to_string()])
+        );
+    }
+
+    #[test]
+    fn script_apply() {
+        let src = r#"
+            fun add(a, b) {
+                return a + b;
+            }
+            fun forward(f, args) {
+                return apply(f, args);
+            }
+            print apply(add, [2, 3]);
+            print forward(add, ["a", "b"]) + "c";
+            print apply(add, 2, 3);
+            print apply(add, 2);
+        "#;
+        assert_eq!(
+            run_script(src),
+            Ok(vec![
+                "5".to_string(),
+                "abc".to_string(),
+                "nil".to_string(),
+                "nil".to_string()
+            ])
+        );
+        let src = r#"
+            fun add(a, b) {
+                return a + b;
+            }
+            apply(add, [1]);
+        "#;
+        assert_eq!(
+            run_script(src),
+            Err("Expected 2 arguments but got 1 calling 'add', missing 'b'".to_string())
+        );
+    }
+
+    #[test]
+    fn rounding_invalid_arguments() {
+        let funcs: [NativeFn; 4] = [native_trunc, native_floor, native_ceil, native_round];
+        for func in funcs {
+            assert_eq!(func(&[]), Value::Nil);
+            assert_eq!(func(&[Value::text_from_str("1.5")]), Value::Nil);
+            assert_eq!(func(&[Value::number(1.0), Value::number(2.0)]), Value::Nil);
+        }
+    }
 }
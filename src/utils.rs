@@ -1,4 +1,9 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::hash_map::{DefaultHasher, RandomState},
+    hash::BuildHasher,
+    rc::Rc,
+};
 
 // IO
 pub fn file_to_chars<T: AsRef<str>>(path: T) -> std::io::Result<Vec<char>> {
@@ -25,3 +30,28 @@ pub type Shared<T> = Rc<RefCell<T>>;
 pub fn shared<T>(value: T) -> Shared<T> {
     Rc::new(RefCell::new(value))
 }
+
+// hashing
+/// Randomly seeded by default, the fixed variant gives the same iteration order on every run
+#[derive(Clone)]
+pub enum MapHasher {
+    Random(RandomState),
+    Fixed,
+}
+
+impl Default for MapHasher {
+    fn default() -> Self {
+        Self::Random(RandomState::new())
+    }
+}
+
+impl BuildHasher for MapHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            Self::Random(state) => state.build_hasher(),
+            Self::Fixed => DefaultHasher::new(),
+        }
+    }
+}
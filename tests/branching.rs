@@ -20,6 +20,21 @@ fn if_statement_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn comment_after_condition_test() {
+    let src = r#"
+    var x = 1;
+    if (x == 1) // check
+        print "one";
+    while (x < 3) // loop
+        x = x + 1;
+    print x; // done
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["one", "3"]);
+}
+
 #[test]
 fn if_else_statement_test() {
     let src = r#"
@@ -47,6 +62,67 @@ fn if_else_statement_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn else_if_chain_test() {
+    let src = r#"
+        fun classify(n) {
+            var before = "before";
+            if (n == 0) {
+                print "zero";
+            } else if (n == 1) {
+                print "one";
+            } else if (n == 2) {
+                print "two";
+            } else if (n == 3) {
+                print "three";
+            } else {
+                print "many";
+            }
+            var after = "after";
+            print before + " " + after;
+        }
+        for (var i = 0; i < 5; i = i + 1) {
+            classify(i);
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &[
+        "zero",
+        "before after",
+        "one",
+        "before after",
+        "two",
+        "before after",
+        "three",
+        "before after",
+        "many",
+        "before after",
+    ];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn else_if_chain_without_else_test() {
+    let src = r#"
+        fun check(n) {
+            var marker = n;
+            if (n == 1) print "one";
+            else if (n == 2) print "two";
+            else if (n == 3) print "three";
+            else if (n == 4) print "four";
+            var tail = marker * 10;
+            return tail;
+        }
+        print check(4);
+        print check(7);
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["four", "40", "70"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
 #[test]
 fn logical_operators_test() {
     let src = r#"
@@ -72,6 +148,23 @@ fn logical_operators_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn logical_operators_operand_values_test() {
+    let src = r#"
+        print nil or "x";
+        print 0 and "y";
+        print nil and "z";
+        print "a" or "b";
+        print false or nil;
+        print 1 and 2 and 3;
+        print nil or false or "last";
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["x", "y", "nil", "a", "nil", "3", "last"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
 #[test]
 fn while_loop_test() {
     let src = r#"
@@ -116,6 +209,95 @@ fn for_loop_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn for_in_range_test() {
+    let src = r#"
+        var start = 1;
+        for (i in start..start + 3) {
+            print i;
+        }
+        for (i in 5..5) {
+            print "never";
+        }
+        print "Done";
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["1", "2", "3", "Done"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn for_in_range_break_continue_test() {
+    let src = r#"
+        var total = 0;
+        for (i in 0..10) {
+            if (i == 2) continue;
+            if (i == 5) break;
+            total = total + i;
+        }
+        print total;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["8"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn for_in_list_test() {
+    let src = r#"
+        var items = ["a", "b", "c"];
+        for (x in items) {
+            print x;
+        }
+        for (x in []) {
+            print "never";
+        }
+        var total = 0;
+        for (x in [1, 2, 3, 4, 5, 6]) {
+            if (x == 2) continue;
+            if (x == 5) break;
+            total = total + x;
+        }
+        print total;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["a", "b", "c", "8"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn for_in_list_pushed_in_body_test() {
+    let src = r#"
+        var items = [1];
+        for (x in items) {
+            print x;
+            if (x < 3) items.push(x + 1);
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["1", "2", "3"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn for_in_without_range_or_list_test() {
+    let src = r#"
+        for (x in 3) {
+            print x;
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_compile_error_line());
+    assert_eq!(
+        Some("Only instances have methods"),
+        probe.borrow().top_error_message()
+    );
+}
+
 #[test]
 fn break_inside_while_loop_test() {
     let src = r#"
@@ -259,6 +441,74 @@ fn break_outside_loop_test() {
     );
 }
 
+#[test]
+fn loop_expression_break_value_test() {
+    let src = r#"
+        var found = while (true) {
+            var n = 7;
+            break n * 6;
+        };
+        print found;
+        fun first_square_above(limit) {
+            var result = for (var i = 1; i < 100; i = i + 1) {
+                var square = i * i;
+                if (square > limit) break square;
+            };
+            return result;
+        }
+        print first_square_above(50);
+        {
+            var none = for (i in 0..3) {
+                if (i > 5) break i;
+            };
+            var plain = while (true) break;;
+            print none;
+            print plain;
+        }
+        var count = 0;
+        while (count < 3) {
+            count = count + 1;
+            if (count == 2) break;
+        }
+        print count;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["42", "64", "nil", "nil", "2"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn loop_expression_outside_initializer_test() {
+    let src = r#"
+        var x;
+        x = while (true) { break 5; };
+        print x;
+    "#;
+    let probe = interpret_using_probe(src);
+    let probe = probe.borrow();
+    assert_eq!(
+        probe.top_error_message(),
+        Some("Loop expression is allowed as a variable initializer only")
+    );
+    assert_eq!(probe.top_compile_error_line(), Some(3));
+    assert!(probe.output_lines().is_empty());
+}
+
+#[test]
+fn break_value_in_loop_statement_test() {
+    let src = r#"
+        while (true) {
+            break 1;
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("'break' with a value is allowed in loop expressions only"),
+        probe.borrow().top_error_message()
+    );
+}
+
 #[test]
 fn continue_outside_loop_test() {
     let src = r#"
@@ -334,6 +584,66 @@ fn switch_with_default_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn switch_on_strings_test() {
+    let src = r#"
+        fun greet(name) {
+            var prefix = "> ";
+            switch (name) {
+                case "a": print prefix + "Alice";
+                case "b": print prefix + "Bob";
+                case "b" + "ob": print prefix + "Bob again";
+            }
+            var suffix = "!";
+            print prefix + name + suffix;
+        }
+        greet("a");
+        greet("b");
+        greet("bob");
+        greet("c");
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &[
+        "> Alice",
+        "> a!",
+        "> Bob",
+        "> b!",
+        "> Bob again",
+        "> bob!",
+        "> c!",
+    ];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn switch_mixed_values_default_test() {
+    let src = r#"
+        fun describe(value) {
+            var before = "[";
+            switch (value) {
+                case 1: print "number";
+                case "1": print "text";
+                case nil: print "nil";
+                default: print "unknown";
+            }
+            var after = "]";
+            print before + after;
+        }
+        describe(1);
+        describe("1");
+        describe(nil);
+        describe(true);
+        describe("2");
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &[
+        "number", "[]", "text", "[]", "nil", "[]", "unknown", "[]", "unknown", "[]",
+    ];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
 #[test]
 fn switch_with_random_default_place_test() {
     let src = r#"
@@ -368,6 +678,35 @@ fn switch_with_random_default_place_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn switch_with_default_first_test() {
+    let src = r#"
+        fun check(i) {
+            var before = "<";
+            switch (i) {
+                default: {
+                    var formatted = "Value " + i;
+                    print formatted;
+                }
+                case 0: print "Zero";
+                case 1: print "One";
+            }
+            var after = ">";
+            print before + after;
+        }
+        for (var i = 0; i < 3; i = i + 1) {
+            check(i);
+        }
+        switch ("x") {
+            default: print "Only default";
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["Zero", "<>", "One", "<>", "Value 2", "<>", "Only default"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
 #[test]
 fn switch_duplicate_default_test() {
     let src = r#"
@@ -394,3 +733,143 @@ fn switch_duplicate_default_test() {
         probe.borrow().top_error_message()
     );
 }
+
+#[test]
+fn strict_conditions_warning_test() {
+    use fox_bytecode::{CompileOptions, compile_with_warnings};
+
+    let warnings = |src: &str, strict_conditions: bool| {
+        let options = CompileOptions {
+            strict_conditions,
+            ..Default::default()
+        };
+        let (result, warnings) = compile_with_warnings(common::str_to_code_ref(src), options);
+        assert!(result.is_ok());
+        warnings
+            .iter()
+            .map(|x| x.message().to_string())
+            .collect::<Vec<_>>()
+    };
+    let message = "Condition is a literal of non-boolean type";
+    assert_eq!(warnings("if (1) {}", true), vec![message]);
+    assert_eq!(warnings("while (\"yes\") {}", true), vec![message]);
+    assert_eq!(warnings("for (;nil;) {}", true), vec![message]);
+    assert!(warnings("if (1) {}", false).is_empty());
+    assert!(warnings("if (true) {}", true).is_empty());
+    assert!(warnings("var x = 1; if (x) {}", true).is_empty());
+}
+
+#[test]
+fn switch_continue_enclosing_loop_test() {
+    let src = r#"
+        var total = 0;
+        for (var i = 0; i < 6; i = i + 1) {
+            var label = "i=" + i;
+            switch (i) {
+                case 1: {
+                    var skipped = "one";
+                    continue;
+                }
+                case 3: continue;
+                default: print label;
+            }
+            total = total + i;
+        }
+        print total;
+        var j = 0;
+        while (j < 3) {
+            j = j + 1;
+            switch (j) {
+                case 2: continue;
+            }
+            print j;
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["i=0", "i=2", "i=4", "i=5", "11", "1", "3"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn literal_loop_conditions_test() {
+    let src = r#"
+        var i = 0;
+        while (true) {
+            i = i + 1;
+            if (i > 2) break;
+            print i;
+        }
+        for (var j = 0; true; j = j + 1) {
+            if (j == 1) continue;
+            if (j > 2) break;
+            print "j=" + j;
+        }
+        while (false) {
+            print "never";
+        }
+        print "done";
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["1", "2", "j=0", "j=2", "done"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn switch_duplicate_case_warning_test() {
+    use fox_bytecode::{CompileOptions, compile_with_warnings};
+
+    let src = r#"
+        var x = 1;
+        var y = 2;
+        switch (x) {
+            case 1: print "one";
+            case "1": print "text";
+            case y: print "y";
+            case 1: print "again";
+            case y: print "y again";
+            case 300: print "big";
+            case 300: print "big again";
+        }
+    "#;
+    let (result, warnings) =
+        compile_with_warnings(common::str_to_code_ref(src), CompileOptions::default());
+    assert!(result.is_ok());
+    let warnings = warnings
+        .iter()
+        .map(|x| (x.message(), x.line()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        warnings,
+        vec![
+            ("Duplicate case value", Some(8)),
+            ("Duplicate case value", Some(11))
+        ]
+    );
+}
+
+#[test]
+fn switch_duplicate_shared_text_case_warning_test() {
+    use fox_bytecode::{CompileOptions, compile_with_warnings};
+
+    let src = r#"
+        var x = "a";
+        switch (x) {
+            case "a": print "first";
+            case "b": print "second";
+            case "a": print "again";
+        }
+    "#;
+    let options = CompileOptions {
+        shared_constants: true,
+        ..Default::default()
+    };
+    let (result, warnings) = compile_with_warnings(common::str_to_code_ref(src), options);
+    assert!(result.is_ok());
+    let warnings = warnings
+        .iter()
+        .map(|x| (x.message(), x.line()))
+        .collect::<Vec<_>>();
+    assert_eq!(warnings, vec![("Duplicate case value", Some(6))]);
+}
@@ -0,0 +1,93 @@
+use fox_bytecode::{
+    BYTECODE_VERSION, BytecodeError, EmptyNative, compile, deserialize_func, interpret_func,
+    serialize_func, shared,
+};
+
+use crate::common::{Probe, interpret_using_probe, str_to_code_ref};
+
+mod common;
+
+#[test]
+fn compiled_bytes_run_after_load_test() {
+    let src = r#"
+        class Counter {
+            init(start) {
+                this.value = start;
+            }
+            next() {
+                this.value = this.value + 1;
+                return this.value;
+            }
+        }
+        fun make(text) {
+            var counter = Counter(0);
+            fun describe() {
+                return text + counter.next();
+            }
+            return describe;
+        }
+        var describe = make("step ");
+        print describe();
+        print describe();
+        print 1.5 + 1;
+        print nil;
+    "#;
+    let func = compile(str_to_code_ref(src)).expect("Failed to compile");
+    let bytes = serialize_func(&func).expect("Failed to serialize");
+    let func = deserialize_func(&bytes).expect("Failed to deserialize");
+    let probe = shared(Probe::default());
+    interpret_func(func, probe.clone(), EmptyNative);
+    let output = &["step 1", "step 2", "2.5", "nil"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+    // same as running the source
+    interpret_using_probe(src)
+        .borrow()
+        .assert_output_match(output);
+}
+
+#[test]
+fn bytecode_version_mismatch_test() {
+    let func = compile(str_to_code_ref("print 1;")).expect("Failed to compile");
+    let mut bytes = serialize_func(&func).expect("Failed to serialize");
+    let version = (BYTECODE_VERSION + 1).to_le_bytes();
+    bytes[4..6].copy_from_slice(&version);
+    let Err(err) = deserialize_func(&bytes) else {
+        panic!("Bytecode of another version must be rejected");
+    };
+    assert!(matches!(err, BytecodeError::VersionMismatch { .. }));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Bytecode version {} isn't supported, expected version {BYTECODE_VERSION}. Recompile the script",
+            BYTECODE_VERSION + 1
+        )
+    );
+}
+
+#[test]
+fn shared_constants_lift_constants_limit_test() {
+    use fox_bytecode::{CompileOptions, compile_with};
+
+    let mut src = String::from("fun texts() {\n    var count = 0;\n");
+    for i in 0..300 {
+        src.push_str(&format!(
+            "    if (\"text {i}\" != nil) count = count + 1;\n"
+        ));
+    }
+    src.push_str("    return count;\n}\nprint texts();\nprint \"text 299\";\n");
+    assert!(compile(str_to_code_ref(&src)).is_err());
+
+    let options = CompileOptions {
+        shared_constants: true,
+        ..Default::default()
+    };
+    let func = compile_with(str_to_code_ref(&src), options).expect("Failed to compile");
+    let bytes = serialize_func(&func).expect("Failed to serialize");
+    let func = deserialize_func(&bytes).expect("Failed to deserialize");
+    assert_eq!(func.chunk().shared_constants().map(|x| x.len()), Some(300));
+    let probe = shared(Probe::default());
+    interpret_func(func, probe.clone(), EmptyNative);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["300", "text 299"]);
+}
@@ -1,4 +1,4 @@
-use crate::common::interpret_using_probe;
+use crate::common::{interpret_using_probe, interpret_with};
 mod common;
 
 #[test]
@@ -122,6 +122,39 @@ fn class_this_nested_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn class_this_deeply_nested_test() {
+    let src = r#"
+        class Outer {
+          init() {
+            this.name = "outer";
+          }
+
+          method() {
+            fun first() {
+              fun second() {
+                fun third() {
+                  print this;
+                  print this.name;
+                }
+                return third;
+              }
+              return second;
+            }
+            return first;
+          }
+        }
+
+        var f = Outer().method();
+        f()()();
+        print "OK";
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["<Outer instance>", "outer", "OK"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
 #[test]
 fn misuse_this_at_top_level_test() {
     let src = r#"
@@ -198,7 +231,7 @@ fn class_initializer_with_wrong_args_test() {
     "#;
     let probe = interpret_using_probe(src);
     assert_eq!(
-        Some("Expected 3 arguments but got 1"),
+        Some("Expected 3 arguments but got 1 calling 'init', missing 'b', 'c'"),
         probe.borrow().top_error_message()
     );
 }
@@ -235,7 +268,7 @@ fn class_initializer_invalid_implicit_test() {
     "#;
     let probe = interpret_using_probe(src);
     assert_eq!(
-        Some("Expected 0 arguments but got 3"),
+        Some("Expected 0 arguments but got 3 calling 'init'"),
         probe.borrow().top_error_message()
     );
 }
@@ -257,3 +290,432 @@ fn class_initializer_return_misuse_test() {
         probe.borrow().top_error_message()
     );
 }
+
+#[test]
+fn class_to_string_test() {
+    let src = r#"
+        class Brioche {
+          init(jam) {
+            this.jam = jam;
+          }
+
+          toString() {
+            return "Brioche with " + this.jam;
+          }
+        }
+
+        var brioche = Brioche("grape");
+        print brioche;
+        print "OK";
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["Brioche with grape", "OK"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn class_to_string_wrong_arity_test() {
+    let src = r#"
+        class Brioche {
+          toString(a) {
+            return a;
+          }
+        }
+
+        print Brioche();
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Expected 1 arguments but got 0 calling 'toString', missing 'a'"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn instance_fields_snapshot_test() {
+    use fox_bytecode::{
+        BackendService, ErrorInfo, InterpreterService, OutputEvent, Value, interpret, shared,
+    };
+
+    #[derive(Default)]
+    struct Capture {
+        values: Vec<Value>,
+    }
+
+    impl BackendService for Capture {
+        fn on_event(&mut self, event: OutputEvent) {
+            if let OutputEvent::Print(value) = event {
+                self.values.push(value);
+            }
+        }
+    }
+
+    impl InterpreterService for Capture {
+        fn set_compile_errors(&mut self, errors: &[ErrorInfo]) {
+            panic!("Unexpected compile errors: {errors:?}");
+        }
+    }
+
+    let src = r#"
+        class Point {}
+        var p = Point();
+        p.x = 1;
+        p.label = "origin";
+        print p;
+    "#;
+    let capture = shared(Capture::default());
+    interpret(
+        common::str_to_code_ref(src),
+        capture.clone(),
+        capture.clone(),
+        fox_bytecode::EmptyNative,
+    );
+    let capture = capture.borrow();
+    let instance = capture
+        .values
+        .first()
+        .and_then(|x| x.as_instance())
+        .expect("Instance wasn't captured");
+    let mut fields = instance
+        .fields()
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect::<Vec<_>>();
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        fields,
+        vec![
+            ("label".to_string(), Value::text_from_str("origin")),
+            ("x".to_string(), Value::Number(1.0)),
+        ]
+    );
+}
+
+#[test]
+fn text_methods_test() {
+    let src = r#"
+        var greeting = "  Hello, World  ";
+        print "hello".upper();
+        print "hello".length();
+        print greeting.trim().lower().length();
+        print greeting.contains("World");
+        print greeting.trim().upper().contains("world");
+        print "fox-bytecode".substring(4).upper();
+        print "fox-bytecode".substring(0, 3);
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["HELLO", "5", "12", "true", "false", "BYTECODE", "fox"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn text_unknown_method_test() {
+    let src = r#"
+        "hello".reverse();
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Undefined property 'reverse'"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn text_method_wrong_arguments_test() {
+    let src = r#"
+        "hello".contains(1);
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Argument must be a string"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn host_class_methods_test() {
+    let src = r#"
+        var counter = Counter(10);
+        counter.inc();
+        counter.inc();
+        print counter.get();
+        print counter;
+        print Counter;
+    "#;
+    let probe = interpret_with(src, host_classes::Provider);
+    let output = &["12", "<Counter host instance>", "<host class Counter>"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn host_class_undefined_method_test() {
+    let src = r#"
+        var counter = Counter();
+        counter.reset();
+    "#;
+    let probe = interpret_with(src, host_classes::Provider);
+    assert_eq!(
+        Some("Undefined property 'reset'"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn host_class_method_captures_host_state_test() {
+    let src = r#"
+        var log = Log();
+        log.write("start");
+        log.write(1 + 2);
+    "#;
+    let provider = host_classes::LogProvider::default();
+    let probe = interpret_with(src, provider.clone());
+    assert_eq!(None, probe.borrow().top_error_message());
+    assert_eq!(*provider.lines.borrow(), vec!["start", "3"]);
+}
+
+mod host_classes {
+    use std::{any::Any, cell::RefCell, rc::Rc};
+
+    use fox_bytecode::{ForeignClass, NativeFn, Value};
+
+    pub struct Provider;
+
+    impl fox_bytecode::NativeFunctionsProvider for Provider {
+        fn get_functions(&self) -> Vec<(String, NativeFn)> {
+            Vec::new()
+        }
+
+        fn get_classes(&self) -> Vec<ForeignClass> {
+            let mut class = ForeignClass::with("Counter", counter_new);
+            class.add_method("inc", counter_inc);
+            class.add_method("get", counter_get);
+            vec![class]
+        }
+    }
+
+    /// Collects values written by script into a list owned by the host
+    #[derive(Clone, Default)]
+    pub struct LogProvider {
+        pub lines: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl fox_bytecode::NativeFunctionsProvider for LogProvider {
+        fn get_functions(&self) -> Vec<(String, NativeFn)> {
+            Vec::new()
+        }
+
+        fn get_classes(&self) -> Vec<ForeignClass> {
+            let mut class = ForeignClass::with("Log", |_| Box::new(()));
+            let lines = self.lines.clone();
+            class.add_method("write", move |_, args| {
+                if let Some(value) = args.first() {
+                    lines.borrow_mut().push(value.to_string());
+                }
+                Value::Nil
+            });
+            vec![class]
+        }
+    }
+
+    fn counter_new(args: &[Value]) -> Box<dyn Any> {
+        let start = args.first().and_then(|x| x.as_number()).unwrap_or(0.0);
+        Box::new(start)
+    }
+
+    fn counter_inc(state: &mut dyn Any, _args: &[Value]) -> Value {
+        let Some(counter) = state.downcast_mut::<f32>() else {
+            return Value::Nil;
+        };
+        *counter += 1.0;
+        Value::Nil
+    }
+
+    fn counter_get(state: &mut dyn Any, _args: &[Value]) -> Value {
+        let Some(counter) = state.downcast_ref::<f32>() else {
+            return Value::Nil;
+        };
+        Value::Number(*counter)
+    }
+}
+
+#[test]
+fn class_safe_property_access_test() {
+    let src = r#"
+        class Node {
+            init(name, next) {
+                this.name = name;
+                this.next = next;
+            }
+            describe() {
+                return "node " + this.name;
+            }
+        }
+        var empty = nil;
+        var tail = Node("tail", nil);
+        var head = Node("head", tail);
+        print empty?.name;
+        print empty?.name.length;
+        print empty?.describe();
+        print tail?.name;
+        print head?.next?.name;
+        print head?.next?.next?.name;
+        print head?.next.describe();
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["nil", "nil", "nil", "tail", "tail", "nil", "node tail"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn class_safe_property_assignment_test() {
+    let src = r#"
+        class Box {}
+        var box = Box();
+        box?.value = 1;
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Invalid assignment target"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn class_method_chaining_test() {
+    let src = r#"
+        class Builder {
+            init() {
+                this.a = 0;
+                this.b = 0;
+            }
+            setA(value) {
+                this.a = value;
+                return this;
+            }
+            setB(value) {
+                this.b = value;
+                return this;
+            }
+            describe() {
+                return "a=" + this.a + " b=" + this.b;
+            }
+        }
+        var builder = Builder().setA(1).setB(2);
+        print builder.describe();
+        var setter = builder.setA;
+        print setter(3).setB(4) == builder;
+        print builder.describe();
+        print Builder().setB(5).setA(6).describe();
+        print builder.describe();
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["a=1 b=2", "true", "a=3 b=4", "a=6 b=5", "a=3 b=4"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn class_bool_method_truthiness_test() {
+    let src = r#"
+        class Bag {
+            init(size) {
+                this.size = size;
+            }
+            __bool() {
+                return this.size > 0;
+            }
+        }
+        var empty = Bag(0);
+        var full = Bag(3);
+        if (empty) {
+            print "empty is truthy";
+        } else {
+            print "empty is falsey";
+        }
+        if (full) print "full is truthy";
+        print !empty;
+        print empty or "fallback";
+        print full and "both";
+        var count = 0;
+        while (Bag(3 - count)) {
+            count = count + 1;
+        }
+        print count;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &[
+        "empty is falsey",
+        "full is truthy",
+        "true",
+        "fallback",
+        "both",
+        "3",
+    ];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn class_bool_method_wrong_result_test() {
+    let src = r#"
+        class Odd {
+            __bool() {
+                return 1;
+            }
+        }
+        if (Odd()) print "unreachable";
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("'__bool' must return a boolean"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn method_without_receiver_test() {
+    let src = r#"
+        class Point {
+            init(x) {
+                this.x = x;
+            }
+            show() {
+                print this.x;
+            }
+        }
+        var show = unbound(Point(1), "show");
+        show();
+    "#;
+    let probe = interpret_with(src, unbound_methods::Provider);
+    assert_eq!(
+        Some("Cannot access 'this' without a receiver"),
+        probe.borrow().top_error_message()
+    );
+}
+
+/// Hands out raw method closures, the way a careless host could
+mod unbound_methods {
+    use fox_bytecode::{NativeFn, Value};
+
+    pub struct Provider;
+
+    impl fox_bytecode::NativeFunctionsProvider for Provider {
+        fn get_functions(&self) -> Vec<(String, NativeFn)> {
+            vec![("unbound".to_string(), unbound)]
+        }
+    }
+
+    fn unbound(args: &[Value]) -> Value {
+        let [instance, name] = args else {
+            return Value::Nil;
+        };
+        let (Some(instance), Some(name)) = (instance.as_instance(), name.as_text()) else {
+            return Value::Nil;
+        };
+        instance.class().get_method(&name).unwrap_or_default()
+    }
+}
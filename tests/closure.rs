@@ -1,3 +1,5 @@
+use fox_bytecode::{HeapStats, heap_stats, set_heap_stats_enabled};
+
 use crate::common::interpret_using_probe;
 mod common;
 
@@ -196,3 +198,71 @@ fn closure_capture_loop_var_test() {
     assert_eq!(None, probe.borrow().top_error_message());
     probe.borrow().assert_output_match(output);
 }
+
+#[test]
+fn closure_display_test() {
+    let src = r#"
+        fun outer() {
+            var x = "captured";
+            fun inner() {
+                return x;
+            }
+            return inner;
+        }
+        print outer;
+        print outer();
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["<fn outer>", "<fn inner>"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn closures_released_after_run_test() {
+    let src = r#"
+        fun make(i) {
+            fun get() {
+                return i;
+            }
+            return get;
+        }
+        var sum = 0;
+        for (var i = 0; i < 100; i = i + 1) {
+            var f = make(i);
+            sum = sum + f();
+        }
+        print sum;
+    "#;
+    set_heap_stats_enabled(true);
+    let baseline = heap_stats();
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["4950"]);
+    assert_eq!(baseline, heap_stats());
+}
+
+#[test]
+fn instance_closure_cycle_leaks_test() {
+    let src = r#"
+        class Node {}
+        {
+            var node = Node();
+            fun get() {
+                return node;
+            }
+            node.get = get;
+        }
+    "#;
+    set_heap_stats_enabled(true);
+    let baseline = heap_stats();
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    // node -> get -> upvalue -> node isn't collected by reference counting
+    let leaked = HeapStats {
+        closures: baseline.closures + 1,
+        instances: baseline.instances + 1,
+        upvalues: baseline.upvalues + 1,
+    };
+    assert_eq!(leaked, heap_stats());
+}
@@ -5,7 +5,9 @@ pub use probe::*;
 
 use std::rc::Rc;
 
-use fox_bytecode::{EmptyNative, NativeFunctionsProvider, Shared, interpret, shared};
+use fox_bytecode::{
+    EmptyNative, Machine, NativeFunctionsProvider, Shared, Value, compile, interpret, shared,
+};
 
 pub fn str_to_code_ref(input: &str) -> Rc<Vec<char>> {
     Rc::new(input.chars().collect())
@@ -33,3 +35,13 @@ pub fn interpret_with(
     );
     probe_shared
 }
+
+/// Evaluates a single expression and returns its value, panics if it fails
+#[allow(dead_code)] // not every test binary uses it
+pub fn eval_expr(expr: &str) -> Value {
+    let code_ref = str_to_code_ref(&format!("var __r = ({expr});"));
+    let func = compile(code_ref).expect("Failed to compile");
+    let mut machine = Machine::with(func, shared(Probe::default()), EmptyNative);
+    machine.run().expect("Failed to run");
+    machine.global("__r").expect("Result is missing")
+}
@@ -1,8 +1,11 @@
-use fox_bytecode::{BackendService, ErrorInfo, InterpreterService, probe::ProbeBackendService};
+use fox_bytecode::{
+    BackendService, ErrorInfo, InterpreterService, OutputEvent, probe::ProbeBackendService,
+};
 
 #[derive(Default)]
 pub struct Probe {
     compiler_errors: Vec<ErrorInfo>,
+    compiler_warnings: Vec<ErrorInfo>,
     backend: ProbeBackendService,
 }
 
@@ -29,23 +32,45 @@ impl Probe {
         None
     }
 
+    #[allow(dead_code)]
+    pub fn top_compile_error_line(&self) -> Option<usize> {
+        self.compiler_errors.first().and_then(|err| err.line())
+    }
+
+    #[allow(dead_code)]
+    pub fn compile_warnings(&self) -> Vec<&str> {
+        self.compiler_warnings.iter().map(|x| x.message()).collect()
+    }
+
     #[allow(dead_code)]
     pub fn assert_output_match<T: AsRef<str>>(&self, output: &[T]) {
         self.backend.assert_output_match(output)
     }
-}
 
-impl BackendService for Probe {
-    fn print_value(&mut self, value: fox_bytecode::Value) {
-        self.backend.print_value(value);
+    #[allow(dead_code)]
+    pub fn output_lines(&self) -> &[String] {
+        &self.backend.print_buffer
     }
 
-    fn set_error(&mut self, error: fox_bytecode::MachineError) {
-        self.backend.set_error(error);
+    #[allow(dead_code)]
+    pub fn diagnostics(&self) -> &[String] {
+        &self.backend.diagnostics
+    }
+
+    /// Checks that some printed line contains `substr`
+    #[allow(dead_code)]
+    pub fn assert_output_contains(&self, substr: &str) {
+        assert!(
+            self.output_lines().iter().any(|line| line.contains(substr)),
+            "No output line contains '{substr}': {:?}",
+            self.output_lines()
+        )
     }
+}
 
-    fn set_stack_trace(&mut self, stack_trace: Vec<fox_bytecode::StackTraceElement>) {
-        self.backend.set_stack_trace(stack_trace);
+impl BackendService for Probe {
+    fn on_event(&mut self, event: OutputEvent) {
+        self.backend.on_event(event);
     }
 }
 
@@ -53,4 +78,8 @@ impl InterpreterService for Probe {
     fn set_compile_errors(&mut self, errors: &[ErrorInfo]) {
         self.compiler_errors = errors.to_vec();
     }
+
+    fn set_compile_warnings(&mut self, warnings: &[ErrorInfo]) {
+        self.compiler_warnings = warnings.to_vec();
+    }
 }
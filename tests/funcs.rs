@@ -11,7 +11,7 @@ fn func_declaration_test() {
         print areWeHavingItYet;
     "#;
     let probe = interpret_using_probe(src);
-    let output = &["<closure <fn areWeHavingItYet>>"];
+    let output = &["<fn areWeHavingItYet>"];
     assert_eq!(None, probe.borrow().top_error_message());
     probe.borrow().assert_output_match(output);
 }
@@ -41,6 +41,17 @@ fn script_return_test() {
     );
 }
 
+#[test]
+fn func_unterminated_body_test() {
+    let src = "fun f() {\n    print 1;\n\n";
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Expect '}' after block"),
+        probe.borrow().top_error_message()
+    );
+    assert_eq!(Some(2), probe.borrow().top_compile_error_line());
+}
+
 #[test]
 fn func_return_test() {
     let src = r#"
@@ -121,7 +132,7 @@ fn stacktrace_test() {
     "#;
     let probe = interpret_using_probe(src);
     assert_eq!(
-        Some("Expected 0 arguments but got 2"),
+        Some("Expected 0 arguments but got 2 calling 'c'"),
         probe.borrow().top_error_message()
     );
 
@@ -145,6 +156,23 @@ fn native_function_call_test() {
     probe.borrow().assert_output_match(output);
 }
 
+#[test]
+fn native_function_call_local_scope_test() {
+    let src = r#"
+        fun calc() {
+            var a = sum(1, 2);
+            var b = sum(a, 3);
+            print a;
+            print b;
+        }
+        calc();
+    "#;
+    let probe = interpret_with(src, native_funcs::Provider);
+    let output = &["3", "6"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
 mod native_funcs {
     use fox_bytecode::Value;
 
@@ -152,10 +180,17 @@ mod native_funcs {
 
     impl fox_bytecode::NativeFunctionsProvider for Provider {
         fn get_functions(&self) -> Vec<(String, fox_bytecode::NativeFn)> {
-            vec![("sum".to_string(), sum)]
+            vec![("sum".to_string(), sum), ("clock".to_string(), clock)]
         }
     }
 
+    fn clock(_args: &[Value]) -> Value {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Value::Number(now.as_secs_f32())
+    }
+
     fn sum(args: &[Value]) -> Value {
         let mut acc = 0.0;
         for x in args {
@@ -167,3 +202,245 @@ mod native_funcs {
         Value::Number(acc)
     }
 }
+
+#[test]
+fn native_clock_output_test() {
+    let src = r#"
+        var start = clock();
+        print "Started at " + start;
+        print clock() >= start;
+    "#;
+    let probe = interpret_with(src, native_funcs::Provider);
+    assert_eq!(None, probe.borrow().top_error_message());
+    let probe = probe.borrow();
+    assert_eq!(probe.output_lines().len(), 2);
+    probe.assert_output_contains("Started at ");
+    probe.assert_output_contains("true");
+}
+
+#[test]
+fn unreachable_code_warning_test() {
+    let src = r#"
+        fun early() {
+            return "done";
+            print "unreachable";
+            print "also unreachable";
+        }
+        print early();
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    assert_eq!(
+        probe.borrow().compile_warnings(),
+        vec!["Unreachable code after return"]
+    );
+    probe.borrow().assert_output_match(&["done"]);
+}
+
+#[test]
+fn no_unreachable_code_warning_test() {
+    let src = r#"
+        fun check(x) {
+            if (x) return "yes";
+            return "no";
+        }
+        print check(true);
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    assert!(probe.borrow().compile_warnings().is_empty());
+}
+
+#[test]
+fn print_location_test() {
+    use fox_bytecode::{
+        BackendService, ErrorInfo, InterpreterService, OutputEvent, StackTraceElement, Value,
+        interpret, shared,
+    };
+
+    #[derive(Default)]
+    struct Capture {
+        prints: Vec<(String, Option<usize>, Option<String>)>,
+    }
+
+    impl BackendService for Capture {
+        fn on_event(&mut self, _event: OutputEvent) {}
+
+        fn print_value_at(&mut self, value: Value, location: StackTraceElement) {
+            self.prints
+                .push((value.to_string(), location.line, location.func_name));
+        }
+    }
+
+    impl InterpreterService for Capture {
+        fn set_compile_errors(&mut self, errors: &[ErrorInfo]) {
+            panic!("Unexpected compile errors: {errors:?}");
+        }
+    }
+
+    let src = r#"print "top";
+        fun greet() {
+            print "inner";
+        }
+        greet();
+    "#;
+    let capture = shared(Capture::default());
+    interpret(
+        common::str_to_code_ref(src),
+        capture.clone(),
+        capture.clone(),
+        fox_bytecode::EmptyNative,
+    );
+    assert_eq!(
+        capture.borrow().prints,
+        vec![
+            ("top".to_string(), Some(1), None),
+            ("inner".to_string(), Some(3), Some("greet".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn assert_eq_passing_test() {
+    let src = r#"
+        fun square(x) {
+            return x * x;
+        }
+        assert_eq(square(3), 9);
+        assert_eq("a" + "b", "ab");
+        assert_eq(nil, nil);
+        print "passed";
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["passed"]);
+}
+
+#[test]
+fn assert_eq_failing_test() {
+    let src = r#"
+        assert_eq(1 + 1, 2);
+        assert_eq(2, 1 + 2);
+        print "unreachable";
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("assertion failed: 2 != 3"),
+        probe.borrow().top_error_message()
+    );
+    assert!(
+        !probe
+            .borrow()
+            .output_lines()
+            .iter()
+            .any(|x| x == "unreachable")
+    );
+}
+
+#[test]
+fn too_many_arguments_and_parameters_position_test() {
+    use fox_bytecode::{CompileErrorKind, CompileOptions, compile_detailed};
+
+    let first_error = |src: &str| {
+        compile_detailed(common::str_to_code_ref(src), CompileOptions::default())
+            .expect_err("Compilation must fail")
+            .remove(0)
+    };
+    // one item per line, the extra one is at line 257 of the list
+    let arguments = (0..300)
+        .map(|i| format!("  -{i}"))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    let error = first_error(&format!("fun f() {{}}\nf(\n{arguments});"));
+    assert_eq!(error.kind, CompileErrorKind::TooManyArguments);
+    assert_eq!(error.message, "Can't have more than 255 arguments");
+    assert_eq!(error.line, Some(258));
+    assert_eq!(error.column, Some(3));
+
+    let parameters = (0..300)
+        .map(|i| format!("  p{i}"))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    let error = first_error(&format!("fun f(\n{parameters}) {{}}"));
+    assert_eq!(error.kind, CompileErrorKind::TooManyParameters);
+    assert_eq!(error.message, "Can't have more than 255 parameters");
+    assert_eq!(error.line, Some(257));
+    assert_eq!(error.column, Some(3));
+}
+
+#[test]
+fn return_from_nested_scopes_unwinds_test() {
+    let src = r#"
+        fun find(limit) {
+            var outer = "outer";
+            for (var i = 0; i < limit; i = i + 1) {
+                var label = "i=" + i;
+                while (true) {
+                    var inner = label + "!";
+                    switch (i) {
+                        case 2: {
+                            var captured = inner;
+                            fun get() { return captured; }
+                            return get;
+                        }
+                    }
+                    break;
+                }
+            }
+            return outer;
+        }
+        var before = "before";
+        print find(5)();
+        print find(1);
+        print before;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["i=2!", "outer", "before"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn multiple_return_values_test() {
+    let src = r#"
+        fun min_max(a, b) {
+            if (a < b) return a, b;
+            return b, a;
+        }
+        var (low, high) = min_max(7, 3);
+        print low;
+        print high;
+        print min_max(1, 2);
+        fun sum_of_pair() {
+            var (first, second) = min_max(5, 4);
+            return first + second;
+        }
+        print sum_of_pair();
+        {
+            var (x, y, z) = [1, "two", nil];
+            print y;
+            print z;
+        }
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["3", "7", "[1, 2]", "9", "two", "nil"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn destructuring_errors_test() {
+    let cases = [
+        ("var (a, b) = [1];", "Expected 2 values to unpack but got 1"),
+        ("var (a, b) = 5;", "Only lists can be unpacked, got number"),
+        (
+            "{ var (a, a) = [1, 2]; }",
+            "Already a variable with this name in this scope",
+        ),
+        ("var (a, b);", "Expect '=' after variable names"),
+    ];
+    for (src, message) in cases {
+        let probe = interpret_using_probe(src);
+        assert_eq!(Some(message), probe.borrow().top_error_message(), "{src}");
+    }
+}
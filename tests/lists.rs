@@ -0,0 +1,75 @@
+use crate::common::interpret_using_probe;
+mod common;
+
+#[test]
+fn list_literal_and_index_test() {
+    let src = r#"
+        var items = [1, "two", nil, [3, 4]];
+        print items;
+        print items[1];
+        print items[3][0];
+        items[0] = items[0] + 10;
+        print items[0];
+        print [];
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["[1, \"two\", nil, [3, 4]]", "two", "3", "11", "[]"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn list_methods_test() {
+    let src = r#"
+        var items = [];
+        items.push("a");
+        items.push("b");
+        print items.length();
+        print items;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["2", "[\"a\", \"b\"]"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn list_is_shared_by_reference_test() {
+    let src = r#"
+        fun fill(target) {
+            target.push(1);
+        }
+        var a = [];
+        var b = a;
+        fill(b);
+        print a;
+        print a == b;
+        print [1] == [1];
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["[1]", "true", "false"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn list_index_errors_test() {
+    let cases = [
+        ("print [1, 2][2];", "List index out of range"),
+        (
+            "print [1, 2][-1];",
+            "List index must be a non-negative integer",
+        ),
+        (
+            "print [1, 2][0.5];",
+            "List index must be a non-negative integer",
+        ),
+        ("var x = [1]; x[1] = 2;", "List index out of range"),
+        ("print \"abc\"[0];", "Only lists can be indexed, got string"),
+        ("print [].length;", "List method 'length' must be called"),
+    ];
+    for (src, message) in cases {
+        let probe = interpret_using_probe(src);
+        assert_eq!(Some(message), probe.borrow().top_error_message(), "{src}");
+    }
+}
@@ -1,4 +1,5 @@
-use crate::common::interpret_using_probe;
+use crate::common::{eval_expr, interpret_using_probe};
+use fox_bytecode::Value;
 mod common;
 
 #[test]
@@ -27,3 +28,161 @@ fn unary_precedence() {
     assert_eq!(None, probe.borrow().top_error_message());
     probe.borrow().assert_output_match(output);
 }
+
+#[test]
+fn floor_division_test() {
+    let src = r"
+        print 7 ~/ 2; // integer part
+        print -7 ~/ 2;
+        var a = 9;
+        print a ~/ 3 * 2;
+        print 1 + 8 ~/ 3;
+    ";
+    let probe = interpret_using_probe(src);
+    let output = &["3", "-4", "6", "3"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn floor_division_by_zero_test() {
+    let src = r"
+        print 7 ~/ 0;
+    ";
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Division by zeros"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn eval_expression_test() {
+    assert_eq!(eval_expr("2 + 3 * 4"), Value::number(14.0));
+    assert_eq!(eval_expr("(2 + 3) * 4"), Value::number(20.0));
+    assert_eq!(eval_expr("-2 * -3 - 1"), Value::number(5.0));
+    assert_eq!(eval_expr("1 < 2 == !false"), Value::Bool(true));
+}
+
+#[test]
+fn combined_comparison_test() {
+    let cases = [
+        ("1 != 2", true),
+        ("2 != 2", false),
+        ("\"a\" != \"a\"", false),
+        ("nil != false", true),
+        ("3 >= 2", true),
+        ("2 >= 2", true),
+        ("1 >= 2", false),
+        ("1 <= 2", true),
+        ("2 <= 2", true),
+        ("3 <= 2", false),
+    ];
+    for (expr, expected) in cases {
+        assert_eq!(eval_expr(expr), Value::Bool(expected), "{expr}");
+    }
+}
+
+#[test]
+fn small_integer_constants_test() {
+    let src = r"
+        print 5;
+        print 127 + 1;
+        print 0 - 128;
+        print 2.5 * 2;
+    ";
+    let probe = interpret_using_probe(src);
+    let output = &["5", "128", "-128", "5"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn text_repeat_test() {
+    assert_eq!(eval_expr("\"ab\" * 3"), Value::text_from_str("ababab"));
+    assert_eq!(eval_expr("2 * \"-\""), Value::text_from_str("--"));
+    assert_eq!(eval_expr("\"ab\" * 0"), Value::text_from_str(""));
+
+    let probe = interpret_using_probe("print \"ab\" * 1.5;");
+    assert_eq!(
+        Some("Operator '*' cannot be applied to string and number"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn digit_separators_test() {
+    assert_eq!(eval_expr("1_000"), Value::number(1000.0));
+    assert_eq!(
+        eval_expr("1_000_000 + 0.000_5"),
+        Value::number(1000000.0005)
+    );
+
+    let probe = interpret_using_probe("print 1__0;");
+    assert_eq!(
+        Some("Digit separator '_' must be between digits"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn binary_operator_type_error_test() {
+    let cases = [
+        (
+            "nil + 1",
+            "Operator '+' cannot be applied to nil and number",
+        ),
+        (
+            "1 - \"a\"",
+            "Operator '-' cannot be applied to number and string",
+        ),
+        (
+            "true < 2",
+            "Operator '<' cannot be applied to bool and number",
+        ),
+        (
+            "clock >= nil",
+            "Operator '>=' cannot be applied to function and nil",
+        ),
+        (
+            "7 ~/ false",
+            "Operator '~/' cannot be applied to number and bool",
+        ),
+    ];
+    for (expr, message) in cases {
+        let src = format!("fun clock() {{}}\nprint {expr};");
+        let probe = interpret_using_probe(&src);
+        assert_eq!(Some(message), probe.borrow().top_error_message(), "{expr}");
+    }
+}
+
+#[test]
+fn strict_arithmetic_test() {
+    use fox_bytecode::{EmptyNative, Machine, compile, shared};
+
+    let run = |src: &str, strict: bool| {
+        let func = compile(common::str_to_code_ref(src)).expect("Failed to compile");
+        let probe = shared(common::Probe::default());
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        machine.set_strict_arithmetic(strict);
+        _ = machine.run();
+        probe
+    };
+    let src = "print 1 + 2; print \"a\" + \"b\"; print 1 + \"x\";";
+    let probe = run(src, false);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["3", "ab", "1x"]);
+
+    let probe = run(src, true);
+    assert_eq!(
+        Some("Operator '+' cannot be applied to number and string"),
+        probe.borrow().top_error_message()
+    );
+    probe.borrow().assert_output_match(&["3", "ab"]);
+
+    let probe = run("print nil + \"x\";", true);
+    assert_eq!(
+        Some("Operator '+' cannot be applied to nil and string"),
+        probe.borrow().top_error_message()
+    );
+}
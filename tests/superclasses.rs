@@ -114,3 +114,59 @@ fn get_super_opcode_test() {
     assert_eq!(None, probe.borrow().top_error_message());
     probe.borrow().assert_output_match(output);
 }
+
+#[test]
+fn redefined_class_inherits_subclass_test() {
+    let src = r#"
+        class A {
+          name() {
+            return "A";
+          }
+        }
+
+        class B : A {}
+
+        // the new A inherits B, which still refers to the previous A
+        class A : B {}
+
+        print A().name();
+        print B().name();
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["A", "A"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn method_override_notice_test() {
+    use fox_bytecode::{EmptyNative, Machine, compile, shared};
+
+    let src = r#"
+        class Animal {
+            init(name) { this.name = name; }
+            speak() { return "..."; }
+            eat() { return "food"; }
+        }
+        class Dog : Animal {
+            init(name) { super.init(name); }
+            speak() { return "woof"; }
+            fetch() { return "ball"; }
+        }
+        print Dog("rex").speak();
+    "#;
+    let run = |warn_overrides: bool| {
+        let func = compile(common::str_to_code_ref(src)).expect("Failed to compile");
+        let probe = shared(common::Probe::default());
+        let mut machine = Machine::with(func, probe.clone(), EmptyNative);
+        machine.set_override_warnings(warn_overrides);
+        machine.run().expect("Failed to run");
+        probe.borrow().assert_output_match(&["woof"]);
+        probe.borrow().diagnostics().to_vec()
+    };
+    assert!(run(false).is_empty());
+    assert_eq!(
+        run(true),
+        vec!["Method 'speak' of class 'Dog' overrides method of 'Animal'".to_string()]
+    );
+}
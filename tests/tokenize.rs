@@ -0,0 +1,31 @@
+use std::rc::Rc;
+
+use fox_bytecode::{TokenType, tokenize};
+
+fn token_types(input: &str) -> Vec<TokenType> {
+    let code = Rc::new(input.chars().collect());
+    tokenize(code).map(|token| token.t_type).collect()
+}
+
+#[test]
+fn tokenize_expression_test() {
+    use TokenType::*;
+    assert_eq!(
+        token_types("1 + 2;"),
+        vec![Number, Plus, Number, Semicolon, Eof]
+    );
+}
+
+#[test]
+fn tokenize_yields_errors_test() {
+    use TokenType::*;
+    assert_eq!(
+        token_types("var x = @ 1;"),
+        vec![Var, Identifier, Equal, Error, Number, Semicolon, Eof]
+    );
+}
+
+#[test]
+fn tokenize_empty_test() {
+    assert_eq!(token_types(""), vec![TokenType::Eof]);
+}
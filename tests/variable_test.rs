@@ -135,3 +135,242 @@ fn update_local_variables() {
     assert_eq!(None, probe.borrow().top_error_message());
     probe.borrow().assert_output_match(output);
 }
+
+#[test]
+fn global_read_in_hot_loop() {
+    let src = r#"
+        var step = 2;
+        var total = 0;
+        for (var i = 0; i < 10000; i = i + 1) {
+            total = total + step;
+        }
+        print total;
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["20000"]);
+}
+
+#[test]
+fn global_redefinition_seen_by_functions() {
+    let src = r#"
+        var value = 1;
+        fun read() {
+            return value;
+        }
+        print read();
+        var value = 2;
+        print read();
+        value = 3;
+        print read();
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["1", "2", "3"]);
+}
+
+#[test]
+fn global_undefined_until_defined() {
+    let src = r#"
+        fun read() {
+            return later;
+        }
+        print read();
+        var later = 1;
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Undefined variable later"),
+        probe.borrow().top_error_message()
+    );
+}
+
+#[test]
+fn unterminated_string_error_line() {
+    let src = "var a = 1;\nvar b = 2;\nvar c = \"unterminated\n\nprint a;\n";
+    let probe = interpret_using_probe(src);
+    assert_eq!(
+        Some("Unterminated string"),
+        probe.borrow().top_error_message()
+    );
+    assert_eq!(Some(3), probe.borrow().top_compile_error_line());
+}
+
+#[test]
+fn raw_string_keeps_backslashes() {
+    let src = r#"
+        var path = r"C:\path\n";
+        print path;
+        print r"" + path;
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe
+        .borrow()
+        .assert_output_match(&[r"C:\path\n", r"C:\path\n"]);
+}
+
+#[test]
+fn print_primitives_test() {
+    let src = r#"
+        print true;
+        print false;
+        print nil;
+        print !nil;
+        print 1 == true;
+        print 0 == false;
+        print nil == false;
+        print 2.5;
+        print "text";
+        print "" + true + false + nil;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &[
+        "true",
+        "false",
+        "nil",
+        "true",
+        "false",
+        "false",
+        "false",
+        "2.5",
+        "text",
+        "truefalsenil",
+    ];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn multibyte_text_round_trip() {
+    let src = r#"
+        var greeting = "こんにちは 🦊";
+        print greeting + ", 世界 👋🏽";
+        print greeting.length();
+        print greeting.substring(6).upper();
+        print greeting == "こんにちは " + "🦊";
+    "#;
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe
+        .borrow()
+        .assert_output_match(&["こんにちは 🦊, 世界 👋🏽", "7", "🦊", "true"]);
+}
+
+#[test]
+fn shebang_first_line() {
+    let src = "#!/usr/bin/env fox-bytecode\nvar a = 1;\nprint a;\n";
+    let probe = interpret_using_probe(src);
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(&["1"]);
+}
+
+#[test]
+fn scope_cleanup_with_captured_local_test() {
+    let src = r#"
+        var get;
+        {
+            var a = "a";
+            var b = "b";
+            var c = "c";
+            fun f() { return b; }
+            var d = "d";
+            var e = "e";
+            get = f;
+            print a + c + d + e;
+        }
+        var after = "after";
+        print get();
+        print after;
+    "#;
+    let probe = interpret_using_probe(src);
+    let output = &["acde", "b", "after"];
+    assert_eq!(None, probe.borrow().top_error_message());
+    probe.borrow().assert_output_match(output);
+}
+
+#[test]
+fn shadowing_warning_test() {
+    use fox_bytecode::{CompileOptions, compile_with_warnings};
+
+    let src = r#"
+        var x = 1;
+        {
+            var y = 2;
+            {
+                var y = 3;
+                var z = 4;
+            }
+            var x = 5;
+        }
+        fun f(z) {
+            var z2 = z;
+        }
+        for (x in 0..2) {
+            print x;
+        }
+    "#;
+    let warnings = |warn_shadowing: bool| {
+        let options = CompileOptions {
+            warn_shadowing,
+            ..Default::default()
+        };
+        let (result, warnings) = compile_with_warnings(common::str_to_code_ref(src), options);
+        assert!(result.is_ok());
+        warnings
+            .iter()
+            .map(|x| (x.message().to_string(), x.line()))
+            .collect::<Vec<_>>()
+    };
+    assert!(warnings(false).is_empty());
+    assert_eq!(
+        warnings(true),
+        vec![
+            ("'y' shadows an outer binding".to_string(), Some(6)),
+            ("'x' shadows an outer binding".to_string(), Some(9)),
+            ("'x' shadows an outer binding".to_string(), Some(14)),
+        ]
+    );
+}
+
+#[test]
+fn too_many_constants_error_kind() {
+    use fox_bytecode::{CompileErrorKind, CompileOptions, compile_detailed};
+
+    let src = (0..300)
+        .map(|i| format!("print \"text {i}\";"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let errors = compile_detailed(common::str_to_code_ref(&src), CompileOptions::default())
+        .expect_err("Compilation must fail");
+    let error = &errors[0];
+    assert_eq!(error.kind, CompileErrorKind::TooManyConstants);
+    assert_eq!(error.message, "Too many constants in one chunk");
+    assert_eq!(error.line, Some(257));
+    assert_eq!(error.column, Some(7));
+}
+
+#[test]
+fn jump_too_large_error_points_to_statement() {
+    use fox_bytecode::{CompileErrorKind, CompileOptions, compile_detailed};
+
+    let body = "a = a + 1;\n".repeat(9000);
+    let check = |src: String, message: &str| {
+        let errors = compile_detailed(common::str_to_code_ref(&src), CompileOptions::default())
+            .expect_err("Compilation must fail");
+        let error = &errors[0];
+        assert_eq!(error.kind, CompileErrorKind::JumpTooLarge);
+        assert_eq!(error.message, message);
+        assert_eq!(error.line, Some(3));
+        assert_eq!(error.column, Some(1));
+    };
+    // locals and small numbers don't need constants
+    check(
+        format!("{{\nvar a = 0;\nwhile (a < 1) {{\n{body}}}\n}}"),
+        "Jump size is too large",
+    );
+    check(
+        format!("{{\nvar a = 0;\nif (a < 1) {{\n{body}}}\n}}"),
+        "Too much code to jump over",
+    );
+}